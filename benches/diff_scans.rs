@@ -26,14 +26,17 @@ fn create_scan_result(file_count: usize, base_path: &str) -> ScanResult {
             path: PathBuf::from(format!("{}/file_{:05}.txt", base_path, i)),
             size: 1024 * (i as u64 + 1),
             mtime: SystemTime::now(),
-            hash: mock_hash(i as u64),
+            hash: Some(mock_hash(i as u64)),
             permissions: Some(0o644),
+            symlink_target: None,
         })
         .collect();
 
     ScanResult {
         root: PathBuf::from(base_path),
         files,
+        dirs: Vec::new(),
+        excluded: Vec::new(),
         scan_time: SystemTime::now(),
     }
 }
@@ -44,6 +47,8 @@ fn create_identical_scans(file_count: usize) -> (ScanResult, ScanResult) {
     let dest = ScanResult {
         root: PathBuf::from("dest"),
         files: source.files.clone(),
+        dirs: Vec::new(),
+        excluded: Vec::new(),
         scan_time: SystemTime::now(),
     };
     (source, dest)
@@ -55,6 +60,8 @@ fn create_all_new_scans(file_count: usize) -> (ScanResult, ScanResult) {
     let dest = ScanResult {
         root: PathBuf::from("dest"),
         files: vec![],
+        dirs: Vec::new(),
+        excluded: Vec::new(),
         scan_time: SystemTime::now(),
     };
     (source, dest)
@@ -76,8 +83,9 @@ fn create_modified_scans(file_count: usize, modify_percent: usize) -> (ScanResul
                     path: f.path.clone(),
                     size: f.size + 100,
                     mtime: f.mtime,
-                    hash: mock_hash((i + 100000) as u64),
+                    hash: Some(mock_hash((i + 100000) as u64)),
                     permissions: f.permissions,
+                    symlink_target: None,
                 }
             } else {
                 // Unchanged
@@ -89,6 +97,8 @@ fn create_modified_scans(file_count: usize, modify_percent: usize) -> (ScanResul
     let dest = ScanResult {
         root: PathBuf::from("dest"),
         files: dest_files,
+        dirs: Vec::new(),
+        excluded: Vec::new(),
         scan_time: SystemTime::now(),
     };
     (source, dest)
@@ -112,6 +122,7 @@ fn create_renamed_scans(file_count: usize, rename_percent: usize) -> (ScanResult
                     mtime: f.mtime,
                     hash: f.hash.clone(),
                     permissions: f.permissions,
+                    symlink_target: None,
                 }
             } else {
                 // Unchanged
@@ -123,6 +134,8 @@ fn create_renamed_scans(file_count: usize, rename_percent: usize) -> (ScanResult
     let dest = ScanResult {
         root: PathBuf::from("dest"),
         files: dest_files,
+        dirs: Vec::new(),
+        excluded: Vec::new(),
         scan_time: SystemTime::now(),
     };
     (source, dest)
@@ -146,8 +159,9 @@ fn create_mixed_scans(file_count: usize) -> (ScanResult, ScanResult) {
             path: source.files[i].path.clone(),
             size: source.files[i].size + 100,
             mtime: source.files[i].mtime,
-            hash: mock_hash((i + 100000) as u64),
+            hash: Some(mock_hash((i + 100000) as u64)),
             permissions: source.files[i].permissions,
+            symlink_target: None,
         });
     }
 
@@ -159,6 +173,7 @@ fn create_mixed_scans(file_count: usize) -> (ScanResult, ScanResult) {
             mtime: source.files[i].mtime,
             hash: source.files[i].hash.clone(),
             permissions: source.files[i].permissions,
+            symlink_target: None,
         });
     }
 
@@ -167,6 +182,8 @@ fn create_mixed_scans(file_count: usize) -> (ScanResult, ScanResult) {
     let dest = ScanResult {
         root: PathBuf::from("dest"),
         files: dest_files,
+        dirs: Vec::new(),
+        excluded: Vec::new(),
         scan_time: SystemTime::now(),
     };
     (source, dest)
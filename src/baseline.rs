@@ -0,0 +1,565 @@
+//! Two-way sync against the tree both sides last agreed on
+//!
+//! A one-way diff can't tell a file added on one side from one deleted on the
+//! other. So after every two-way sync the agreed tree is stored as the
+//! baseline, in `.jan-baseline` in both roots, and the next sync compares
+//! each side with it: whatever one side changed since is carried over to the
+//! other, deletes included. A path the two sides changed differently is a
+//! conflict and stays as it is on both. Without a baseline, as on the first
+//! sync, nothing counts as deleted: the trees are merged, and files that
+//! differ are conflicts.
+//!
+//! Each direction is then an ordinary diff applied with [`sync_changes`], so
+//! renames, journaling and transactional syncs work as they do one way.
+//!
+//! One-way syncs into a local destination leave a baseline there too, of
+//! what they wrote. A destination file that no longer matches it was changed
+//! since; if the source changed as well, [`find_conflicts`] reports it
+//! instead of letting it be overwritten unseen. Either way a
+//! [`ConflictPolicy`] settles conflicts.
+//!
+//! The file is a series of [`wire`](crate::wire) frames: a header with the
+//! format version, then the [`Baseline`] split over as many as it needs.
+
+use crate::core::{
+    content_differs, diff_scans_with_options, sync_changes, ConflictPolicy, DiffOptions,
+    DiffResult, DirMeta, FileConflict, FileMeta, Resolution, ScanResult, SyncOptions,
+};
+use crate::io::{format_utc, generate_temp_path, AtomicWriter, JAN_BASELINE_FILE, JAN_TEMP_DIR};
+use crate::lock::hostname;
+use crate::wire::{
+    from_bytes, read_chunked, read_frame, to_bytes, write_chunked, write_frame, WireError,
+};
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Baseline file format version
+pub const BASELINE_VERSION: u32 = 2;
+
+const MAGIC: [u8; 8] = *b"JAN-BASE";
+
+/// The tree two roots held after their last two-way sync
+#[derive(Debug, Clone)]
+pub struct Baseline {
+    /// Canonical roots of the pair, in the order they were synced
+    pub roots: (PathBuf, PathBuf),
+    pub files: Vec<FileMeta>,
+    pub dirs: Vec<DirMeta>,
+}
+
+impl Baseline {
+    /// Load the baseline `a_root` and `b_root` share, if they have one
+    ///
+    /// Either root's copy will do, as long as it was written for this pair in
+    /// either order; one left by a sync with some other tree is ignored. With
+    /// two, the newer wins, since one-way syncs only update the destination's.
+    pub fn load(a_root: &Path, b_root: &Path) -> Result<Option<Self>> {
+        let (a, b) = canonical_pair(a_root, b_root)?;
+        let mut newest: Option<(SystemTime, Self)> = None;
+        for root in [&a, &b] {
+            let path = root.join(JAN_BASELINE_FILE);
+            let Some(baseline) = Self::read(&path)? else {
+                continue;
+            };
+            let (x, y) = &baseline.roots;
+            let saved = fs::metadata(&path).and_then(|m| m.modified()).unwrap_or(UNIX_EPOCH);
+            let ours = (x == &a && y == &b) || (x == &b && y == &a);
+            if ours && newest.as_ref().map_or(true, |(time, _)| saved > *time) {
+                newest = Some((saved, baseline));
+            }
+        }
+        Ok(newest.map(|(_, baseline)| baseline))
+    }
+
+    /// The baseline a one-way sync of `diff` leaves in the destination
+    ///
+    /// That is the source tree plus the destination files the sync kept,
+    /// whether because `deleted` is false or they are protected. Conflicts
+    /// settled in the destination's favour keep their `previous` entry, so
+    /// they come up again next time.
+    pub fn after_sync(
+        source: &ScanResult,
+        dest: &ScanResult,
+        diff: &DiffResult,
+        deleted: bool,
+        previous: Option<&Baseline>,
+    ) -> Result<Self> {
+        let roots = canonical_pair(&source.root, &dest.root)?;
+        let mut files: BTreeMap<PathBuf, FileMeta> =
+            source.files.iter().map(|f| (f.path.clone(), f.clone())).collect();
+        let kept = if deleted {
+            &[][..]
+        } else {
+            diff.removed.as_slice()
+        };
+        for file in kept.iter().chain(&diff.protected) {
+            files.insert(file.path.clone(), file.clone());
+        }
+
+        let old: HashMap<&Path, &FileMeta> = previous
+            .map(|base| base.files.iter().map(|f| (f.path.as_path(), f)).collect())
+            .unwrap_or_default();
+        for conflict in &diff.conflicts {
+            let path = &conflict.dest.path;
+            match &conflict.resolution {
+                Resolution::Source => {},
+                Resolution::KeepBoth(aside) => {
+                    let moved = FileMeta {
+                        path: aside.clone(),
+                        ..conflict.dest.clone()
+                    };
+                    files.insert(aside.clone(), moved);
+                },
+                Resolution::Dest | Resolution::Unresolved => match old.get(path.as_path()) {
+                    Some(file) => {
+                        files.insert(path.clone(), (*file).clone());
+                    },
+                    None => {
+                        files.remove(path);
+                    },
+                },
+            }
+        }
+
+        let mut dirs = source.dirs.clone();
+        if !deleted {
+            let source_dirs: HashSet<&Path> =
+                source.dirs.iter().map(|d| d.path.as_path()).collect();
+            let moved = |path: &Path| diff.renamed_dirs.iter().any(|d| path.starts_with(&d.old));
+            dirs.extend(
+                dest.dirs
+                    .iter()
+                    .filter(|d| !source_dirs.contains(d.path.as_path()) && !moved(&d.path))
+                    .cloned(),
+            );
+        }
+        let files: Vec<FileMeta> = files.into_values().collect();
+        let known: HashMap<&Path, &DirMeta> =
+            source.dirs.iter().chain(&dest.dirs).map(|d| (d.path.as_path(), d)).collect();
+        keep_parents(&files, &mut dirs, &known);
+        Ok(Self { roots, files, dirs })
+    }
+
+    /// The baseline a resumed sync of `diff` leaves in the destination
+    ///
+    /// The source may have moved on since the sync was planned, so what the
+    /// sync wrote is read back from `dest`, scanned once it finished.
+    pub fn after_resume(
+        source_root: &Path,
+        dest: &ScanResult,
+        diff: &DiffResult,
+        previous: Option<&Baseline>,
+    ) -> Result<Self> {
+        let written = ScanResult {
+            root: source_root.to_path_buf(),
+            ..dest.clone()
+        };
+        // Whatever the sync kept is in `dest` already
+        Self::after_sync(&written, dest, diff, true, previous)
+    }
+
+    fn read(path: &Path) -> Result<Option<Self>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Can't read {}", path.display())),
+        };
+        let mut reader = BufReader::new(file);
+        let corrupt = |e: WireError| {
+            anyhow::anyhow!("Corrupt baseline {}: {e}; delete it to start over", path.display())
+        };
+
+        let header = read_frame(&mut reader).map_err(corrupt)?;
+        if header.len() != MAGIC.len() + 4 || header[..MAGIC.len()] != MAGIC {
+            anyhow::bail!("{} is not a jan baseline", path.display());
+        }
+        let version = u32::from_le_bytes(header[MAGIC.len()..].try_into().unwrap());
+        if version != BASELINE_VERSION {
+            anyhow::bail!(
+                "Baseline {} has format version {version}, this build reads {BASELINE_VERSION}",
+                path.display(),
+            );
+        }
+        let payload = read_chunked(&mut reader).map_err(corrupt)?;
+        Ok(Some(from_bytes(&payload).map_err(corrupt)?))
+    }
+
+    /// Write the baseline into both of its roots, replacing what was there
+    pub fn save(&self) -> io::Result<()> {
+        self.save_in(&self.roots.0)?;
+        self.save_in(&self.roots.1)
+    }
+
+    /// Write the baseline into `root` alone, as a one-way sync does
+    pub fn save_in(&self, root: &Path) -> io::Result<()> {
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&BASELINE_VERSION.to_le_bytes());
+        let mut content = Vec::new();
+        write_frame(&mut content, &header).map_err(io::Error::other)?;
+        write_chunked(&mut content, &to_bytes(self)).map_err(io::Error::other)?;
+
+        let temp_dir = root.join(JAN_TEMP_DIR);
+        fs::create_dir_all(&temp_dir)?;
+        let mut writer =
+            AtomicWriter::new(generate_temp_path(&temp_dir), root.join(JAN_BASELINE_FILE), false)?;
+        writer.write(&content)?;
+        writer.commit(None)?;
+        // Only succeeds if nothing else is using the temp dir
+        let _ = fs::remove_dir(&temp_dir);
+        Ok(())
+    }
+}
+
+/// Take the modified files whose source and destination copies both changed
+/// since `baseline` out of `diff.modified` and into `diff.conflicts`, settled
+/// by `policy`
+///
+/// A file the baseline doesn't know was created on both sides since, so it
+/// conflicts too. Settling by [`ConflictPolicy::Abort`] leaves them
+/// unresolved for the caller to stop on.
+pub fn find_conflicts(
+    diff: &mut DiffResult,
+    dest: &ScanResult,
+    baseline: &Baseline,
+    policy: ConflictPolicy,
+) {
+    let base: HashMap<&Path, &FileMeta> =
+        baseline.files.iter().map(|f| (f.path.as_path(), f)).collect();
+    let dest_files: HashMap<&Path, &FileMeta> =
+        dest.files.iter().map(|f| (f.path.as_path(), f)).collect();
+    let mut taken: HashSet<PathBuf> = dest.files.iter().map(|f| f.path.clone()).collect();
+    taken.extend(diff.added.iter().map(|f| f.path.clone()));
+    taken.extend(diff.renamed.iter().chain(&diff.copied).map(|(_, new)| new.path.clone()));
+
+    let mut modified = Vec::with_capacity(diff.modified.len());
+    for source in std::mem::take(&mut diff.modified) {
+        let agreed = base.get(source.path.as_path());
+        let changed = dest_files.get(source.path.as_path()).filter(|dest| {
+            agreed.map_or(true, |agreed| {
+                content_differs(&source, agreed) && content_differs(dest, agreed)
+            })
+        });
+        let Some(&dest) = changed else {
+            modified.push(source);
+            continue;
+        };
+        let resolution = policy.resolve(Some(&source), Some(dest), || {
+            let aside = conflict_path(&source.path, |p| taken.contains(p));
+            taken.insert(aside.clone());
+            aside
+        });
+        diff.conflicts.push(FileConflict { source, dest: dest.clone(), resolution });
+    }
+    diff.modified = modified;
+}
+
+/// Where the destination's version of `path` goes when both are kept:
+/// `NAME.conflict-<host>-<date>`, numbered if that is `taken`
+pub fn conflict_path(path: &Path, taken: impl Fn(&Path) -> bool) -> PathBuf {
+    let date = &format_utc(SystemTime::now())[..10];
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(format!(".conflict-{}-{date}", hostname()));
+    let mut aside = path.with_file_name(&name);
+    let mut n = 2;
+    while taken(&aside) {
+        let mut numbered = name.clone();
+        numbered.push(format!("-{n}"));
+        aside = path.with_file_name(numbered);
+        n += 1;
+    }
+    aside
+}
+
+/// A path both sides changed since the baseline, each its own way
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub path: PathBuf,
+    /// The file in the first tree, `None` if it was deleted there
+    pub a: Option<FileMeta>,
+    /// The file in the second tree, `None` if it was deleted there
+    pub b: Option<FileMeta>,
+    /// With the first tree as source: [`Resolution::Source`] means its
+    /// version wins, [`Resolution::KeepBoth`] that the second's moves aside
+    pub resolution: Resolution,
+}
+
+/// Everything a two-way sync will do
+#[derive(Debug, Clone)]
+pub struct TwoWayPlan {
+    /// Changes to carry from the first tree into the second
+    pub a_to_b: DiffResult,
+    /// Changes to carry from the second tree into the first
+    pub b_to_a: DiffResult,
+    /// Paths both sides changed, and how each is settled
+    pub conflicts: Vec<Conflict>,
+    /// Stored once both directions are applied
+    pub baseline: Baseline,
+}
+
+impl TwoWayPlan {
+    /// Whether applying the plan leaves both trees as they are
+    pub fn is_empty(&self) -> bool {
+        !has_changes(&self.a_to_b) && !has_changes(&self.b_to_a)
+    }
+}
+
+/// Where a path's next state comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    /// Both sides already match
+    Both,
+    A,
+    B,
+    Conflict,
+}
+
+/// Decide a path from its entries in both trees and the baseline
+fn pick<T: Copy>(a: Option<T>, b: Option<T>, base: Option<T>, same: impl Fn(T, T) -> bool) -> Side {
+    let same = |x: Option<T>, y: Option<T>| match (x, y) {
+        (Some(x), Some(y)) => same(x, y),
+        (x, y) => x.is_none() && y.is_none(),
+    };
+    if same(a, b) {
+        Side::Both
+    } else if same(a, base) {
+        Side::B
+    } else if same(b, base) {
+        Side::A
+    } else {
+        Side::Conflict
+    }
+}
+
+/// Compare both scans with their baseline and plan the sync in each direction
+///
+/// Both scans should be full ones: hashes a quick scan left out are not
+/// filled in for the three-way comparison. `options.protect` keeps files
+/// from being deleted on either side. Conflicts are settled by `policy`,
+/// with the first tree as the source, or left alone without one.
+pub fn plan_two_way(
+    a: &ScanResult,
+    b: &ScanResult,
+    baseline: Option<&Baseline>,
+    options: &DiffOptions,
+    policy: Option<ConflictPolicy>,
+) -> Result<TwoWayPlan> {
+    let roots = canonical_pair(&a.root, &b.root)?;
+    let (base_files, base_dirs) =
+        baseline.map_or((&[][..], &[][..]), |base| (base.files.as_slice(), base.dirs.as_slice()));
+
+    let mut want_a = Vec::new();
+    let mut want_b = Vec::new();
+    let mut agreed = Vec::new();
+    let mut conflicts = Vec::new();
+    let files = by_path([&a.files, &b.files, base_files], |f| &f.path);
+    let mut taken: HashSet<PathBuf> = files.keys().map(|p| p.to_path_buf()).collect();
+    for (&path, &[fa, fb, base]) in &files {
+        let side = pick(fa, fb, base, |x, y| !content_differs(x, y));
+        let resolution = match (side, policy) {
+            (Side::Conflict, Some(policy)) => policy.resolve(fa, fb, || {
+                let aside = conflict_path(path, |p| taken.contains(p));
+                taken.insert(aside.clone());
+                aside
+            }),
+            _ => Resolution::Unresolved,
+        };
+        if side == Side::Conflict {
+            conflicts.push(Conflict {
+                path: path.to_path_buf(),
+                a: fa.cloned(),
+                b: fb.cloned(),
+                resolution: resolution.clone(),
+            });
+        }
+
+        match (side, &resolution) {
+            (Side::Both, _) => {
+                want_a.extend(fa.cloned());
+                want_b.extend(fb.cloned());
+                agreed.extend(fa.cloned());
+            },
+            (Side::A, _) | (Side::Conflict, Resolution::Source) => {
+                want_a.extend(fa.cloned());
+                want_b.extend(fa.cloned());
+                agreed.extend(fa.cloned());
+            },
+            (Side::B, _) | (Side::Conflict, Resolution::Dest) => {
+                want_a.extend(fb.cloned());
+                want_b.extend(fb.cloned());
+                agreed.extend(fb.cloned());
+            },
+            // The second tree's version moves aside in both, and the first's
+            // takes its place
+            (Side::Conflict, Resolution::KeepBoth(aside)) => {
+                let (Some(fa), Some(fb)) = (fa, fb) else {
+                    unreachable!("both versions are kept only when both exist");
+                };
+                let moved = FileMeta { path: aside.clone(), ..fb.clone() };
+                for list in [&mut want_a, &mut want_b, &mut agreed] {
+                    list.push(fa.clone());
+                    list.push(moved.clone());
+                }
+            },
+            (Side::Conflict, Resolution::Unresolved) => {
+                want_a.extend(fa.cloned());
+                want_b.extend(fb.cloned());
+                // Still unsettled next time
+                agreed.extend(base.cloned());
+            },
+        }
+    }
+
+    let mut want_a_dirs = Vec::new();
+    let mut want_b_dirs = Vec::new();
+    let mut agreed_dirs = Vec::new();
+    // Directories only count by whether they exist
+    for (_, [da, db, base]) in by_path([&a.dirs, &b.dirs, base_dirs], |d| &d.path) {
+        let keep = match pick(da, db, base, |_, _| true) {
+            Side::A => [da, da],
+            Side::B => [db, db],
+            Side::Both | Side::Conflict => [da, db],
+        };
+        want_a_dirs.extend(keep[0].cloned());
+        want_b_dirs.extend(keep[1].cloned());
+        agreed_dirs.extend(keep[0].cloned());
+    }
+
+    let want = |root: &Path, files, dirs| ScanResult {
+        root: root.to_path_buf(),
+        files,
+        dirs,
+        excluded: Vec::new(),
+        scan_time: SystemTime::now(),
+    };
+    let known_dirs: HashMap<&Path, &DirMeta> =
+        a.dirs.iter().chain(&b.dirs).map(|d| (d.path.as_path(), d)).collect();
+    keep_parents(&want_a, &mut want_a_dirs, &known_dirs);
+    keep_parents(&want_b, &mut want_b_dirs, &known_dirs);
+    let a_to_b = diff_scans_with_options(&want(&a.root, want_b, want_b_dirs), b, options)?;
+    let b_to_a = diff_scans_with_options(&want(&b.root, want_a, want_a_dirs), a, options)?;
+
+    // A protected file stays deleted on one side only, not copied back later
+    agreed.extend(a_to_b.protected.iter().chain(&b_to_a.protected).cloned());
+    keep_parents(&agreed, &mut agreed_dirs, &known_dirs);
+
+    Ok(TwoWayPlan {
+        a_to_b,
+        b_to_a,
+        conflicts,
+        baseline: Baseline { roots, files: agreed, dirs: agreed_dirs },
+    })
+}
+
+/// Apply `plan` one direction after the other, then store its baseline
+///
+/// Deletes are always carried over, whatever `options.delete_removed` says.
+pub fn sync_two_way(plan: &TwoWayPlan, options: &SyncOptions) -> Result<()> {
+    let options = SyncOptions { delete_removed: true, ..options.clone() };
+    let (a_root, b_root) = &plan.baseline.roots;
+    if has_changes(&plan.a_to_b) {
+        sync_changes(a_root, b_root, &plan.a_to_b, &options)?;
+    }
+    if has_changes(&plan.b_to_a) {
+        sync_changes(b_root, a_root, &plan.b_to_a, &options)?;
+    }
+    plan.baseline.save().context("Can't save the baseline")
+}
+
+fn has_changes(diff: &DiffResult) -> bool {
+    !(diff.added.is_empty()
+        && diff.removed.is_empty()
+        && diff.modified.is_empty()
+        && diff.renamed.is_empty()
+        && diff.copied.is_empty()
+        && diff.renamed_dirs.is_empty()
+        && diff.added_dirs.is_empty()
+        && diff.removed_dirs.is_empty()
+        && diff.modified_dirs.is_empty())
+}
+
+/// Every path in any of `lists`, with its entry in each
+fn by_path<'a, T>(
+    lists: [&'a [T]; 3],
+    path: impl Fn(&'a T) -> &'a PathBuf,
+) -> BTreeMap<&'a Path, [Option<&'a T>; 3]> {
+    let mut paths = BTreeMap::new();
+    for (i, list) in lists.into_iter().enumerate() {
+        for item in list {
+            paths.entry(path(item).as_path()).or_insert([None; 3])[i] = Some(item);
+        }
+    }
+    paths
+}
+
+/// Add the directories above `files` that `dirs` is missing
+///
+/// A file kept in a directory the other side deleted keeps the directory.
+fn keep_parents(files: &[FileMeta], dirs: &mut Vec<DirMeta>, known: &HashMap<&Path, &DirMeta>) {
+    let mut present: HashSet<PathBuf> = dirs.iter().map(|d| d.path.clone()).collect();
+    for file in files {
+        for parent in file.path.ancestors().skip(1) {
+            if parent.as_os_str().is_empty() {
+                break;
+            }
+            if present.insert(parent.to_path_buf()) {
+                dirs.extend(known.get(parent).map(|d| (*d).clone()));
+            }
+        }
+    }
+    dirs.sort_by(|x, y| x.path.cmp(&y.path));
+}
+
+fn canonical_pair(a: &Path, b: &Path) -> Result<(PathBuf, PathBuf)> {
+    let canonical =
+        |p: &Path| fs::canonicalize(p).with_context(|| format!("Can't resolve {}", p.display()));
+    Ok((canonical(a)?, canonical(b)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_baseline_is_found_for_its_pair_only() -> Result<()> {
+        let (a, b, c) = (tempdir()?, tempdir()?, tempdir()?);
+        let baseline = Baseline {
+            roots: canonical_pair(a.path(), b.path())?,
+            files: Vec::new(),
+            dirs: Vec::new(),
+        };
+        baseline.save()?;
+        assert!(a.path().join(JAN_BASELINE_FILE).exists());
+        assert!(b.path().join(JAN_BASELINE_FILE).exists());
+
+        let loaded = Baseline::load(b.path(), a.path())?.unwrap();
+        assert_eq!(loaded.roots, baseline.roots);
+        assert!(Baseline::load(a.path(), c.path())?.is_none());
+
+        // The other root's copy stands in for a missing one
+        fs::remove_file(a.path().join(JAN_BASELINE_FILE))?;
+        assert!(Baseline::load(a.path(), b.path())?.is_some());
+
+        fs::write(b.path().join(JAN_BASELINE_FILE), b"garbage")?;
+        assert!(Baseline::load(a.path(), b.path()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pick_follows_the_side_that_changed() {
+        let same = |x: u8, y: u8| x == y;
+        assert_eq!(pick(Some(1), Some(1), Some(0), same), Side::Both);
+        assert_eq!(pick(Some(2), Some(1), Some(1), same), Side::A);
+        assert_eq!(pick(None, Some(1), Some(1), same), Side::A);
+        assert_eq!(pick(Some(1), None, None, same), Side::A);
+        assert_eq!(pick(Some(1), Some(2), Some(1), same), Side::B);
+        assert_eq!(pick(Some(2), Some(3), Some(1), same), Side::Conflict);
+        assert_eq!(pick(None, Some(2), Some(1), same), Side::Conflict);
+        assert_eq!(pick(Some(1), Some(2), None, same), Side::Conflict);
+    }
+}
@@ -0,0 +1,363 @@
+//! Content-defined chunking (FastCDC) and a chunk-level dedup store
+//!
+//! Files are cut where a gear hash of the last few dozen bytes hits a mask,
+//! so chunk boundaries follow the content rather than fixed offsets: an
+//! insertion only disturbs the chunks around it, and near-duplicate files
+//! share most of their chunks. Every chunk is named by its content hash.
+//!
+//! Cut points are normalized FastCDC: a stricter mask before the average size
+//! and a looser one after it keep chunk sizes close to the average.
+
+use crate::hash::{hash_bytes, ContentHash, Hasher};
+use crate::io::{read_full, set_file_mtime, AtomicWriter};
+use ahash::HashMap;
+use rayon::prelude::*;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+const READ_SIZE: usize = 256 * 1024;
+
+/// Chunk size bounds; cut points land near `avg`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkerConfig {
+    pub min: usize,
+    pub avg: usize,
+    pub max: usize,
+}
+
+impl Default for ChunkerConfig {
+    fn default() -> Self {
+        Self {
+            min: 4 * 1024,
+            avg: 16 * 1024,
+            max: 64 * 1024,
+        }
+    }
+}
+
+/// One content-defined chunk of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub offset: u64,
+    pub len: u32,
+    pub hash: ContentHash,
+}
+
+/// Where a chunk can be read from in the destination
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkLocation {
+    pub path: PathBuf,
+    pub offset: u64,
+    pub len: u32,
+}
+
+/// How much of a rebuilt file came from existing chunks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChunkStats {
+    pub reused: u64,
+    pub literal: u64,
+}
+
+// Random 64-bit value per byte; splitmix64 so the table is fixed across builds
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Mask of the top `bits` bits; the gear hash mixes its high bits best
+fn top_bits(bits: u32) -> u64 {
+    !0u64 << (64 - bits)
+}
+
+impl ChunkerConfig {
+    /// Length of the next chunk at the start of `data`
+    ///
+    /// `data` must hold `max` bytes unless the input ends within it.
+    pub fn cut_point(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min {
+            return data.len();
+        }
+        let end = data.len().min(self.max);
+        let normal = self.avg.min(end);
+        let bits = self.avg.max(4).ilog2();
+        let (strict, loose) = (top_bits(bits + 1), top_bits(bits - 1));
+
+        let mut fingerprint = 0u64;
+        for (i, &byte) in data.iter().enumerate().take(end).skip(self.min) {
+            fingerprint = (fingerprint << 1).wrapping_add(GEAR[byte as usize]);
+            let mask = if i < normal { strict } else { loose };
+            if fingerprint & mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+
+    /// Cut `reader` into chunks, handing each one's offset and bytes to `visit`
+    pub fn for_each_chunk(
+        &self,
+        mut reader: impl Read,
+        mut visit: impl FnMut(u64, &[u8]) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let mut buf: Vec<u8> = Vec::with_capacity(READ_SIZE + self.max);
+        let mut start = 0;
+        let mut offset = 0u64;
+        let mut eof = false;
+        loop {
+            if !eof && buf.len() - start < self.max {
+                buf.drain(..start);
+                start = 0;
+                let filled = buf.len();
+                buf.resize(filled + READ_SIZE.max(self.max), 0);
+                let n = read_full(&mut reader, &mut buf[filled..])?;
+                eof = filled + n < buf.len();
+                buf.truncate(filled + n);
+            }
+            if start == buf.len() {
+                return Ok(());
+            }
+            let len = self.cut_point(&buf[start..]);
+            visit(offset, &buf[start..start + len])?;
+            start += len;
+            offset += len as u64;
+        }
+    }
+
+    /// Chunk list of a file
+    pub fn chunk_file(&self, path: &Path) -> io::Result<Vec<Chunk>> {
+        let file = File::open(path)?;
+        let mut chunks = Vec::new();
+        self.for_each_chunk(BufReader::with_capacity(READ_SIZE, file), |offset, data| {
+            chunks.push(Chunk {
+                offset,
+                len: data.len() as u32,
+                hash: hash_bytes(data),
+            });
+            Ok(())
+        })?;
+        Ok(chunks)
+    }
+}
+
+/// Every chunk already present in a tree, by content hash
+#[derive(Debug, Default)]
+pub struct ChunkStore {
+    chunks: HashMap<ContentHash, ChunkLocation>,
+}
+
+impl ChunkStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Chunk `files` (relative to `root`) in parallel and record where each chunk lives
+    ///
+    /// Files that can't be read are left out; they only cost a missed match.
+    pub fn build(root: &Path, files: &[PathBuf], config: &ChunkerConfig) -> Self {
+        let chunked: Vec<(PathBuf, Vec<Chunk>)> = files
+            .par_iter()
+            .filter_map(|rel| {
+                let path = root.join(rel);
+                config.chunk_file(&path).ok().map(|chunks| (path, chunks))
+            })
+            .collect();
+
+        let mut store = Self::new();
+        for (path, chunks) in chunked {
+            store.insert_file(&path, &chunks);
+        }
+        store
+    }
+
+    /// Record the chunks of the file at `path`; the first location seen wins
+    pub fn insert_file(&mut self, path: &Path, chunks: &[Chunk]) {
+        for chunk in chunks {
+            self.chunks.entry(chunk.hash.clone()).or_insert_with(|| ChunkLocation {
+                path: path.to_path_buf(),
+                offset: chunk.offset,
+                len: chunk.len,
+            });
+        }
+    }
+
+    pub fn get(&self, hash: &ContentHash) -> Option<&ChunkLocation> {
+        self.chunks.get(hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Read a stored chunk into `buf`; false if it's gone or no longer hashes the same
+    ///
+    /// Files in the store may be replaced during a sync, so every read is
+    /// checked against the chunk's hash before it is trusted.
+    pub fn read(&self, hash: &ContentHash, buf: &mut Vec<u8>) -> bool {
+        let Some(location) = self.get(hash) else {
+            return false;
+        };
+        let read = |buf: &mut Vec<u8>| -> io::Result<()> {
+            let mut file = File::open(&location.path)?;
+            file.seek(SeekFrom::Start(location.offset))?;
+            buf.resize(location.len as usize, 0);
+            file.read_exact(buf)
+        };
+        read(buf).is_ok() && hash_bytes(buf) == *hash
+    }
+}
+
+/// Write `source` to `dest`, taking every chunk the store already has from there
+///
+/// The new file is assembled at `temp_path` and committed atomically. Like
+/// a delta copy its hash is always checked, against `expected_hash` if given
+/// or else against the source as read.
+pub fn atomic_chunked_copy(
+    source: &Path,
+    dest: &Path,
+    temp_path: &Path,
+    store: &ChunkStore,
+    config: &ChunkerConfig,
+    preserve_timestamps: bool,
+    expected_hash: Option<&ContentHash>,
+) -> io::Result<ChunkStats> {
+    let metadata = fs::metadata(source)?;
+    let mut writer = AtomicWriter::new(temp_path.to_path_buf(), dest.to_path_buf(), true)?;
+    let mut source_hasher = Hasher::new();
+    let mut stats = ChunkStats::default();
+    let mut stored = Vec::with_capacity(config.max);
+
+    let reader = BufReader::with_capacity(READ_SIZE, File::open(source)?);
+    config.for_each_chunk(reader, |_, data| {
+        source_hasher.update(data);
+        let hash = hash_bytes(data);
+        if store.read(&hash, &mut stored) {
+            stats.reused += stored.len() as u64;
+            writer.write(&stored)
+        } else {
+            stats.literal += data.len() as u64;
+            writer.write(data)
+        }
+    })?;
+
+    let source_hash = source_hasher.finalize();
+    writer.commit(Some(expected_hash.unwrap_or(&source_hash)))?;
+
+    if preserve_timestamps {
+        set_file_mtime(dest, metadata.modified()?)?;
+    }
+
+    #[cfg(unix)]
+    {
+        crate::io::set_file_permissions(dest, &metadata)?;
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta::tests::sample;
+    use std::collections::HashSet;
+
+    fn chunk_hashes(config: &ChunkerConfig, data: &[u8]) -> Vec<(usize, ContentHash)> {
+        let mut chunks = Vec::new();
+        config
+            .for_each_chunk(data, |_, bytes| {
+                chunks.push((bytes.len(), hash_bytes(bytes)));
+                Ok(())
+            })
+            .unwrap();
+        chunks
+    }
+
+    #[test]
+    fn test_chunks_cover_input_within_bounds() {
+        let config = ChunkerConfig::default();
+        let data = sample(1_000_000, 1);
+        let chunks = chunk_hashes(&config, &data);
+
+        assert_eq!(chunks.iter().map(|(len, _)| len).sum::<usize>(), data.len());
+        let (last, body) = chunks.split_last().unwrap();
+        assert!(body.iter().all(|(len, _)| (config.min..=config.max).contains(len)));
+        assert!(last.0 <= config.max);
+        // Normalized chunking keeps the mean near the target
+        let mean = data.len() / chunks.len();
+        assert!(mean > config.avg / 2 && mean < config.avg * 2, "mean chunk size {mean}");
+    }
+
+    #[test]
+    fn test_insertion_only_disturbs_nearby_chunks() {
+        let config = ChunkerConfig::default();
+        let old = sample(500_000, 2);
+        let mut new = old.clone();
+        new.splice(250_000..250_000, sample(100, 3));
+
+        let before: HashSet<ContentHash> =
+            chunk_hashes(&config, &old).into_iter().map(|(_, h)| h).collect();
+        let after = chunk_hashes(&config, &new);
+        let fresh = after.iter().filter(|(_, h)| !before.contains(h)).count();
+        assert!(fresh <= 2, "{fresh} of {} chunks changed", after.len());
+    }
+
+    #[test]
+    fn test_chunked_copy_reuses_chunks_from_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ChunkerConfig::default();
+        let shared = sample(300_000, 4);
+        fs::write(dir.path().join("old.bin"), &shared).unwrap();
+
+        let mut source = sample(1_000, 5);
+        source.extend_from_slice(&shared);
+        fs::write(dir.path().join("source.bin"), &source).unwrap();
+
+        let store = ChunkStore::build(dir.path(), &[PathBuf::from("old.bin")], &config);
+        assert!(!store.is_empty());
+
+        let dest = dir.path().join("new.bin");
+        let stats = atomic_chunked_copy(
+            &dir.path().join("source.bin"),
+            &dest,
+            &dir.path().join("new.tmp"),
+            &store,
+            &config,
+            false,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(fs::read(&dest).unwrap(), source);
+        assert!(stats.reused > 250_000, "reused only {} bytes", stats.reused);
+        assert_eq!(stats.reused + stats.literal, source.len() as u64);
+    }
+
+    #[test]
+    fn test_store_ignores_stale_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ChunkerConfig::default();
+        let path = dir.path().join("a.bin");
+        fs::write(&path, sample(10_000, 6)).unwrap();
+        let first = config.chunk_file(&path).unwrap().remove(0);
+        let store = ChunkStore::build(dir.path(), &[PathBuf::from("a.bin")], &config);
+
+        let mut buf = Vec::new();
+        assert!(store.read(&first.hash, &mut buf));
+        fs::write(&path, sample(10_000, 7)).unwrap();
+        assert!(!store.read(&first.hash, &mut buf));
+    }
+}
@@ -1,16 +1,29 @@
 //! Core synchronization logic for scanning, diffing, and syncing directories.
 
-use crate::hash::{ContentHash, Hasher};
+use crate::chunk::{atomic_chunked_copy, ChunkStore, ChunkerConfig};
+use crate::delta::atomic_delta_copy;
+use crate::filter::{Filter, FilterAction, TreeFilter, EXCLUDE_SOURCE};
+use crate::hash::{hash_bytes, hash_file, ContentHash, Hasher};
+use crate::index::{HashIndex, IndexKey};
 use crate::io::{
-    atomic_copy_file_with_metadata, fsync_directory, generate_temp_path, remove_file_safe,
-    SyncJournal, JAN_JOURNAL_FILE, JAN_TEMP_DIR,
+    atomic_copy_file_with_metadata, atomic_hard_link, atomic_reflink, atomic_resumable_copy,
+    atomic_symlink, fsync_directory, generate_temp_path, link_or_copy, move_dir, move_file,
+    remove_file_safe, set_file_mtime, PartialCopy, ResumePoint, SyncJournal, JAN_BASELINE_FILE,
+    JAN_INCOMING_DIR, JAN_INDEX_FILE, JAN_JOURNAL_FILE, JAN_LOCK_FILE, JAN_PLAN_FILE,
+    JAN_ROLLBACK_DIR, JAN_TEMP_DIR, JAN_TRASH_DIR, RESUME_CHECKPOINT,
 };
+use crate::plan::{PlanLog, SavedPlan, Step, SyncPlan};
+use crate::rollback::{rollback, undo_state, UndoLog, UndoState};
 use ahash::{HashMap, HashMapExt};
 use anyhow::Result;
 use rayon::prelude::*;
+use std::borrow::Cow;
 use std::collections::HashSet;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 use thiserror::Error;
 
@@ -42,10 +55,43 @@ pub struct FileMeta {
     pub size: u64,
     /// Last modified time
     pub mtime: SystemTime,
-    /// Content hash (BLAKE3 or SHA-256)
-    pub hash: ContentHash,
+    /// Content hash (BLAKE3 or SHA-256); for symlinks, the hash of the target.
+    /// `None` when a quick scan deferred hashing to the diff.
+    pub hash: Option<ContentHash>,
     /// Unix permissions (if available)
     pub permissions: Option<u32>,
+    /// Link target if this entry is a symlink rather than a regular file
+    pub symlink_target: Option<PathBuf>,
+}
+
+/// Metadata for a single directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirMeta {
+    /// Relative path from scan root
+    pub path: PathBuf,
+    /// Last modified time
+    pub mtime: SystemTime,
+    /// Unix permissions (if available)
+    pub permissions: Option<u32>,
+}
+
+impl DirMeta {
+    /// Build from the metadata of the directory at `path` (relative to its root)
+    pub fn from_metadata(path: PathBuf, metadata: &fs::Metadata) -> std::io::Result<Self> {
+        #[cfg(unix)]
+        let permissions = {
+            use std::os::unix::fs::PermissionsExt;
+            Some(metadata.permissions().mode())
+        };
+        #[cfg(not(unix))]
+        let permissions = None;
+
+        Ok(Self {
+            path,
+            mtime: metadata.modified()?,
+            permissions,
+        })
+    }
 }
 
 /// Result of scanning a directory
@@ -55,6 +101,10 @@ pub struct ScanResult {
     pub root: PathBuf,
     /// List of all files found
     pub files: Vec<FileMeta>,
+    /// Every directory below the root, empty or not
+    pub dirs: Vec<DirMeta>,
+    /// Paths skipped per rule source, e.g. `(".gitignore", 12)`
+    pub excluded: Vec<(String, usize)>,
     /// Timestamp when scan was performed
     pub scan_time: SystemTime,
 }
@@ -77,6 +127,182 @@ pub struct DiffResult {
     pub modified: Vec<FileMeta>,
     /// Files that were renamed (old, new)
     pub renamed: Vec<(FileMeta, FileMeta)>,
+    /// New files whose content already exists in the destination (existing, new)
+    pub copied: Vec<(FileMeta, FileMeta)>,
+    /// Whole directories that moved; applied before everything else, and all
+    /// other destination paths in this diff refer to the tree after the moves
+    pub renamed_dirs: Vec<DirRename>,
+    /// Directories present in source but not in destination
+    pub added_dirs: Vec<DirMeta>,
+    /// Directories present in destination but not in source
+    pub removed_dirs: Vec<DirMeta>,
+    /// Directories present in both but with different mtime or permissions
+    pub modified_dirs: Vec<DirMeta>,
+    /// Files missing from source that protect rules keep from being deleted
+    pub protected: Vec<FileMeta>,
+    /// Files changed in the destination since the last sync and in the source
+    /// too; found by [`find_conflicts`](crate::baseline::find_conflicts) and
+    /// no longer listed as modified
+    pub conflicts: Vec<FileConflict>,
+}
+
+impl DiffResult {
+    /// Files written from the source, in the order of [`Step::Copy`]: new,
+    /// modified, then conflicts settled in the source's favour
+    pub fn files_to_copy(&self) -> Vec<&FileMeta> {
+        let conflicts = self.conflicts.iter().filter(|c| c.resolution.takes_source());
+        self.added
+            .iter()
+            .chain(&self.modified)
+            .chain(conflicts.map(|c| &c.source))
+            .collect()
+    }
+}
+
+/// A directory whose contents moved as a unit
+#[derive(Debug, Clone)]
+pub struct DirRename {
+    /// Directory path in the destination
+    pub old: PathBuf,
+    /// Directory path in the source
+    pub new: PathBuf,
+    /// File renames covered by this move (old, new)
+    pub files: Vec<(FileMeta, FileMeta)>,
+}
+
+impl DirRename {
+    /// Total size of the files carried by this move
+    pub fn total_size(&self) -> u64 {
+        self.files.iter().map(|(old, _)| old.size).sum()
+    }
+}
+
+/// A file changed in both trees since they were last synced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileConflict {
+    /// The source's version
+    pub source: FileMeta,
+    /// The destination's version
+    pub dest: FileMeta,
+    pub resolution: Resolution,
+}
+
+/// How a conflict is settled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// The source's version replaces the destination's
+    Source,
+    /// The destination's version stays
+    Dest,
+    /// The destination's version moves aside to this path, then the source's
+    /// takes its place
+    KeepBoth(PathBuf),
+    /// Both stay as they are, and the conflict comes up again next time
+    Unresolved,
+}
+
+impl Resolution {
+    /// Whether the source's version gets written
+    pub fn takes_source(&self) -> bool {
+        matches!(self, Resolution::Source | Resolution::KeepBoth(_))
+    }
+}
+
+/// What to do with a file changed on both sides since the last sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    /// Overwrite the destination's changes, as if there were no conflict
+    #[default]
+    SourceWins,
+    /// Keep the destination's changes
+    DestWins,
+    /// Keep whichever version was modified last
+    Newer,
+    /// Keep the destination's version under a `.conflict-<host>-<date>` name
+    KeepBoth,
+    /// Change nothing; the caller stops before syncing
+    Abort,
+}
+
+impl ConflictPolicy {
+    /// Settle a conflict between two versions of a file, one maybe deleted
+    ///
+    /// `aside` names where the destination's version goes if both are kept.
+    /// Where only one version is left, keeping both or the newer means
+    /// keeping that one: an edit beats a delete.
+    pub fn resolve(
+        self,
+        source: Option<&FileMeta>,
+        dest: Option<&FileMeta>,
+        aside: impl FnOnce() -> PathBuf,
+    ) -> Resolution {
+        match (self, source, dest) {
+            (ConflictPolicy::SourceWins, _, _) => Resolution::Source,
+            (ConflictPolicy::DestWins, _, _) => Resolution::Dest,
+            (ConflictPolicy::Abort, _, _) => Resolution::Unresolved,
+            (ConflictPolicy::KeepBoth, Some(_), Some(_)) => Resolution::KeepBoth(aside()),
+            (_, Some(source), Some(dest)) if source.mtime < dest.mtime => Resolution::Dest,
+            (_, Some(_), _) => Resolution::Source,
+            (_, None, _) => Resolution::Dest,
+        }
+    }
+}
+
+/// How the scanner treats symbolic links
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkMode {
+    /// Record links as links, comparing and recreating their targets
+    #[default]
+    Preserve,
+    /// Follow links and treat what they point to as regular files and dirs
+    Follow,
+    /// Like `Preserve`, but skip absolute links and links escaping the root
+    Safe,
+}
+
+/// Options for directory scans
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    /// Ordered include/exclude rules, first match wins
+    pub filter: Filter,
+    /// Glob patterns to exclude from scan, checked after `filter`
+    pub exclude_patterns: Vec<String>,
+    /// Honour `.gitignore` files and `.git/info/exclude`
+    pub respect_gitignore: bool,
+    /// Extra per-directory ignore files to honour, e.g. `.janignore`
+    pub ignore_files: Vec<String>,
+    /// Reuse hashes from the tree's `.jan-index`
+    pub use_index: bool,
+    /// Rewrite the tree's `.jan-index` after the scan; off for trees jan must
+    /// not write to, such as a sync's source or any tree in a dry run
+    pub save_index: bool,
+    /// Ignore stored hashes and hash every file again (`save_index` still applies)
+    pub rehash: bool,
+    /// Symlink handling
+    pub symlinks: SymlinkMode,
+    /// Don't hash files the index can't vouch for; the diff hashes only those
+    /// it can't settle by size and mtime
+    pub quick: bool,
+}
+
+/// Options for comparing scans
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    /// Destination paths never reported as removed: those for which the first
+    /// matching rule (on the path or a parent dir) is an include rule
+    pub protect: Filter,
+}
+
+/// How content that already exists in the destination is duplicated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LocalCopyMode {
+    /// Stream a fresh copy from the existing destination file
+    #[default]
+    Copy,
+    /// Hard-link to the existing destination file (shares metadata)
+    Hardlink,
+    /// Clone the existing file's blocks, falling back to a copy if unsupported
+    Reflink,
 }
 
 /// Options for sync operations
@@ -88,6 +314,21 @@ pub struct SyncOptions {
     pub preserve_timestamps: bool,
     /// Verify file hash after copying
     pub verify_after_copy: bool,
+    /// How to materialize files whose content is already in the destination
+    pub local_copy: LocalCopyMode,
+    /// Rules the destination was filtered with; excluded paths are never deleted
+    pub filter: Filter,
+    /// Rebuild modified files from the blocks of their old copy that still match
+    pub delta: bool,
+    /// Build new and modified files from content-defined chunks found anywhere
+    /// in the destination
+    pub chunks: bool,
+    /// Keep everything the sync overwrites or deletes, and put it all back if
+    /// any step fails; see [`rollback`](crate::rollback)
+    pub transactional: bool,
+    /// Keep what the sync deletes or overwrites in this directory, relative
+    /// to the destination root unless absolute; see [`trash`](crate::trash)
+    pub backup_dir: Option<PathBuf>,
 }
 
 impl Default for SyncOptions {
@@ -96,6 +337,12 @@ impl Default for SyncOptions {
             delete_removed: false,
             preserve_timestamps: true,
             verify_after_copy: false,
+            local_copy: LocalCopyMode::Copy,
+            filter: Filter::new(),
+            delta: false,
+            chunks: false,
+            transactional: false,
+            backup_dir: None,
         }
     }
 }
@@ -115,7 +362,7 @@ impl Default for SyncOptions {
 /// - Uses `ignore` crate for parallel directory traversal
 /// - Hashes files in parallel using `rayon`
 /// - Streaming hash computation for constant memory usage
-/// - Respects .gitignore patterns for efficiency
+/// - Ignore files are not consulted unless requested via [`ScanOptions`]
 pub fn scan_directory(root: &Path) -> Result<ScanResult> {
     scan_directory_with_excludes(root, &[])
 }
@@ -125,6 +372,22 @@ pub fn scan_directory_with_excludes(
     root: &Path,
     exclude_patterns: &[String],
 ) -> Result<ScanResult> {
+    scan_directory_with_options(
+        root,
+        &ScanOptions {
+            exclude_patterns: exclude_patterns.to_vec(),
+            ..ScanOptions::default()
+        },
+    )
+}
+
+/// Scan a directory with full control over excludes and the hash index
+///
+/// With `use_index`, files whose size, mtime, inode and ctime match the
+/// tree's `.jan-index` reuse the stored hash instead of being read again.
+/// With `save_index`, the hashes are written back; failing to is reported as
+/// a warning, never an error.
+pub fn scan_directory_with_options(root: &Path, options: &ScanOptions) -> Result<ScanResult> {
     if !root.exists() {
         return Err(SyncError::InvalidPath(format!(
             "Directory does not exist: {}",
@@ -133,20 +396,34 @@ pub fn scan_directory_with_excludes(
         .into());
     }
 
+    let mut filter = options.filter.clone();
+    for pattern in &options.exclude_patterns {
+        filter
+            .exclude(pattern, EXCLUDE_SOURCE)
+            .map_err(|e| SyncError::InvalidPath(e.to_string()))?;
+    }
+    let filter =
+        Arc::new(TreeFilter::new(root, filter, options.respect_gitignore, &options.ignore_files));
+
+    // Ignore files only count when asked for, via the filter below
     let mut builder = ignore::WalkBuilder::new(root);
     builder
         .hidden(false)
-        .git_ignore(true)
-        .git_exclude(true)
+        .parents(false)
+        .ignore(false)
+        .git_ignore(false)
+        .git_global(false)
+        .git_exclude(false)
+        .follow_links(options.symlinks == SymlinkMode::Follow)
         .threads(std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1));
 
-    // Add custom exclude patterns
+    let entry_filter = Arc::clone(&filter);
+    builder.filter_entry(move |entry| {
+        let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+        entry.depth() == 0 || !entry_filter.is_excluded(entry.path(), is_dir)
+    });
+
     let mut override_builder = ignore::overrides::OverrideBuilder::new(root);
-    for pattern in exclude_patterns {
-        override_builder.add(&format!("!{}", pattern)).map_err(|e| {
-            SyncError::InvalidPath(format!("Invalid exclude pattern '{}': {}", pattern, e))
-        })?;
-    }
 
     // Auto-exclude Janice internal files
     override_builder
@@ -155,6 +432,28 @@ pub fn scan_directory_with_excludes(
     override_builder
         .add(&format!("!{JAN_JOURNAL_FILE}"))
         .map_err(|e| SyncError::InvalidPath(format!("Internal exclude failed: {e}")))?;
+    override_builder
+        .add(&format!("!{JAN_PLAN_FILE}"))
+        .map_err(|e| SyncError::InvalidPath(format!("Internal exclude failed: {e}")))?;
+    override_builder
+        .add(&format!("!{JAN_ROLLBACK_DIR}"))
+        .map_err(|e| SyncError::InvalidPath(format!("Internal exclude failed: {e}")))?;
+    // The lock, and the copy it is linked from while being taken
+    override_builder
+        .add(&format!("!{JAN_LOCK_FILE}*"))
+        .map_err(|e| SyncError::InvalidPath(format!("Internal exclude failed: {e}")))?;
+    override_builder
+        .add(&format!("!{JAN_INDEX_FILE}"))
+        .map_err(|e| SyncError::InvalidPath(format!("Internal exclude failed: {e}")))?;
+    override_builder
+        .add(&format!("!{JAN_INCOMING_DIR}"))
+        .map_err(|e| SyncError::InvalidPath(format!("Internal exclude failed: {e}")))?;
+    override_builder
+        .add(&format!("!{JAN_BASELINE_FILE}"))
+        .map_err(|e| SyncError::InvalidPath(format!("Internal exclude failed: {e}")))?;
+    override_builder
+        .add(&format!("!{JAN_TRASH_DIR}"))
+        .map_err(|e| SyncError::InvalidPath(format!("Internal exclude failed: {e}")))?;
 
     if let Ok(overrides) = override_builder.build() {
         builder.overrides(overrides);
@@ -162,14 +461,27 @@ pub fn scan_directory_with_excludes(
 
     let walker = builder.build_parallel();
 
+    // (path, is_symlink); followed links already report their target's type
     let files = std::sync::Mutex::new(Vec::with_capacity(1024));
+    let dirs = std::sync::Mutex::new(Vec::new());
+    let unsafe_links = AtomicUsize::new(0);
 
     walker.run(|| {
         Box::new(|entry_result| {
             if let Ok(entry) = entry_result {
                 if let Some(file_type) = entry.file_type() {
                     if file_type.is_file() {
-                        files.lock().unwrap().push(entry.path().to_path_buf());
+                        files.lock().unwrap().push((entry.path().to_path_buf(), false));
+                    } else if file_type.is_symlink() {
+                        if options.symlinks == SymlinkMode::Safe
+                            && !is_safe_symlink(root, entry.path())
+                        {
+                            unsafe_links.fetch_add(1, Ordering::Relaxed);
+                        } else {
+                            files.lock().unwrap().push((entry.path().to_path_buf(), true));
+                        }
+                    } else if file_type.is_dir() && entry.depth() > 0 {
+                        dirs.lock().unwrap().push(entry.path().to_path_buf());
                     }
                 }
             }
@@ -178,29 +490,56 @@ pub fn scan_directory_with_excludes(
     });
 
     let file_paths = files.into_inner().unwrap();
+    let dir_paths = dirs.into_inner().unwrap();
+
+    let unsafe_links = unsafe_links.into_inner();
+    if unsafe_links > 0 {
+        eprintln!("Warning: skipped {unsafe_links} symlinks pointing outside {}", root.display());
+    }
+
+    // Followed links are seen as what they point to, down to their metadata
+    let stat = |path: &Path| match options.symlinks {
+        SymlinkMode::Follow => fs::metadata(path),
+        _ => fs::symlink_metadata(path),
+    };
+
+    let scan_start = SystemTime::now();
+    let index = if options.use_index && !options.rehash {
+        HashIndex::load(root).unwrap_or_else(|e| {
+            eprintln!("Warning: ignoring hash index for {}: {e}", root.display());
+            HashIndex::new()
+        })
+    } else {
+        HashIndex::new()
+    };
 
     // Hash files in parallel
-    let file_metas: Vec<Result<FileMeta>> = file_paths
+    let file_metas: Vec<Result<(FileMeta, IndexKey)>> = file_paths
         .par_iter()
-        .map(|path| {
-            let metadata = fs::metadata(path)?;
+        .map(|(path, is_symlink)| {
+            let metadata = if *is_symlink {
+                fs::symlink_metadata(path)?
+            } else {
+                stat(path)?
+            };
             let size = metadata.len();
             let mtime = metadata.modified()?;
+            let key = IndexKey::from_metadata(&metadata);
+            let symlink_target = if *is_symlink {
+                Some(fs::read_link(path)?)
+            } else {
+                None
+            };
 
-            // Get permissions on Unix systems
+            // Get permissions on Unix systems (link modes are meaningless)
             #[cfg(unix)]
             let permissions = {
                 use std::os::unix::fs::PermissionsExt;
-                Some(metadata.permissions().mode())
+                symlink_target.is_none().then(|| metadata.permissions().mode())
             };
             #[cfg(not(unix))]
             let permissions = None;
 
-            // Compute content hash using streaming
-            let mut hasher = Hasher::new();
-            hasher.hash_file(path)?;
-            let hash = hasher.finalize();
-
             // Make path relative to root
             let rel_path = path
                 .strip_prefix(root)
@@ -209,23 +548,45 @@ pub fn scan_directory_with_excludes(
                 })?
                 .to_path_buf();
 
-            Ok(FileMeta {
+            // Compute content hash using streaming, unless the index vouches for it
+            let hash = match (&symlink_target, index.lookup(&rel_path, &key)) {
+                (Some(target), _) => Some(hash_bytes(target.as_os_str().as_encoded_bytes())),
+                (None, Some(hash)) => Some(hash.clone()),
+                (None, None) if options.quick => None,
+                (None, None) => {
+                    let mut hasher = Hasher::new();
+                    hasher.hash_file(path)?;
+                    Some(hasher.finalize())
+                },
+            };
+
+            let meta = FileMeta {
                 path: rel_path,
                 size,
                 mtime,
                 hash,
                 permissions,
-            })
+                symlink_target,
+            };
+            Ok((meta, key))
         })
         .collect();
 
     // Collect results, logging errors but not failing the entire scan
     let mut successful_files = Vec::new();
+    let mut new_index = HashIndex::new();
     let mut error_count = 0;
 
     for result in file_metas {
         match result {
-            Ok(meta) => successful_files.push(meta),
+            Ok((meta, key)) => {
+                if let (true, None, Some(hash)) =
+                    (options.save_index, &meta.symlink_target, &meta.hash)
+                {
+                    new_index.insert(meta.path.clone(), key, hash.clone(), scan_start);
+                }
+                successful_files.push(meta);
+            },
             Err(e) => {
                 error_count += 1;
                 eprintln!("Warning: Failed to process file: {e}");
@@ -237,13 +598,60 @@ pub fn scan_directory_with_excludes(
         eprintln!("Warning: {error_count} files could not be processed");
     }
 
+    let mut successful_dirs = Vec::with_capacity(dir_paths.len());
+    for path in &dir_paths {
+        let meta = stat(path).and_then(|metadata| {
+            let rel_path = path.strip_prefix(root).unwrap_or(path).to_path_buf();
+            DirMeta::from_metadata(rel_path, &metadata)
+        });
+        match meta {
+            Ok(meta) => successful_dirs.push(meta),
+            Err(e) => eprintln!("Warning: Failed to process directory {}: {e}", path.display()),
+        }
+    }
+
+    if options.save_index {
+        if let Err(e) = new_index.save(root) {
+            eprintln!("Warning: failed to write hash index for {}: {e}", root.display());
+        }
+    }
+
     Ok(ScanResult {
         root: root.to_path_buf(),
         files: successful_files,
+        dirs: successful_dirs,
+        excluded: filter.counts(),
         scan_time: SystemTime::now(),
     })
 }
 
+/// Whether a symlink's target stays inside the scanned tree
+///
+/// Judged lexically: absolute targets are unsafe, and `..` components may not
+/// climb above `root` from the link's own directory.
+fn is_safe_symlink(root: &Path, link: &Path) -> bool {
+    let Ok(target) = fs::read_link(link) else {
+        return false;
+    };
+    let Some(parent) = link.parent().and_then(|p| p.strip_prefix(root).ok()) else {
+        return false;
+    };
+
+    let mut depth = parent.components().count();
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {},
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None => return false,
+            },
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
 /// Compare two scan results and identify differences
 ///
 /// This function performs intelligent diff computation with rename detection:
@@ -251,6 +659,8 @@ pub fn scan_directory_with_excludes(
 /// 2. Identify added/removed/modified files
 /// 3. Detect renames by matching content hashes
 /// 4. Use path similarity as fallback for ambiguous renames
+/// 5. Turn new files whose content is already in the destination into local copies
+/// 6. Collapse renames that share a directory prefix into directory moves
 ///
 /// # Performance
 ///
@@ -258,51 +668,87 @@ pub fn scan_directory_with_excludes(
 /// - O(1) lookups for most operations
 /// - Rename detection is O(n*m) worst case but typically O(n) with hash matching
 pub fn diff_scans(source: &ScanResult, dest: &ScanResult) -> Result<DiffResult> {
+    diff_scans_with_options(source, dest, &DiffOptions::default())
+}
+
+/// Compare two scan results with protect rules applied
+///
+/// Removed files and directories matching `options.protect` are moved to
+/// [`DiffResult::protected`] (files) or dropped (directories), so no sync
+/// will delete them.
+pub fn diff_scans_with_options(
+    source: &ScanResult,
+    dest: &ScanResult,
+    options: &DiffOptions,
+) -> Result<DiffResult> {
+    // Quick scans leave hashes out; fill in only those the comparison needs
+    let (source_files, dest_files) = fill_missing_hashes(source, dest)?;
+
+    // Protected destination paths stay put: never moved, nor copied from
+    let is_protected = |path: &Path, is_dir| {
+        !options.protect.is_empty()
+            && matches!(
+                options.protect.matches_path(path, is_dir),
+                Some((FilterAction::Include, _))
+            )
+    };
+
     // O(n) lookups via hash maps
     let source_by_path: HashMap<&PathBuf, &FileMeta> =
-        HashMap::from_iter(source.files.iter().map(|f| (&f.path, f)));
+        HashMap::from_iter(source_files.iter().map(|f| (&f.path, f)));
     let dest_by_path: HashMap<&PathBuf, &FileMeta> =
-        HashMap::from_iter(dest.files.iter().map(|f| (&f.path, f)));
+        HashMap::from_iter(dest_files.iter().map(|f| (&f.path, f)));
 
     let mut source_by_hash: HashMap<&ContentHash, Vec<&FileMeta>> =
-        HashMap::with_capacity(source.files.len());
-    for file in &source.files {
-        source_by_hash
-            .entry(&file.hash)
-            .or_insert_with(|| Vec::with_capacity(2))
-            .push(file);
+        HashMap::with_capacity(source_files.len());
+    for file in source_files.iter().filter(|f| f.symlink_target.is_none()) {
+        if let Some(hash) = &file.hash {
+            source_by_hash.entry(hash).or_insert_with(|| Vec::with_capacity(2)).push(file);
+        }
     }
 
     let mut dest_by_hash: HashMap<&ContentHash, Vec<&FileMeta>> =
-        HashMap::with_capacity(dest.files.len());
-    for file in &dest.files {
-        dest_by_hash
-            .entry(&file.hash)
-            .or_insert_with(|| Vec::with_capacity(2))
-            .push(file);
+        HashMap::with_capacity(dest_files.len());
+    // Links are only ever compared by path, never moved or copied by content
+    for file in dest_files
+        .iter()
+        .filter(|f| f.symlink_target.is_none() && !is_protected(&f.path, false))
+    {
+        if let Some(hash) = &file.hash {
+            dest_by_hash.entry(hash).or_insert_with(|| Vec::with_capacity(2)).push(file);
+        }
     }
 
-    let mut added = Vec::with_capacity(source.files.len() / 10);
-    let mut removed = Vec::with_capacity(dest.files.len() / 10);
-    let mut modified = Vec::with_capacity(source.files.len() / 20);
-    let mut renamed = Vec::with_capacity(source.files.len() / 50);
-    let mut processed_dest_paths = HashSet::with_capacity(dest.files.len());
+    let mut added = Vec::with_capacity(source_files.len() / 10);
+    let mut removed = Vec::with_capacity(dest_files.len() / 10);
+    let mut modified = Vec::with_capacity(source_files.len() / 20);
+    let mut renamed = Vec::with_capacity(source_files.len() / 50);
+    let mut copied = Vec::new();
+    let mut processed_dest_paths = HashSet::with_capacity(dest_files.len());
 
-    for source_file in &source.files {
+    for source_file in source_files.iter() {
         if let Some(dest_file) = dest_by_path.get(&source_file.path) {
-            if source_file.hash != dest_file.hash {
+            if content_differs(source_file, dest_file) {
                 modified.push(source_file.clone());
             }
             processed_dest_paths.insert(&dest_file.path);
         } else {
             // Not at same path - check if renamed or new
-            if let Some(dest_files_with_hash) = dest_by_hash.get(&source_file.hash) {
+            if let Some(dest_files_with_hash) = source_file
+                .hash
+                .as_ref()
+                .filter(|_| source_file.symlink_target.is_none())
+                .and_then(|hash| dest_by_hash.get(hash))
+            {
                 // Same content exists - find best path match
                 let mut best_match: Option<&FileMeta> = None;
                 let mut best_score = 0.0;
 
                 for candidate in dest_files_with_hash {
-                    if processed_dest_paths.contains(&candidate.path) {
+                    // Files still present at the same path in source are not free to move
+                    if processed_dest_paths.contains(&candidate.path)
+                        || source_by_path.contains_key(&candidate.path)
+                    {
                         continue;
                     }
 
@@ -317,7 +763,8 @@ pub fn diff_scans(source: &ScanResult, dest: &ScanResult) -> Result<DiffResult>
                     renamed.push(((*matched_dest).clone(), source_file.clone()));
                     processed_dest_paths.insert(&matched_dest.path);
                 } else {
-                    added.push(source_file.clone());
+                    // Every candidate is spoken for, but the bytes are still local
+                    copied.push(((*dest_files_with_hash[0]).clone(), source_file.clone()));
                 }
             } else {
                 added.push(source_file.clone());
@@ -326,7 +773,7 @@ pub fn diff_scans(source: &ScanResult, dest: &ScanResult) -> Result<DiffResult>
     }
 
     // Find removed files (in dest but not in source, and not part of a rename)
-    for dest_file in &dest.files {
+    for dest_file in dest_files.iter() {
         if !source_by_path.contains_key(&dest_file.path)
             && !processed_dest_paths.contains(&dest_file.path)
         {
@@ -334,7 +781,343 @@ pub fn diff_scans(source: &ScanResult, dest: &ScanResult) -> Result<DiffResult>
         }
     }
 
-    Ok(DiffResult { added, removed, modified, renamed })
+    let mut diff = DiffResult {
+        added,
+        removed,
+        modified,
+        renamed,
+        copied,
+        renamed_dirs: Vec::new(),
+        added_dirs: Vec::new(),
+        removed_dirs: Vec::new(),
+        modified_dirs: Vec::new(),
+        protected: Vec::new(),
+        conflicts: Vec::new(),
+    };
+    detect_dir_renames(&mut diff, source, dest, &is_protected);
+    diff_dirs(&mut diff, source, dest);
+
+    if !options.protect.is_empty() {
+        let (protected, removed) = std::mem::take(&mut diff.removed)
+            .into_iter()
+            .partition(|f| is_protected(&f.path, false));
+        diff.protected = protected;
+        diff.removed = removed;
+        diff.removed_dirs.retain(|d| !is_protected(&d.path, true));
+    }
+
+    Ok(diff)
+}
+
+/// Whether two files at the same place need the source copied over
+///
+/// Hashes decide when both are known. Otherwise this is a quick check: any
+/// size or mtime difference counts as a change.
+pub(crate) fn content_differs(source: &FileMeta, dest: &FileMeta) -> bool {
+    if source.symlink_target != dest.symlink_target {
+        return true;
+    }
+    match (&source.hash, &dest.hash) {
+        (Some(a), Some(b)) => a != b,
+        _ => source.size != dest.size || source.mtime != dest.mtime,
+    }
+}
+
+/// A scan's files, copied only if some hashes had to be filled in
+type FileList<'a> = Cow<'a, [FileMeta]>;
+
+/// Hash the files a quick comparison can't settle by size and mtime alone
+///
+/// Those are same-path pairs of equal size but different mtime, plus new
+/// source files and any destination files that share a size with each
+/// other, since only those can turn out to be renames or local copies.
+/// Returns the scans' files unchanged (borrowed) when nothing is missing.
+fn fill_missing_hashes<'a>(
+    source: &'a ScanResult,
+    dest: &'a ScanResult,
+) -> Result<(FileList<'a>, FileList<'a>)> {
+    let complete = |files: &[FileMeta]| files.iter().all(|f| f.hash.is_some());
+    if complete(&source.files) && complete(&dest.files) {
+        return Ok((Cow::Borrowed(&source.files), Cow::Borrowed(&dest.files)));
+    }
+
+    let regular = |f: &&FileMeta| f.symlink_target.is_none();
+    let dest_by_path: HashMap<&Path, &FileMeta> =
+        dest.files.iter().map(|f| (f.path.as_path(), f)).collect();
+
+    let mut wanted_dest: HashSet<&Path> = HashSet::new();
+    let mut new_sizes: HashSet<u64> = HashSet::new();
+    let wanted_source: HashSet<&Path> = source
+        .files
+        .iter()
+        .filter(regular)
+        .filter(|f| match dest_by_path.get(f.path.as_path()) {
+            Some(d) if d.symlink_target.is_none() && d.size == f.size && d.mtime != f.mtime => {
+                wanted_dest.insert(d.path.as_path());
+                true
+            },
+            Some(_) => false,
+            None => {
+                new_sizes.insert(f.size);
+                true
+            },
+        })
+        .map(|f| f.path.as_path())
+        .collect();
+
+    let dest_sizes: HashSet<u64> = dest.files.iter().filter(regular).map(|f| f.size).collect();
+    wanted_dest.extend(
+        dest.files
+            .iter()
+            .filter(regular)
+            .filter(|f| new_sizes.contains(&f.size))
+            .map(|f| f.path.as_path()),
+    );
+
+    let fill = |scan: &ScanResult, wanted: &(dyn Fn(&FileMeta) -> bool + Sync)| {
+        scan.files
+            .par_iter()
+            .map(|file| {
+                let mut file = file.clone();
+                if file.hash.is_none() && wanted(&file) {
+                    let hash = hash_file(&scan.root.join(&file.path)).map_err(|e| {
+                        SyncError::HashError(format!("{}: {e}", file.path.display()))
+                    })?;
+                    file.hash = Some(hash);
+                }
+                Ok(file)
+            })
+            .collect::<Result<Vec<FileMeta>>>()
+    };
+
+    // New source files only matter if the destination has something that size
+    let source_files = fill(source, &|f| {
+        wanted_source.contains(f.path.as_path())
+            && (dest_by_path.contains_key(f.path.as_path()) || dest_sizes.contains(&f.size))
+    })?;
+    let dest_files = fill(dest, &|f| wanted_dest.contains(f.path.as_path()))?;
+
+    Ok((Cow::Owned(source_files), Cow::Owned(dest_files)))
+}
+
+/// Fewest same-suffix renames before a pattern is reported as a directory move
+const MIN_DIR_RENAME_FILES: usize = 2;
+
+/// Collapse file renames into directory moves where a whole subtree moved
+///
+/// A pair (A, B) qualifies when most files under A in the destination reappear
+/// under B with the same relative suffix, A no longer exists in the source, B
+/// does not exist in the destination yet, and every other file under A still
+/// has a sensible fate once it sits under B (renamed elsewhere, or now a
+/// modification of the source file at the same place). A with anything
+/// `is_protected` in or under it never moves. Shallow pairs win over nested
+/// ones. Accepted moves rewrite the rest of the diff to post-move paths.
+fn detect_dir_renames(
+    diff: &mut DiffResult,
+    source: &ScanResult,
+    dest: &ScanResult,
+    is_protected: &dyn Fn(&Path, bool) -> bool,
+) {
+    // Count renames per (old dir, new dir) pair sharing the same suffix
+    let mut pair_counts: HashMap<(PathBuf, PathBuf), usize> = HashMap::new();
+    for (old, new) in &diff.renamed {
+        let (mut a, mut b) = (old.path.as_path(), new.path.as_path());
+        while a.file_name().is_some() && a.file_name() == b.file_name() {
+            match (a.parent(), b.parent()) {
+                (Some(pa), Some(pb))
+                    if !pa.as_os_str().is_empty() && !pb.as_os_str().is_empty() =>
+                {
+                    a = pa;
+                    b = pb;
+                },
+                _ => break,
+            }
+            *pair_counts.entry((a.to_path_buf(), b.to_path_buf())).or_insert(0) += 1;
+        }
+    }
+
+    let mut candidates: Vec<((PathBuf, PathBuf), usize)> = pair_counts
+        .into_iter()
+        .filter(|(_, count)| *count >= MIN_DIR_RENAME_FILES)
+        .collect();
+    if candidates.is_empty() {
+        return;
+    }
+    candidates.sort_by(|((a1, _), c1), ((a2, _), c2)| {
+        a1.components().count().cmp(&a2.components().count()).then(c2.cmp(c1))
+    });
+
+    let source_dirs: HashSet<&Path> = source
+        .files
+        .iter()
+        .flat_map(|f| parent_dirs(&f.path))
+        .chain(source.dirs.iter().map(|d| d.path.as_path()))
+        .collect();
+    let dest_dirs: HashSet<&Path> = dest
+        .files
+        .iter()
+        .flat_map(|f| parent_dirs(&f.path))
+        .chain(dest.dirs.iter().map(|d| d.path.as_path()))
+        .collect();
+    let dest_paths: HashSet<&Path> = dest.files.iter().map(|f| f.path.as_path()).collect();
+
+    let mut accepted: Vec<DirRename> = Vec::new();
+
+    for ((a, b), count) in candidates {
+        let related = |p: &Path, q: &Path| p.starts_with(q) || q.starts_with(p);
+        if related(&a, &b)
+            || accepted.iter().any(|d| {
+                related(&a, &d.old)
+                    || related(&a, &d.new)
+                    || related(&b, &d.old)
+                    || related(&b, &d.new)
+            })
+        {
+            continue;
+        }
+        if source_dirs.contains(a.as_path())
+            || dest_dirs.contains(b.as_path())
+            || dest_paths.contains(b.as_path())
+        {
+            continue;
+        }
+
+        let dest_under: Vec<&FileMeta> =
+            dest.files.iter().filter(|f| f.path.starts_with(&a)).collect();
+        if count * 2 <= dest_under.len() {
+            continue;
+        }
+        if is_protected(&a, true)
+            || dest_under.iter().any(|f| is_protected(&f.path, false))
+            || dest.dirs.iter().any(|d| d.path.starts_with(&a) && is_protected(&d.path, true))
+        {
+            continue;
+        }
+
+        let rebase = |p: &Path| b.join(p.strip_prefix(&a).unwrap());
+
+        // Nothing else may be written where a moved file will land
+        let absorbed = |old: &FileMeta, new: &FileMeta| {
+            old.path.starts_with(&a) && rebase(&old.path) == new.path
+        };
+        let targets: HashSet<&Path> = diff
+            .renamed
+            .iter()
+            .filter(|(old, new)| !absorbed(old, new))
+            .map(|(_, new)| new.path.as_path())
+            .chain(diff.copied.iter().map(|(_, new)| new.path.as_path()))
+            .collect();
+        if dest_under.iter().any(|f| targets.contains(rebase(&f.path).as_path())) {
+            continue;
+        }
+
+        // Leftovers must become modifications, never strays under the new name
+        let added_paths: HashSet<&Path> = diff.added.iter().map(|f| f.path.as_path()).collect();
+        if diff
+            .removed
+            .iter()
+            .filter(|f| f.path.starts_with(&a))
+            .any(|f| !added_paths.contains(rebase(&f.path).as_path()))
+        {
+            continue;
+        }
+
+        // Accepted: rewrite the diff in terms of the moved tree
+        let mut files = Vec::new();
+        let mut renamed = Vec::with_capacity(diff.renamed.len());
+        for (mut old, new) in std::mem::take(&mut diff.renamed) {
+            if absorbed(&old, &new) {
+                files.push((old, new));
+            } else {
+                if old.path.starts_with(&a) {
+                    old.path = rebase(&old.path);
+                }
+                renamed.push((old, new));
+            }
+        }
+        diff.renamed = renamed;
+
+        for (existing, _) in &mut diff.copied {
+            if existing.path.starts_with(&a) {
+                existing.path = rebase(&existing.path);
+            }
+        }
+
+        let mut moved_leftovers: HashMap<PathBuf, FileMeta> = HashMap::new();
+        diff.removed.retain(|f| {
+            if f.path.starts_with(&a) {
+                moved_leftovers.insert(rebase(&f.path), f.clone());
+                false
+            } else {
+                true
+            }
+        });
+        let mut added = Vec::with_capacity(diff.added.len());
+        for file in std::mem::take(&mut diff.added) {
+            match moved_leftovers.get(&file.path) {
+                Some(old) if !content_differs(old, &file) => {},
+                Some(_) => diff.modified.push(file),
+                None => added.push(file),
+            }
+        }
+        diff.added = added;
+
+        accepted.push(DirRename { old: a, new: b, files });
+    }
+
+    diff.renamed_dirs = accepted;
+}
+
+/// Compare directory entries against the destination as it will be after
+/// any directory moves
+fn diff_dirs(diff: &mut DiffResult, source: &ScanResult, dest: &ScanResult) {
+    let rebase = |path: &Path| {
+        for dir in &diff.renamed_dirs {
+            if let Ok(suffix) = path.strip_prefix(&dir.old) {
+                return if suffix.as_os_str().is_empty() {
+                    dir.new.clone()
+                } else {
+                    dir.new.join(suffix)
+                };
+            }
+        }
+        path.to_path_buf()
+    };
+
+    let dest_by_path: HashMap<PathBuf, &DirMeta> =
+        dest.dirs.iter().map(|d| (rebase(&d.path), d)).collect();
+    let source_paths: HashSet<&Path> = source.dirs.iter().map(|d| d.path.as_path()).collect();
+
+    let mut added_dirs = Vec::new();
+    let mut modified_dirs = Vec::new();
+    for dir in &source.dirs {
+        match dest_by_path.get(&dir.path) {
+            None => added_dirs.push(dir.clone()),
+            Some(existing) => {
+                if existing.mtime != dir.mtime || existing.permissions != dir.permissions {
+                    modified_dirs.push(dir.clone());
+                }
+            },
+        }
+    }
+
+    let mut removed_dirs = Vec::new();
+    for (path, dir) in dest_by_path {
+        if !source_paths.contains(path.as_path()) {
+            removed_dirs.push(DirMeta { path, ..dir.clone() });
+        }
+    }
+
+    removed_dirs.sort_by(|a, b| a.path.cmp(&b.path));
+
+    diff.added_dirs = added_dirs;
+    diff.removed_dirs = removed_dirs;
+    diff.modified_dirs = modified_dirs;
+}
+
+/// All proper ancestors of a relative path, excluding the empty root
+fn parent_dirs(path: &Path) -> impl Iterator<Item = &Path> {
+    path.ancestors().skip(1).filter(|p| !p.as_os_str().is_empty())
 }
 
 /// Compute path similarity score between two paths (0.0 to 1.0)
@@ -397,6 +1180,23 @@ fn simple_string_similarity(s1: &str, s2: &str) -> f64 {
 /// the destination is never left in a corrupted state. A journal tracks
 /// in-progress operations for crash recovery.
 ///
+/// Directory moves run first, then directory creation, local copies (while
+/// every destination file they read from is still in place), source copies,
+/// renames and deletes. Emptied directories are pruned at the end, and
+/// directory mtimes and permissions restored once nothing else will touch them.
+///
+/// Large plain copies record resume points in the journal. If one is cut
+/// short, the next sync checks the partial file against its recorded hash and
+/// appends the rest instead of starting over, as long as the source file is
+/// unchanged.
+///
+/// The whole plan is saved in the destination as well, with each step marked
+/// as it completes, so a sync that fails or is killed can be finished with
+/// [`resume_sync`].
+///
+/// With `options.transactional`, a failure instead rolls the destination back
+/// to how it was before the sync.
+///
 /// # Arguments
 ///
 /// * `source_root` - Source directory root
@@ -409,11 +1209,94 @@ pub fn sync_changes(
     diff: &DiffResult,
     options: &SyncOptions,
 ) -> Result<()> {
+    apply_plan(source_root, dest_root, diff, options, None)
+}
+
+/// Put back a move that failed halfway, before reporting `e`
+///
+/// A directory moved file by file across filesystems can fail with part of
+/// it already moved. Its pending `MOVE` in `journal` says how to undo that;
+/// if undoing fails as well, the journal stays for the next run's recovery.
+fn abort_moves(journal: SyncJournal, temp_dir: &Path, e: anyhow::Error) -> anyhow::Error {
+    let undone = journal.abort(temp_dir);
+    let _ = fs::remove_dir_all(temp_dir);
+    match undone {
+        Ok(()) => e,
+        Err(undo) => e.context(format!("Undoing it failed too ({undo}); the next sync retries")),
+    }
+}
+
+/// Finish a sync that a previous run left unfinished in `dest_root`
+///
+/// Runs the steps of `saved` not yet marked done, without rescanning either
+/// tree. Refuses if a source file still to be copied has changed since.
+pub fn resume_sync(dest_root: &Path, saved: &SavedPlan) -> Result<()> {
+    saved.check_source()?;
+    let plan = &saved.plan;
+    apply_plan(&plan.source_root, dest_root, &plan.diff, &plan.options, Some(&saved.done))
+}
+
+/// Apply `diff`, skipping the steps in `done` when resuming a saved plan
+fn apply_plan(
+    source_root: &Path,
+    dest_root: &Path,
+    diff: &DiffResult,
+    options: &SyncOptions,
+    done: Option<&HashSet<Step>>,
+) -> Result<()> {
+    let mut undo_started = false;
+    let result = run_plan(source_root, dest_root, diff, options, done, &mut undo_started);
+    match result {
+        Err(e) if undo_started => match rollback(dest_root) {
+            Ok(_) => Err(e.context("Sync failed and was rolled back")),
+            Err(undo) => Err(e.context(format!("Sync failed, and so did rolling back: {undo:#}"))),
+        },
+        result => result,
+    }
+}
+
+fn run_plan(
+    source_root: &Path,
+    dest_root: &Path,
+    diff: &DiffResult,
+    options: &SyncOptions,
+    done: Option<&HashSet<Step>>,
+    undo_started: &mut bool,
+) -> Result<()> {
+    // A new sync makes an earlier one impossible to roll back, unless that
+    // one never finished. Directories are snapshotted before jan's own files
+    // change their mtimes.
+    let resuming = done.is_some();
+    if !resuming && undo_state(dest_root) == UndoState::Interrupted {
+        anyhow::bail!(
+            "An interrupted transactional sync into {} must be resumed or rolled back first",
+            dest_root.display(),
+        );
+    }
+    let undo_log = if options.transactional {
+        let undo = if resuming {
+            UndoLog::reopen(dest_root)
+        } else {
+            let dirs = touched_dirs(dest_root, diff);
+            UndoLog::create(dest_root, dirs.iter().map(PathBuf::as_path))
+        }
+        .map_err(|e| anyhow::anyhow!("Can't start undo log: {e}"))?;
+        *undo_started = true;
+        Some(undo)
+    } else {
+        let area = dest_root.join(JAN_ROLLBACK_DIR);
+        if area.exists() {
+            fs::remove_dir_all(&area)
+                .map_err(|e| anyhow::anyhow!("Can't remove {}: {e}", area.display()))?;
+        }
+        None
+    };
+
     let temp_dir = dest_root.join(JAN_TEMP_DIR);
     let journal_path = dest_root.join(JAN_JOURNAL_FILE);
 
-    // Recover from any prior interrupted sync
-    SyncJournal::recover(&journal_path, &temp_dir)
+    // Recover from any prior interrupted sync, keeping copies that can resume
+    let partials = SyncJournal::recover(&journal_path, &temp_dir)
         .map_err(|e| anyhow::anyhow!("Journal recovery failed: {e}"))?;
 
     fs::create_dir_all(&temp_dir)
@@ -422,45 +1305,157 @@ pub fn sync_changes(
     let journal = SyncJournal::create(journal_path)
         .map_err(|e| anyhow::anyhow!("Can't create journal: {e}"))?;
 
+    let undo = undo_log.as_ref();
+    let make_dirs = |dir: &Path| match undo {
+        Some(undo) => undo.create_dir_all(dir),
+        None => fs::create_dir_all(dir),
+    };
+    let before_write = |path: &Path| match undo {
+        Some(undo) => undo
+            .before_write(path)
+            .map_err(|e| anyhow::anyhow!("Can't back up {}: {e}", path.display())),
+        None => Ok(()),
+    };
+    let before_move = |old: &Path, new: &Path| match undo {
+        Some(undo) => undo
+            .record_move(old, new)
+            .map_err(|e| anyhow::anyhow!("Undo log write failed: {e}")),
+        None => Ok(()),
+    };
+
+    // Whatever the sync displaces goes to the backup directory, if any, at
+    // its path relative to the destination
+    let backup_dir = options.backup_dir.as_ref().map(|dir| dest_root.join(dir));
+    let backup_path = |path: &Path| {
+        let dir = backup_dir.as_ref()?;
+        Some(dir.join(path.strip_prefix(dest_root).unwrap_or(path)))
+    };
+    let make_backup_dirs = |kept: &Path| match kept.parent() {
+        Some(parent) => make_dirs(parent)
+            .map_err(|e| anyhow::anyhow!("Can't create {}: {}", parent.display(), e)),
+        None => Ok(()),
+    };
+    // Files about to be overwritten are linked there first
+    let keep_old = |path: &Path| {
+        let Some(kept) = backup_path(path) else {
+            return Ok(());
+        };
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) if !metadata.is_dir() => metadata,
+            _ => return Ok(()),
+        };
+        make_backup_dirs(&kept)?;
+        before_write(&kept)?;
+        link_or_copy(path, &kept, &metadata)
+            .or_else(|e| match e.kind() {
+                // Kept by an earlier run of this sync
+                io::ErrorKind::AlreadyExists => Ok(()),
+                _ => Err(e),
+            })
+            .map_err(|e| anyhow::anyhow!("Can't back up {}: {e}", path.display()))
+    };
+
+    let plan = match done {
+        Some(_) => PlanLog::reopen(dest_root),
+        None => PlanLog::create(
+            dest_root,
+            &SyncPlan {
+                source_root: fs::canonicalize(source_root)
+                    .unwrap_or_else(|_| source_root.to_path_buf()),
+                diff: diff.clone(),
+                options: options.clone(),
+            },
+        ),
+    }
+    .map_err(|e| anyhow::anyhow!("Can't save sync plan: {e}"))?;
+    let is_done = |step: Step| done.is_some_and(|done| done.contains(&step));
+    let mark = |step: Step| plan.mark(step).map_err(|e| anyhow::anyhow!("Plan write failed: {e}"));
+
     // Track directories that were written to for batch dir fsync
     let written_dirs: std::sync::Mutex<HashSet<PathBuf>> = std::sync::Mutex::new(HashSet::new());
 
-    // Copy added + modified files
-    let files_to_copy: Vec<&FileMeta> = diff.added.iter().chain(diff.modified.iter()).collect();
+    // Directory moves: one rename per moved subtree
+    let dir_result = diff.renamed_dirs.iter().enumerate().try_for_each(|(i, dir)| {
+        let old_path = dest_root.join(&dir.old);
+        let new_path = dest_root.join(&dir.new);
 
-    let copy_result = files_to_copy.par_iter().try_for_each(|file| {
-        let source_path = source_root.join(&file.path);
-        let dest_path = dest_root.join(&file.path);
+        if let Some(parent) = new_path.parent() {
+            make_dirs(parent)
+                .map_err(|e| anyhow::anyhow!("Can't create {}: {}", parent.display(), e))?;
+            written_dirs.lock().unwrap().insert(parent.to_path_buf());
+        }
+        if let Some(parent) = old_path.parent() {
+            written_dirs.lock().unwrap().insert(parent.to_path_buf());
+        }
+        if is_done(Step::DirMove(i)) || (resuming && already_moved(&old_path, &new_path)) {
+            return Ok(());
+        }
+
+        before_move(&old_path, &new_path)?;
+        journal
+            .record_pending("MOVE", &old_path, &new_path)
+            .map_err(|e| anyhow::anyhow!("Journal write failed: {e}"))?;
+
+        move_dir(&old_path, &new_path, &temp_dir).map_err(|e| {
+            anyhow::anyhow!(
+                "Directory move failed ({} -> {}): {e}",
+                old_path.display(),
+                new_path.display(),
+            )
+        })?;
+
+        journal
+            .record_committed("MOVE", &old_path, &new_path)
+            .map_err(|e| anyhow::anyhow!("Journal write failed: {e}"))?;
+        mark(Step::DirMove(i))?;
+
+        Ok::<_, anyhow::Error>(())
+    });
+
+    if let Err(e) = dir_result {
+        return Err(abort_moves(journal, &temp_dir, e));
+    }
+
+    // New directories, so empty ones exist too
+    let mkdir_result = diff.added_dirs.iter().try_for_each(|dir| {
+        let path = dest_root.join(&dir.path);
+        make_dirs(&path).map_err(|e| anyhow::anyhow!("Can't create {}: {}", path.display(), e))?;
+        if let Some(parent) = path.parent() {
+            written_dirs.lock().unwrap().insert(parent.to_path_buf());
+        }
+        Ok::<_, anyhow::Error>(())
+    });
+
+    if let Err(e) = mkdir_result {
+        let _ = journal.remove();
+        let _ = fs::remove_dir_all(&temp_dir);
+        return Err(e);
+    }
+
+    // Local copies: duplicate content already in the destination
+    let local_result = diff.copied.par_iter().enumerate().try_for_each(|(i, (existing, new))| {
+        let existing_path = dest_root.join(&existing.path);
+        let dest_path = dest_root.join(&new.path);
 
         if let Some(parent) = dest_path.parent() {
-            fs::create_dir_all(parent)
+            make_dirs(parent)
                 .map_err(|e| anyhow::anyhow!("Can't create {}: {}", parent.display(), e))?;
             written_dirs.lock().unwrap().insert(parent.to_path_buf());
         }
+        if is_done(Step::LocalCopy(i)) {
+            return Ok(());
+        }
 
+        before_write(&dest_path)?;
         let temp_path = generate_temp_path(&temp_dir);
         journal
             .record_pending("COPY", &temp_path, &dest_path)
             .map_err(|e| anyhow::anyhow!("Journal write failed: {e}"))?;
 
-        let expected_hash = if options.verify_after_copy {
-            Some(&file.hash)
-        } else {
-            None
-        };
-
-        atomic_copy_file_with_metadata(
-            &source_path,
-            &dest_path,
-            &temp_path,
-            options.preserve_timestamps,
-            options.verify_after_copy,
-            expected_hash,
-        )
-        .map_err(|e| {
+        local_copy(&existing_path, &dest_path, &temp_path, new, options).map_err(|e| {
             anyhow::anyhow!(
-                "Copy failed ({} -> {}): {e}",
-                source_path.display(),
+                "Local copy failed ({} -> {}): {e}",
+                existing_path.display(),
                 dest_path.display(),
             )
         })?;
@@ -468,85 +1463,362 @@ pub fn sync_changes(
         journal
             .record_committed("COPY", &temp_path, &dest_path)
             .map_err(|e| anyhow::anyhow!("Journal write failed: {e}"))?;
+        mark(Step::LocalCopy(i))?;
 
         Ok::<_, anyhow::Error>(())
     });
 
-    if let Err(e) = copy_result {
+    if let Err(e) = local_result {
+        let _ = journal.remove();
+        let _ = fs::remove_dir_all(&temp_dir);
+        return Err(e);
+    }
+
+    // Conflicting destination files that are kept move aside before being
+    // replaced. The new name was free when planned, so if it is taken now an
+    // earlier run got this far.
+    let aside_result = diff.conflicts.iter().try_for_each(|conflict| {
+        let Resolution::KeepBoth(aside) = &conflict.resolution else {
+            return Ok(());
+        };
+        let dest_path = dest_root.join(&conflict.dest.path);
+        let aside_path = dest_root.join(aside);
+        if let Some(parent) = aside_path.parent() {
+            written_dirs.lock().unwrap().insert(parent.to_path_buf());
+        }
+        if fs::symlink_metadata(&aside_path).is_ok() {
+            return Ok(());
+        }
+
+        before_move(&dest_path, &aside_path)?;
+        journal
+            .record_pending("MOVE", &dest_path, &aside_path)
+            .map_err(|e| anyhow::anyhow!("Journal write failed: {e}"))?;
+        move_file(&dest_path, &aside_path, &generate_temp_path(&temp_dir), false, None).map_err(
+            |e| anyhow::anyhow!("Can't move conflicting {} aside: {e}", dest_path.display(),),
+        )?;
+        journal
+            .record_committed("MOVE", &dest_path, &aside_path)
+            .map_err(|e| anyhow::anyhow!("Journal write failed: {e}"))?;
+        Ok::<_, anyhow::Error>(())
+    });
+
+    if let Err(e) = aside_result {
         let _ = journal.remove();
         let _ = fs::remove_dir_all(&temp_dir);
         return Err(e);
     }
 
-    // Renames: atomic copy to new location, remove old
-    let rename_result = diff.renamed.par_iter().try_for_each(|(old, new)| {
-        let source_path = source_root.join(&new.path);
+    // Copy added + modified files; links are recreated from their target
+    let files_to_copy = diff.files_to_copy();
+
+    // Chunks are collected after the directory moves, so every location is current
+    let chunk_config = ChunkerConfig::default();
+    let chunk_store = if options.chunks && files_to_copy.iter().any(|f| f.symlink_target.is_none())
+    {
+        let quick = ScanOptions { quick: true, ..ScanOptions::default() };
+        // Without the store files are still written, just without reuse
+        let existing: Vec<PathBuf> = match scan_directory_with_options(dest_root, &quick) {
+            Ok(scan) => scan
+                .files
+                .into_iter()
+                .filter(|f| f.symlink_target.is_none() && f.size > 0)
+                .map(|f| f.path)
+                .collect(),
+            Err(e) => {
+                eprintln!("Warning: can't list {} for chunk reuse: {e}", dest_root.display());
+                Vec::new()
+            },
+        };
+        Some(ChunkStore::build(dest_root, &existing, &chunk_config))
+    } else {
+        None
+    };
+
+    let partials = Mutex::new(partials);
+    // Set once a failed copy may leave a temp file worth resuming
+    let resumable_left = AtomicBool::new(false);
+
+    let copy_result = files_to_copy.par_iter().enumerate().try_for_each(|(i, file)| {
+        let source_path = source_root.join(&file.path);
+        let dest_path = dest_root.join(&file.path);
+
+        if let Some(parent) = dest_path.parent() {
+            make_dirs(parent)
+                .map_err(|e| anyhow::anyhow!("Can't create {}: {}", parent.display(), e))?;
+            written_dirs.lock().unwrap().insert(parent.to_path_buf());
+        }
+        if is_done(Step::Copy(i)) {
+            return Ok(());
+        }
+        before_write(&dest_path)?;
+        keep_old(&dest_path)?;
+
+        // Only files replacing an older regular file have blocks worth reusing
+        let delta = options.delta
+            && file.symlink_target.is_none()
+            && fs::symlink_metadata(&dest_path).is_ok_and(|m| m.is_file());
+        let op = match (&file.symlink_target, delta, &chunk_store) {
+            (Some(_), _, _) => "SYMLINK",
+            (None, true, _) => "DELTA",
+            (None, false, Some(_)) => "CHUNK",
+            (None, false, None) => "COPY",
+        };
+        let partial = match op {
+            "COPY" => take_partial(&partials, &dest_path, file),
+            _ => None,
+        };
+        let temp_path = match &partial {
+            Some(partial) => partial.temp_path.clone(),
+            None => generate_temp_path(&temp_dir),
+        };
+        journal
+            .record_pending(op, &temp_path, &dest_path)
+            .map_err(|e| anyhow::anyhow!("Journal write failed: {e}"))?;
+        if let Some(partial) = &partial {
+            // Carry the resume point over, so another interruption keeps the file
+            journal
+                .record_progress(&temp_path, &dest_path, &partial.point)
+                .map_err(|e| anyhow::anyhow!("Journal write failed: {e}"))?;
+            resumable_left.store(true, Ordering::Relaxed);
+        }
+
+        if let Some(target) = &file.symlink_target {
+            atomic_symlink(target, &dest_path, &temp_path).map_err(|e| {
+                anyhow::anyhow!(
+                    "Symlink failed ({} -> {}): {e}",
+                    dest_path.display(),
+                    target.display(),
+                )
+            })?;
+        } else if delta {
+            atomic_delta_copy(
+                &source_path,
+                &dest_path,
+                &temp_path,
+                options.preserve_timestamps,
+                file.hash.as_ref(),
+            )
+            .map_err(|e| {
+                anyhow::anyhow!(
+                    "Delta copy failed ({} -> {}): {e}",
+                    source_path.display(),
+                    dest_path.display(),
+                )
+            })?;
+        } else if let Some(store) = &chunk_store {
+            atomic_chunked_copy(
+                &source_path,
+                &dest_path,
+                &temp_path,
+                store,
+                &chunk_config,
+                options.preserve_timestamps,
+                file.hash.as_ref(),
+            )
+            .map_err(|e| {
+                anyhow::anyhow!(
+                    "Chunked copy failed ({} -> {}): {e}",
+                    source_path.display(),
+                    dest_path.display(),
+                )
+            })?;
+        } else {
+            // A quick scan may have left this unhashed; verify against the source as it is now
+            let source_hash =
+                match (&file.hash, options.verify_after_copy) {
+                    (None, true) => Some(hash_file(&source_path).map_err(|e| {
+                        anyhow::anyhow!("Can't hash {}: {e}", source_path.display())
+                    })?),
+                    _ => None,
+                };
+            let expected_hash = if options.verify_after_copy {
+                file.hash.as_ref().or(source_hash.as_ref())
+            } else {
+                None
+            };
+
+            if partial.is_some() || file.size >= RESUME_CHECKPOINT {
+                let mut checkpoint = |point: &ResumePoint| {
+                    resumable_left.store(true, Ordering::Relaxed);
+                    journal.record_progress(&temp_path, &dest_path, point)
+                };
+                atomic_resumable_copy(
+                    &source_path,
+                    &dest_path,
+                    &temp_path,
+                    partial.as_ref().map(|p| &p.point),
+                    options.preserve_timestamps,
+                    expected_hash,
+                    &mut checkpoint,
+                )
+                .map(|_| ())
+            } else {
+                atomic_copy_file_with_metadata(
+                    &source_path,
+                    &dest_path,
+                    &temp_path,
+                    options.preserve_timestamps,
+                    options.verify_after_copy,
+                    expected_hash,
+                )
+            }
+            .map_err(|e| {
+                anyhow::anyhow!(
+                    "Copy failed ({} -> {}): {e}",
+                    source_path.display(),
+                    dest_path.display(),
+                )
+            })?;
+        }
+
+        journal
+            .record_committed(op, &temp_path, &dest_path)
+            .map_err(|e| anyhow::anyhow!("Journal write failed: {e}"))?;
+        mark(Step::Copy(i))?;
+
+        Ok::<_, anyhow::Error>(())
+    });
+
+    if let Err(e) = copy_result {
+        if resumable_left.load(Ordering::Relaxed) {
+            // Everything before the copies is committed, so recovery only has
+            // temp files to sort out, and the resumable ones survive it
+            let _ = journal.keep();
+        } else {
+            let _ = journal.remove();
+            let _ = fs::remove_dir_all(&temp_dir);
+        }
+        return Err(e);
+    }
+
+    // Renames: move the existing destination file, never re-read the source
+    let rename_result = diff.renamed.par_iter().enumerate().try_for_each(|(i, (old, new))| {
+        let old_dest_path = dest_root.join(&old.path);
         let dest_path = dest_root.join(&new.path);
 
         if let Some(parent) = dest_path.parent() {
-            fs::create_dir_all(parent)
+            make_dirs(parent)
                 .map_err(|e| anyhow::anyhow!("Can't create {}: {}", parent.display(), e))?;
             written_dirs.lock().unwrap().insert(parent.to_path_buf());
         }
+        if let Some(parent) = old_dest_path.parent() {
+            written_dirs.lock().unwrap().insert(parent.to_path_buf());
+        }
+        if is_done(Step::Rename(i)) || (resuming && already_moved(&old_dest_path, &dest_path)) {
+            return Ok(());
+        }
 
-        let temp_path = generate_temp_path(&temp_dir);
+        before_move(&old_dest_path, &dest_path)?;
         journal
-            .record_pending("RENAME", &temp_path, &dest_path)
+            .record_pending("MOVE", &old_dest_path, &dest_path)
             .map_err(|e| anyhow::anyhow!("Journal write failed: {e}"))?;
 
         let expected_hash = if options.verify_after_copy {
-            Some(&new.hash)
+            new.hash.as_ref()
         } else {
             None
         };
 
-        atomic_copy_file_with_metadata(
-            &source_path,
+        move_file(
+            &old_dest_path,
             &dest_path,
-            &temp_path,
-            options.preserve_timestamps,
+            &generate_temp_path(&temp_dir),
             options.verify_after_copy,
             expected_hash,
         )
         .map_err(|e| {
             anyhow::anyhow!(
                 "Rename failed ({} -> {}): {e}",
-                source_path.display(),
+                old_dest_path.display(),
                 dest_path.display(),
             )
         })?;
 
-        let old_dest_path = dest_root.join(&old.path);
-        remove_file_safe(&old_dest_path)
-            .map_err(|e| anyhow::anyhow!("Can't remove {}: {}", old_dest_path.display(), e))?;
-
         journal
-            .record_committed("RENAME", &temp_path, &dest_path)
+            .record_committed("MOVE", &old_dest_path, &dest_path)
             .map_err(|e| anyhow::anyhow!("Journal write failed: {e}"))?;
+        mark(Step::Rename(i))?;
 
         Ok::<_, anyhow::Error>(())
     });
 
     if let Err(e) = rename_result {
-        let _ = journal.remove();
-        let _ = fs::remove_dir_all(&temp_dir);
-        return Err(e);
+        return Err(abort_moves(journal, &temp_dir, e));
     }
 
-    // Deletes
+    // Deletes, sparing anything the filter keeps out of the transfer
+    let dest_filter = TreeFilter::new(dest_root, options.filter.clone(), false, &[]);
     if options.delete_removed {
         for file in &diff.removed {
             let dest_path = dest_root.join(&file.path);
-            remove_file_safe(&dest_path)
+            if dest_filter.is_path_excluded(&dest_path, false) {
+                continue;
+            }
+            if let Some(kept) = backup_path(&dest_path) {
+                make_backup_dirs(&kept)?;
+                before_move(&dest_path, &kept)?;
+                match move_file(&dest_path, &kept, &generate_temp_path(&temp_dir), false, None) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => anyhow::bail!(
+                        "Can't move {} to {}: {e}",
+                        dest_path.display(),
+                        kept.display(),
+                    ),
+                    _ => {},
+                }
+            } else {
+                match undo {
+                    Some(undo) => undo.remove_file(&dest_path),
+                    None => remove_file_safe(&dest_path),
+                }
                 .map_err(|e| anyhow::anyhow!("Can't delete {}: {}", dest_path.display(), e))?;
+            }
             if let Some(parent) = dest_path.parent() {
                 written_dirs.lock().unwrap().insert(parent.to_path_buf());
             }
         }
     }
 
-    // Batch directory fsync — persist all renames
+    // Prune directories the source no longer has, deepest first. Without
+    // `delete_removed`, only those vacated by this sync's moves go.
+    let vacated: HashSet<&Path> = diff
+        .renamed
+        .iter()
+        .map(|(old, _)| old.path.as_path())
+        .chain(diff.renamed_dirs.iter().map(|d| d.old.as_path()))
+        .flat_map(parent_dirs)
+        .collect();
+    let mut prunable: Vec<&DirMeta> = diff
+        .removed_dirs
+        .iter()
+        .filter(|d| options.delete_removed || vacated.contains(d.path.as_path()))
+        .collect();
+    prunable.sort_by_key(|d| std::cmp::Reverse(d.path.components().count()));
+    for dir in prunable {
+        let path = dest_root.join(&dir.path);
+        if dest_filter.is_path_excluded(&path, true) {
+            continue;
+        }
+        // Directories still holding excluded or kept files stay put
+        let removed = match undo {
+            Some(undo) => undo.remove_dir(&path),
+            None => fs::remove_dir(&path),
+        };
+        if removed.is_ok() {
+            let mut written = written_dirs.lock().unwrap();
+            written.remove(&path);
+            if let Some(parent) = path.parent() {
+                written.insert(parent.to_path_buf());
+            }
+        }
+    }
+
     let dirs = written_dirs.into_inner().unwrap();
+
+    // Directory metadata last, once no more children will be written
+    restore_dir_metadata(source_root, dest_root, diff, &dirs, options);
+
+    // Batch directory fsync — persist all renames
     for dir in &dirs {
         if let Err(e) = fsync_directory(dir) {
             eprintln!("Warning: directory fsync failed for {}: {e}", dir.display());
@@ -554,12 +1826,162 @@ pub fn sync_changes(
     }
 
     // Clean exit
+    if let Some(undo) = undo_log {
+        undo.finish().map_err(|e| anyhow::anyhow!("Undo log write failed: {e}"))?;
+    }
+    let _ = plan.remove();
     let _ = journal.remove();
     let _ = fs::remove_dir_all(&temp_dir);
 
     Ok(())
 }
 
+/// Existing destination directories whose metadata applying `diff` may change
+fn touched_dirs(dest_root: &Path, diff: &DiffResult) -> HashSet<PathBuf> {
+    let paths = diff
+        .added
+        .iter()
+        .chain(&diff.modified)
+        .chain(&diff.removed)
+        .map(|f| f.path.as_path())
+        .chain(
+            diff.renamed
+                .iter()
+                .flat_map(|(old, new)| [old.path.as_path(), new.path.as_path()]),
+        )
+        .chain(diff.copied.iter().map(|(_, new)| new.path.as_path()))
+        .chain(diff.conflicts.iter().flat_map(|c| match &c.resolution {
+            Resolution::Source => vec![c.source.path.as_path()],
+            Resolution::KeepBoth(aside) => vec![c.source.path.as_path(), aside.as_path()],
+            _ => vec![],
+        }))
+        .chain(diff.renamed_dirs.iter().flat_map(|d| [d.old.as_path(), d.new.as_path()]))
+        .chain(diff.added_dirs.iter().chain(&diff.removed_dirs).map(|d| d.path.as_path()));
+    let mut dirs: HashSet<PathBuf> = paths
+        .flat_map(|path| path.ancestors().skip(1))
+        .map(|dir| dest_root.join(dir))
+        .collect();
+    dirs.extend(diff.modified_dirs.iter().map(|d| dest_root.join(&d.path)));
+    dirs
+}
+
+/// Whether a move finished in an earlier run that died before marking it
+fn already_moved(old: &Path, new: &Path) -> bool {
+    fs::symlink_metadata(old).is_err() && fs::symlink_metadata(new).is_ok()
+}
+
+/// Give destination directories the mtime and permissions of their source
+///
+/// Covers new and modified directories from the diff, plus every directory
+/// whose contents this sync changed (taken from a fresh stat of the source).
+/// Failures are warnings: the data is already in place.
+fn restore_dir_metadata(
+    source_root: &Path,
+    dest_root: &Path,
+    diff: &DiffResult,
+    written_dirs: &HashSet<PathBuf>,
+    options: &SyncOptions,
+) {
+    let mut targets: HashMap<PathBuf, Option<DirMeta>> = HashMap::new();
+    for dir in written_dirs {
+        if let Ok(rel) = dir.strip_prefix(dest_root) {
+            if !rel.as_os_str().is_empty() {
+                targets.insert(rel.to_path_buf(), None);
+            }
+        }
+    }
+    for dir in diff.added_dirs.iter().chain(diff.modified_dirs.iter()) {
+        targets.insert(dir.path.clone(), Some(dir.clone()));
+    }
+
+    for (rel, meta) in targets {
+        let meta = match meta {
+            Some(meta) => meta,
+            None => match fs::symlink_metadata(source_root.join(&rel)) {
+                Ok(m) if m.is_dir() => match DirMeta::from_metadata(rel, &m) {
+                    Ok(meta) => meta,
+                    Err(_) => continue,
+                },
+                _ => continue,
+            },
+        };
+
+        let path = dest_root.join(&meta.path);
+        if options.preserve_timestamps {
+            if let Err(e) = set_file_mtime(&path, meta.mtime) {
+                eprintln!("Warning: can't set mtime of {}: {e}", path.display());
+            }
+        }
+        #[cfg(unix)]
+        if let Some(mode) = meta.permissions {
+            use std::os::unix::fs::PermissionsExt;
+            if let Err(e) = fs::set_permissions(&path, fs::Permissions::from_mode(mode)) {
+                eprintln!("Warning: can't set permissions of {}: {e}", path.display());
+            }
+        }
+    }
+}
+
+/// Claim the partial copy left for `dest_path`, if it was of this same source file
+fn take_partial(
+    partials: &Mutex<Vec<PartialCopy>>,
+    dest_path: &Path,
+    file: &FileMeta,
+) -> Option<PartialCopy> {
+    let mut partials = partials.lock().unwrap();
+    let index = partials.iter().position(|partial| {
+        let point = &partial.point;
+        let same_source = match (&point.source_hash, &file.hash) {
+            (Some(recorded), Some(current)) => recorded == current,
+            _ => point.source_mtime == file.mtime,
+        };
+        partial.final_path == dest_path && point.source_size == file.size && same_source
+    })?;
+    Some(partials.swap_remove(index))
+}
+
+/// Duplicate a destination file to a new destination path per `options.local_copy`
+fn local_copy(
+    existing: &Path,
+    dest: &Path,
+    temp_path: &Path,
+    file: &FileMeta,
+    options: &SyncOptions,
+) -> std::io::Result<()> {
+    match options.local_copy {
+        // Shares the inode, so there is no separate metadata to set
+        LocalCopyMode::Hardlink => return atomic_hard_link(existing, dest, temp_path),
+        LocalCopyMode::Reflink if atomic_reflink(existing, dest, temp_path)? => {},
+        LocalCopyMode::Copy | LocalCopyMode::Reflink => {
+            let expected_hash = if options.verify_after_copy {
+                file.hash.as_ref()
+            } else {
+                None
+            };
+            atomic_copy_file_with_metadata(
+                existing,
+                dest,
+                temp_path,
+                false,
+                options.verify_after_copy,
+                expected_hash,
+            )?;
+        },
+    }
+
+    // Metadata comes from the source file, not the duplicate we copied from
+    if options.preserve_timestamps {
+        set_file_mtime(dest, file.mtime)?;
+    }
+    #[cfg(unix)]
+    if let Some(mode) = file.permissions {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dest, fs::Permissions::from_mode(mode))?;
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
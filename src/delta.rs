@@ -0,0 +1,426 @@
+//! Rsync-style delta transfer: rebuild a file from blocks of its older copy
+//!
+//! The receiver cuts its existing file into fixed-size blocks and records a
+//! weak rolling checksum and a strong hash of each ([`Signature`]). The sender
+//! slides a block-sized window over the new file a byte at a time; wherever
+//! the weak checksum and then the strong hash match, it sends a reference to
+//! the old block instead of the bytes ([`DeltaOp`]).
+//!
+//! The rolling checksum updates in O(1) per byte, so the whole source is
+//! matched in one streaming pass with memory bounded by the signature.
+
+use crate::hash::{hash_bytes, ContentHash, Hasher};
+use crate::io::{read_full, set_file_mtime, AtomicWriter};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Smallest block size; below this the signature outweighs the savings
+pub const MIN_BLOCK_SIZE: usize = 700;
+
+/// Largest block size, reached at files of about 16 GB
+pub const MAX_BLOCK_SIZE: usize = 128 * 1024;
+
+// Literal runs are flushed at this size so a rewritten file never sits in memory
+const MAX_LITERAL: usize = 256 * 1024;
+
+const READ_SIZE: usize = 256 * 1024;
+
+/// Block size for a basis file of `len` bytes: about its square root, like rsync
+pub fn block_size_for(len: u64) -> usize {
+    let root = (len as f64).sqrt() as usize;
+    (root & !7).clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE)
+}
+
+/// Adler-style checksum that can slide over its input one byte at a time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RollingChecksum {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl RollingChecksum {
+    /// Checksum of a whole window
+    pub fn new(window: &[u8]) -> Self {
+        let mut sum = Self {
+            len: window.len() as u32,
+            ..Self::default()
+        };
+        for &byte in window {
+            sum.a = sum.a.wrapping_add(byte as u32);
+            sum.b = sum.b.wrapping_add(sum.a);
+        }
+        sum
+    }
+
+    /// Slide the window forward: drop `out` from the front, append `incoming`
+    pub fn roll(&mut self, out: u8, incoming: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(incoming as u32);
+        self.b = self.b.wrapping_sub(self.len.wrapping_mul(out as u32)).wrapping_add(self.a);
+    }
+
+    pub fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+/// Checksums of one block of the basis file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockSignature {
+    pub weak: u32,
+    pub strong: ContentHash,
+}
+
+/// Block checksums of a basis file, all the sender needs to build a delta
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub block_size: usize,
+    pub file_len: u64,
+    pub blocks: Vec<BlockSignature>,
+}
+
+/// One instruction for rebuilding the new file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeltaOp {
+    /// Copy block `index` of the basis file
+    Block(u32),
+    /// Write these bytes as they are
+    Literal(Vec<u8>),
+}
+
+/// How much of a rebuilt file came from the basis and how much was sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeltaStats {
+    pub matched: u64,
+    pub literal: u64,
+}
+
+impl Signature {
+    /// Signature of `reader` in blocks of `block_size`; the last may be short
+    pub fn compute(mut reader: impl Read, block_size: usize) -> io::Result<Self> {
+        let mut blocks = Vec::new();
+        let mut file_len = 0;
+        let mut block = vec![0u8; block_size];
+        loop {
+            let n = read_full(&mut reader, &mut block)?;
+            if n == 0 {
+                break;
+            }
+            blocks.push(BlockSignature {
+                weak: RollingChecksum::new(&block[..n]).digest(),
+                strong: hash_bytes(&block[..n]),
+            });
+            file_len += n as u64;
+            if n < block_size {
+                break;
+            }
+        }
+        Ok(Self { block_size, file_len, blocks })
+    }
+
+    /// Signature of a file, with the block size picked from its length
+    pub fn of_file(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let block_size = block_size_for(file.metadata()?.len());
+        Self::compute(BufReader::with_capacity(READ_SIZE, file), block_size)
+    }
+
+    /// Offset and length of block `index` in the basis file
+    pub fn block_range(&self, index: u32) -> Option<(u64, usize)> {
+        if index as usize >= self.blocks.len() {
+            return None;
+        }
+        let offset = index as u64 * self.block_size as u64;
+        let len = (self.file_len - offset).min(self.block_size as u64) as usize;
+        Some((offset, len))
+    }
+
+    /// Stream `source` against this signature, handing each op to `emit`
+    ///
+    /// Adjacent literal bytes are batched into runs of up to 256 KB.
+    pub fn diff(
+        &self,
+        mut source: impl Read,
+        mut emit: impl FnMut(DeltaOp) -> io::Result<()>,
+    ) -> io::Result<DeltaStats> {
+        let block_size = self.block_size;
+        let mut by_weak: HashMap<u32, Vec<u32>> = HashMap::with_capacity(self.blocks.len());
+        for (i, block) in self.blocks.iter().enumerate() {
+            // A short tail block can only match at the very end; handled below
+            if self.block_range(i as u32).map(|(_, len)| len) == Some(block_size) {
+                by_weak.entry(block.weak).or_default().push(i as u32);
+            }
+        }
+
+        let mut stats = DeltaStats::default();
+        let mut buf: Vec<u8> = Vec::with_capacity(READ_SIZE + block_size);
+        let mut eof = false;
+        // Window is buf[pos..pos + block_size]; buf[literal..pos] is unsent
+        let mut pos = 0;
+        let mut literal = 0;
+        let mut rolling: Option<RollingChecksum> = None;
+
+        let flush = |bytes: &[u8],
+                     stats: &mut DeltaStats,
+                     emit: &mut dyn FnMut(DeltaOp) -> io::Result<()>|
+         -> io::Result<()> {
+            if !bytes.is_empty() {
+                stats.literal += bytes.len() as u64;
+                emit(DeltaOp::Literal(bytes.to_vec()))?;
+            }
+            Ok(())
+        };
+
+        loop {
+            fill(&mut source, &mut buf, pos + block_size + 1, &mut eof)?;
+            if buf.len() - pos < block_size {
+                break;
+            }
+
+            let window = &buf[pos..pos + block_size];
+            let weak = rolling.get_or_insert_with(|| RollingChecksum::new(window)).digest();
+            let matched = by_weak.get(&weak).and_then(|candidates| {
+                let strong = hash_bytes(window);
+                candidates.iter().copied().find(|&i| self.blocks[i as usize].strong == strong)
+            });
+
+            if let Some(index) = matched {
+                flush(&buf[literal..pos], &mut stats, &mut emit)?;
+                emit(DeltaOp::Block(index))?;
+                stats.matched += block_size as u64;
+                pos += block_size;
+                literal = pos;
+                rolling = None;
+            } else if buf.len() > pos + block_size {
+                if let Some(sum) = rolling.as_mut() {
+                    sum.roll(buf[pos], buf[pos + block_size]);
+                }
+                pos += 1;
+                if pos - literal >= MAX_LITERAL {
+                    flush(&buf[literal..pos], &mut stats, &mut emit)?;
+                    literal = pos;
+                }
+            } else {
+                // Last full window at end of input: nothing left to slide into
+                break;
+            }
+
+            if literal >= READ_SIZE {
+                buf.drain(..literal);
+                pos -= literal;
+                literal = 0;
+            }
+        }
+
+        // The tail is shorter than a block; it may still be the old short last block
+        let tail = &buf[pos..];
+        let tail_block = self.blocks.len().checked_sub(1).filter(|&last| {
+            let block = &self.blocks[last];
+            self.block_range(last as u32).map(|(_, len)| len) == Some(tail.len())
+                && tail.len() < block_size
+                && RollingChecksum::new(tail).digest() == block.weak
+                && hash_bytes(tail) == block.strong
+        });
+        match tail_block {
+            Some(last) if !tail.is_empty() => {
+                flush(&buf[literal..pos], &mut stats, &mut emit)?;
+                emit(DeltaOp::Block(last as u32))?;
+                stats.matched += tail.len() as u64;
+            },
+            _ => flush(&buf[literal..], &mut stats, &mut emit)?,
+        }
+
+        Ok(stats)
+    }
+}
+
+/// Write the bytes of one op, reading blocks from `basis`
+pub fn apply_op(
+    signature: &Signature,
+    basis: &mut File,
+    op: &DeltaOp,
+    out: &mut AtomicWriter,
+    scratch: &mut Vec<u8>,
+) -> io::Result<()> {
+    match op {
+        DeltaOp::Literal(bytes) => out.write(bytes),
+        DeltaOp::Block(index) => {
+            let (offset, len) = signature.block_range(*index).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Delta refers to block {index} past the end of the basis file"),
+                )
+            })?;
+            scratch.resize(len, 0);
+            basis.seek(SeekFrom::Start(offset))?;
+            basis.read_exact(scratch)?;
+            out.write(scratch)
+        },
+    }
+}
+
+/// Replace `dest` with `source`, reusing whatever blocks of `dest` still match
+///
+/// The new file is assembled at `temp_path` and committed atomically. Its
+/// hash is always checked, against `expected_hash` if given or else against
+/// the source as read, so a bad block match can never reach `dest`.
+pub fn atomic_delta_copy(
+    source: &Path,
+    dest: &Path,
+    temp_path: &Path,
+    preserve_timestamps: bool,
+    expected_hash: Option<&ContentHash>,
+) -> io::Result<DeltaStats> {
+    let metadata = fs::metadata(source)?;
+    let signature = Signature::of_file(dest)?;
+    let mut basis = File::open(dest)?;
+
+    let mut writer = AtomicWriter::new(temp_path.to_path_buf(), dest.to_path_buf(), true)?;
+    let mut source_reader = HashingReader {
+        inner: BufReader::with_capacity(READ_SIZE, File::open(source)?),
+        hasher: Hasher::new(),
+    };
+    let mut scratch = Vec::with_capacity(signature.block_size);
+    let stats = signature.diff(&mut source_reader, |op| {
+        apply_op(&signature, &mut basis, &op, &mut writer, &mut scratch)
+    })?;
+
+    let source_hash = source_reader.hasher.finalize();
+    writer.commit(Some(expected_hash.unwrap_or(&source_hash)))?;
+
+    if preserve_timestamps {
+        set_file_mtime(dest, metadata.modified()?)?;
+    }
+
+    #[cfg(unix)]
+    {
+        crate::io::set_file_permissions(dest, &metadata)?;
+    }
+
+    Ok(stats)
+}
+
+/// Reader that hashes everything read through it
+struct HashingReader<R> {
+    inner: R,
+    hasher: Hasher,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Grow `buf` to at least `want` bytes unless the input runs out first
+fn fill(reader: &mut impl Read, buf: &mut Vec<u8>, want: usize, eof: &mut bool) -> io::Result<()> {
+    while !*eof && buf.len() < want {
+        let start = buf.len();
+        buf.resize(start + READ_SIZE.max(want - start), 0);
+        let requested = buf.len() - start;
+        let n = read_full(reader, &mut buf[start..])?;
+        buf.truncate(start + n);
+        *eof = n < requested;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn rebuild(old: &[u8], new: &[u8], block_size: usize) -> (Vec<u8>, DeltaStats) {
+        let signature = Signature::compute(old, block_size).unwrap();
+        let mut out = Vec::new();
+        let stats = signature
+            .diff(new, |op| {
+                match op {
+                    DeltaOp::Literal(bytes) => out.extend_from_slice(&bytes),
+                    DeltaOp::Block(i) => {
+                        let (offset, len) = signature.block_range(i).unwrap();
+                        out.extend_from_slice(&old[offset as usize..offset as usize + len]);
+                    },
+                }
+                Ok(())
+            })
+            .unwrap();
+        (out, stats)
+    }
+
+    /// Deterministic pseudo-random bytes, so matches only come from real repeats
+    pub(crate) fn sample(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rolling_matches_fresh_checksum() {
+        let data = sample(100, 1);
+        let mut sum = RollingChecksum::new(&data[..16]);
+        for start in 1..=data.len() - 16 {
+            sum.roll(data[start - 1], data[start + 15]);
+            assert_eq!(sum.digest(), RollingChecksum::new(&data[start..start + 16]).digest());
+        }
+    }
+
+    #[test]
+    fn test_append_reuses_every_old_block() {
+        let old = sample(10_000, 2);
+        let mut new = old.clone();
+        new.extend_from_slice(b"one more line");
+
+        let (out, stats) = rebuild(&old, &new, 1000);
+        assert_eq!(out, new);
+        assert_eq!(stats.matched, 10_000);
+        assert_eq!(stats.literal, 13);
+    }
+
+    #[test]
+    fn test_insertion_realigns_after_edit() {
+        let old = sample(8_000, 3);
+        let mut new = old.clone();
+        new.splice(4_100..4_100, b"inserted".iter().copied());
+
+        let (out, stats) = rebuild(&old, &new, 1000);
+        assert_eq!(out, new);
+        // Only the block the insertion landed in is resent
+        assert_eq!(stats.matched, 7_000);
+    }
+
+    #[test]
+    fn test_short_tail_block_matches() {
+        let old = sample(2_500, 4);
+        let mut new = sample(300, 5);
+        new.extend_from_slice(&old);
+
+        let (out, stats) = rebuild(&old, &new, 1000);
+        assert_eq!(out, new);
+        assert_eq!(stats.matched, 2_500);
+    }
+
+    #[test]
+    fn test_unrelated_and_empty_inputs() {
+        let (out, stats) = rebuild(&sample(3_000, 6), &sample(3_000, 7), 1000);
+        assert_eq!(out, sample(3_000, 7));
+        assert_eq!(stats.matched, 0);
+
+        assert_eq!(rebuild(b"", b"fresh", 1000).0, b"fresh");
+        assert_eq!(rebuild(b"stale", b"", 1000).0, b"");
+    }
+
+    #[test]
+    fn test_block_size_scales_with_length() {
+        assert_eq!(block_size_for(0), MIN_BLOCK_SIZE);
+        assert_eq!(block_size_for(100 * 1024 * 1024), 10240);
+        assert_eq!(block_size_for(1 << 40), MAX_BLOCK_SIZE);
+    }
+}
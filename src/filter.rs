@@ -0,0 +1,564 @@
+//! Path filtering for scans and deletes
+//!
+//! [`Filter`] is an ordered list of rsync-style include/exclude rules where the
+//! first matching rule decides. [`TreeFilter`] applies a `Filter` to one tree,
+//! expanding per-directory merge files and, when asked, ignore files such as
+//! `.gitignore`, and counts every exclusion against the source that caused it.
+//!
+//! Nothing is ignored unless asked for: `.gitignore` files (and
+//! `.git/info/exclude`) only apply with `respect_gitignore`, and custom ignore
+//! files such as `.janignore` only when named.
+//!
+//! ## Patterns
+//!
+//! - A leading `/` anchors the pattern to the tree root (or to the directory
+//!   of the merge file it came from); otherwise it matches the end of the path
+//! - A trailing `/` only matches directories
+//! - `*` stays within one path component, `**` crosses components
+//! - `dir/***` matches `dir` itself and everything below it
+//!
+//! An excluded directory is not descended into, so including files deep in a
+//! tree also needs their parent directories included (e.g. `+ */`).
+
+use ahash::{HashMap, HashMapExt};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+/// Label under which `--exclude` patterns are counted
+pub const EXCLUDE_SOURCE: &str = "--exclude";
+
+/// Label under which `--include` patterns are reported
+pub const INCLUDE_SOURCE: &str = "--include";
+
+const GITIGNORE: &str = ".gitignore";
+const GIT_INFO_EXCLUDE: &str = ".git/info/exclude";
+
+/// Errors building a filter
+#[derive(Error, Debug)]
+pub enum FilterError {
+    #[error("Invalid pattern '{pattern}': {source}")]
+    InvalidPattern { pattern: String, source: globset::Error },
+
+    #[error("Invalid filter rule '{0}'")]
+    InvalidRule(String),
+
+    #[error("Can't read filter file {path}: {source}")]
+    Io { path: PathBuf, source: std::io::Error },
+}
+
+/// What a matching rule does with a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterAction {
+    Include,
+    Exclude,
+}
+
+#[derive(Debug, Clone)]
+struct PatternRule {
+    action: FilterAction,
+    matcher: GlobSet,
+    dir_only: bool,
+    /// The pattern as written, so the rule can be rebuilt elsewhere
+    pattern: String,
+    /// Where the rule came from, for exclusion counts
+    source: String,
+}
+
+impl PatternRule {
+    fn is_match(&self, rel_path: &Path, is_dir: bool) -> bool {
+        (is_dir || !self.dir_only) && self.matcher.is_match(rel_path)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Rule {
+    Pattern(PatternRule),
+    /// Read rules from this file name in every directory, see [`TreeFilter`]
+    DirMerge(String),
+}
+
+/// Ordered include/exclude rules; the first rule matching a path wins
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    rules: Vec<Rule>,
+}
+
+impl Filter {
+    /// Create a filter with no rules (everything is included)
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a rule including paths that match `pattern`
+    pub fn include(&mut self, pattern: &str, source: &str) -> Result<(), FilterError> {
+        self.push_pattern(FilterAction::Include, pattern, source)
+    }
+
+    /// Append a rule excluding paths that match `pattern`
+    pub fn exclude(&mut self, pattern: &str, source: &str) -> Result<(), FilterError> {
+        self.push_pattern(FilterAction::Exclude, pattern, source)
+    }
+
+    /// Append a per-directory merge rule: every directory's `name` file is read
+    /// for further rules, which apply below that directory at this position
+    pub fn dir_merge(&mut self, name: &str) {
+        self.rules.push(Rule::DirMerge(name.to_string()));
+    }
+
+    /// Append one rule in filter-file syntax
+    ///
+    /// Accepts `+ PATTERN`, `- PATTERN`, `include PATTERN`, `exclude PATTERN`,
+    /// `: NAME` and `dir-merge NAME`. Blank lines and lines starting with `#`
+    /// or `;` are ignored.
+    pub fn add_line(&mut self, line: &str, source: &str) -> Result<(), FilterError> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with(';') {
+            return Ok(());
+        }
+
+        let (keyword, argument) =
+            line.split_once(' ').ok_or_else(|| FilterError::InvalidRule(line.to_string()))?;
+        match keyword {
+            "+" | "include" => self.include(argument, source),
+            "-" | "exclude" => self.exclude(argument, source),
+            ":" | "dir-merge" => {
+                self.dir_merge(argument);
+                Ok(())
+            },
+            _ => Err(FilterError::InvalidRule(line.to_string())),
+        }
+    }
+
+    /// Append every rule in a filter file
+    pub fn add_file(&mut self, path: &Path) -> Result<(), FilterError> {
+        let content = fs::read_to_string(path)
+            .map_err(|source| FilterError::Io { path: path.to_path_buf(), source })?;
+        let label = path.display().to_string();
+        for line in content.lines() {
+            self.add_line(line, &label)?;
+        }
+        Ok(())
+    }
+
+    /// Every rule in filter-file syntax with its source, in order
+    ///
+    /// Feeding these back through [`Filter::add_line`] rebuilds the filter.
+    pub fn to_lines(&self) -> Vec<(String, String)> {
+        self.rules
+            .iter()
+            .map(|rule| match rule {
+                Rule::Pattern(p) => {
+                    let sign = match p.action {
+                        FilterAction::Include => '+',
+                        FilterAction::Exclude => '-',
+                    };
+                    (format!("{sign} {}", p.pattern), p.source.clone())
+                },
+                Rule::DirMerge(name) => (format!(": {name}"), String::new()),
+            })
+            .collect()
+    }
+
+    /// Whether there are no rules at all
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// First pattern rule matching `rel_path`, with its source
+    ///
+    /// Merge rules need a tree to read from and are skipped here; use
+    /// [`TreeFilter`] to honour them.
+    pub fn matches(&self, rel_path: &Path, is_dir: bool) -> Option<(FilterAction, &str)> {
+        self.rules.iter().find_map(|rule| match rule {
+            Rule::Pattern(p) if p.is_match(rel_path, is_dir) => Some((p.action, p.source.as_str())),
+            _ => None,
+        })
+    }
+
+    /// Like [`Filter::matches`], but a decision on any parent directory wins,
+    /// as it would during a walk that never descends into excluded dirs
+    pub fn matches_path(&self, rel_path: &Path, is_dir: bool) -> Option<(FilterAction, &str)> {
+        let mut parents: Vec<&Path> =
+            rel_path.ancestors().skip(1).filter(|p| !p.as_os_str().is_empty()).collect();
+        parents.reverse();
+
+        parents
+            .into_iter()
+            .find_map(|dir| self.matches(dir, true))
+            .or_else(|| self.matches(rel_path, is_dir))
+    }
+
+    fn push_pattern(
+        &mut self,
+        action: FilterAction,
+        pattern: &str,
+        source: &str,
+    ) -> Result<(), FilterError> {
+        let (matcher, dir_only) = compile_pattern(pattern)?;
+        self.rules.push(Rule::Pattern(PatternRule {
+            action,
+            matcher,
+            dir_only,
+            pattern: pattern.to_string(),
+            source: source.to_string(),
+        }));
+        Ok(())
+    }
+}
+
+/// Translate an rsync-style pattern into globs over relative paths
+fn compile_pattern(pattern: &str) -> Result<(GlobSet, bool), FilterError> {
+    let invalid = |source| FilterError::InvalidPattern { pattern: pattern.to_string(), source };
+
+    let (body, dir_only) = match pattern.strip_suffix('/') {
+        Some(body) if !body.is_empty() => (body, true),
+        _ => (pattern, false),
+    };
+    let (body, anchored) = match body.strip_prefix('/') {
+        Some(body) => (body, true),
+        None => (body, false),
+    };
+    let (body, with_contents) = match body.strip_suffix("/***") {
+        Some(body) => (body, true),
+        None => (body, false),
+    };
+    let base = if anchored {
+        body.to_string()
+    } else {
+        format!("**/{body}")
+    };
+
+    let glob = |g: &str| GlobBuilder::new(g).literal_separator(true).build();
+    let mut builder = GlobSetBuilder::new();
+    builder.add(glob(&base).map_err(invalid)?);
+    if with_contents {
+        builder.add(glob(&format!("{base}/**")).map_err(invalid)?);
+    }
+    Ok((builder.build().map_err(invalid)?, dir_only))
+}
+
+/// Ignore files consulted in one directory: (source label, matcher)
+type DirMatchers = Arc<Vec<(String, Gitignore)>>;
+
+/// A [`Filter`] bound to one tree, plus optional ignore files
+///
+/// Merge and ignore files are loaded lazily per directory and cached, so one
+/// `TreeFilter` can be shared across the parallel walker's threads.
+#[derive(Debug)]
+pub struct TreeFilter {
+    root: PathBuf,
+    filter: Filter,
+    /// Per-directory ignore file names, highest precedence first
+    ignore_files: Vec<String>,
+    git_exclude: bool,
+    counts: Mutex<Vec<(String, usize)>>,
+    merges: Mutex<HashMap<(PathBuf, String), Arc<Filter>>>,
+    ignores: Mutex<HashMap<PathBuf, DirMatchers>>,
+}
+
+impl TreeFilter {
+    /// Apply `filter` to the tree at `root`
+    ///
+    /// Files named in `ignore_files` are honoured in every directory, taking
+    /// precedence over `.gitignore` when `respect_gitignore` is set. Ignore
+    /// files are only consulted for paths no filter rule matched.
+    pub fn new(
+        root: &Path,
+        filter: Filter,
+        respect_gitignore: bool,
+        ignore_files: &[String],
+    ) -> Self {
+        let mut ignore_files = ignore_files.to_vec();
+        if respect_gitignore {
+            ignore_files.push(GITIGNORE.to_string());
+        }
+
+        Self {
+            root: root.to_path_buf(),
+            filter,
+            ignore_files,
+            git_exclude: respect_gitignore,
+            counts: Mutex::new(Vec::new()),
+            merges: Mutex::new(HashMap::new()),
+            ignores: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether `path` (under the root) should be skipped, counting the source
+    ///
+    /// Assumes the parent directories were already let through, as they are
+    /// during a walk; see [`TreeFilter::is_path_excluded`] otherwise.
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        let Ok(rel_path) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        if rel_path.as_os_str().is_empty() {
+            return false;
+        }
+
+        match self.decide(rel_path, is_dir) {
+            Some((FilterAction::Exclude, source)) => {
+                self.count(&source);
+                return true;
+            },
+            Some((FilterAction::Include, _)) => return false,
+            None => {},
+        }
+
+        self.is_ignored(path, is_dir)
+    }
+
+    /// Like [`TreeFilter::is_excluded`], but also excluded when any parent
+    /// directory is, for checking single paths outside a walk
+    pub fn is_path_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        let Ok(rel_path) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        let mut parents: Vec<&Path> =
+            rel_path.ancestors().skip(1).filter(|p| !p.as_os_str().is_empty()).collect();
+        parents.reverse();
+
+        parents.into_iter().any(|dir| self.is_excluded(&self.root.join(dir), true))
+            || self.is_excluded(path, is_dir)
+    }
+
+    /// Paths excluded so far per source, in the order sources first matched
+    pub fn counts(&self) -> Vec<(String, usize)> {
+        self.counts.lock().unwrap().clone()
+    }
+
+    /// First filter rule matching `rel_path`, expanding merge files in place
+    fn decide(&self, rel_path: &Path, is_dir: bool) -> Option<(FilterAction, String)> {
+        for rule in &self.filter.rules {
+            match rule {
+                Rule::Pattern(p) => {
+                    if p.is_match(rel_path, is_dir) {
+                        return Some((p.action, p.source.clone()));
+                    }
+                },
+                Rule::DirMerge(name) => {
+                    // Deeper merge files take precedence over inherited ones
+                    for dir in rel_path.ancestors().skip(1) {
+                        let merged = self.merge_rules(dir, name);
+                        let sub_path = rel_path.strip_prefix(dir).unwrap_or(rel_path);
+                        if let Some((action, source)) = merged.matches(sub_path, is_dir) {
+                            return Some((action, source.to_string()));
+                        }
+                    }
+                },
+            }
+        }
+        None
+    }
+
+    /// Check ignore files from the path's own directory upwards, so deeper
+    /// files (and `!` re-includes in them) override shallower ones, as in git
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if self.ignore_files.is_empty() && !self.git_exclude {
+            return false;
+        }
+
+        let mut dir = path.parent();
+        while let Some(current) = dir {
+            if !current.starts_with(&self.root) {
+                break;
+            }
+            for (source, matcher) in self.ignore_matchers(current).iter() {
+                match matcher.matched(path, is_dir) {
+                    Match::Ignore(_) => {
+                        self.count(source);
+                        return true;
+                    },
+                    Match::Whitelist(_) => return false,
+                    Match::None => {},
+                }
+            }
+            dir = current.parent();
+        }
+        false
+    }
+
+    fn count(&self, source: &str) {
+        let mut counts = self.counts.lock().unwrap();
+        match counts.iter_mut().find(|(s, _)| s == source) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((source.to_string(), 1)),
+        }
+    }
+
+    fn merge_rules(&self, rel_dir: &Path, name: &str) -> Arc<Filter> {
+        let key = (rel_dir.to_path_buf(), name.to_string());
+        if let Some(filter) = self.merges.lock().unwrap().get(&key) {
+            return Arc::clone(filter);
+        }
+
+        let file = self.root.join(rel_dir).join(name);
+        let mut filter = Filter::new();
+        if file.is_file() {
+            let loaded = fs::read_to_string(&file)
+                .map_err(|source| FilterError::Io { path: file.clone(), source })
+                .and_then(|content| {
+                    content.lines().try_for_each(|line| filter.add_line(line, name))
+                });
+            if let Err(e) = loaded {
+                eprintln!("Warning: ignoring rest of {}: {e}", file.display());
+            }
+        }
+
+        let filter = Arc::new(filter);
+        self.merges.lock().unwrap().insert(key, Arc::clone(&filter));
+        filter
+    }
+
+    fn ignore_matchers(&self, dir: &Path) -> DirMatchers {
+        if let Some(matchers) = self.ignores.lock().unwrap().get(dir) {
+            return Arc::clone(matchers);
+        }
+
+        let mut matchers = Vec::new();
+        for name in &self.ignore_files {
+            let file = dir.join(name);
+            if file.is_file() {
+                matchers.push((name.clone(), load_ignore_file(dir, &file)));
+            }
+        }
+        if self.git_exclude {
+            let file = dir.join(GIT_INFO_EXCLUDE);
+            if file.is_file() {
+                matchers.push((GIT_INFO_EXCLUDE.to_string(), load_ignore_file(dir, &file)));
+            }
+        }
+
+        let matchers = Arc::new(matchers);
+        self.ignores.lock().unwrap().insert(dir.to_path_buf(), Arc::clone(&matchers));
+        matchers
+    }
+}
+
+/// Parse one ignore file, keeping whatever lines are valid
+fn load_ignore_file(dir: &Path, file: &Path) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(dir);
+    if let Some(e) = builder.add(file) {
+        eprintln!("Warning: {}: {e}", file.display());
+    }
+    builder.build().unwrap_or_else(|e| {
+        eprintln!("Warning: ignoring {}: {e}", file.display());
+        Gitignore::empty()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn rules(lines: &[&str]) -> Filter {
+        let mut filter = Filter::new();
+        for line in lines {
+            filter.add_line(line, "test").unwrap();
+        }
+        filter
+    }
+
+    #[test]
+    fn test_first_match_wins() {
+        let filter = rules(&["+ keep.log", "- *.log"]);
+        let action = |p: &str| filter.matches(Path::new(p), false).map(|(a, _)| a);
+
+        assert_eq!(action("keep.log"), Some(FilterAction::Include));
+        assert_eq!(action("deep/keep.log"), Some(FilterAction::Include));
+        assert_eq!(action("other.log"), Some(FilterAction::Exclude));
+        assert_eq!(action("notes.txt"), None);
+    }
+
+    #[test]
+    fn test_pattern_forms() {
+        let filter = rules(&["- /top.txt", "- build/", "- cache/***", "- a/*.o"]);
+        let excluded = |p: &str, is_dir| filter.matches(Path::new(p), is_dir).is_some();
+
+        assert!(excluded("top.txt", false));
+        assert!(!excluded("sub/top.txt", false), "Anchored to the root");
+        assert!(excluded("x/build", true));
+        assert!(!excluded("x/build", false), "Trailing slash only matches dirs");
+        assert!(excluded("cache", true));
+        assert!(excluded("cache/a/b", false));
+        assert!(excluded("src/a/main.o", false));
+        assert!(!excluded("a/sub/main.o", false), "* stays within a component");
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let mut filter = Filter::new();
+        assert!(matches!(filter.add_line("? what", "test"), Err(FilterError::InvalidRule(_))));
+        let err = filter.exclude("{a,b", EXCLUDE_SOURCE).unwrap_err();
+        assert!(err.to_string().contains("{a,b"));
+    }
+
+    #[test]
+    fn test_tree_filter_dir_merge() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join(".rules"), "- *.tmp\n").unwrap();
+        fs::write(dir.path().join("sub/.rules"), "+ /wanted.tmp\n").unwrap();
+
+        let mut filter = Filter::new();
+        filter.dir_merge(".rules");
+        let tree = TreeFilter::new(dir.path(), filter, false, &[]);
+
+        assert!(tree.is_excluded(&dir.path().join("a.tmp"), false));
+        assert!(tree.is_excluded(&dir.path().join("sub/b.tmp"), false));
+        assert!(!tree.is_excluded(&dir.path().join("sub/wanted.tmp"), false));
+        assert_eq!(tree.counts(), vec![(".rules".to_string(), 2)]);
+    }
+
+    #[test]
+    fn test_nothing_ignored_by_default() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join(GITIGNORE), "*.log\n").unwrap();
+
+        let tree = TreeFilter::new(dir.path(), Filter::new(), false, &[]);
+        assert!(!tree.is_excluded(&dir.path().join("app.log"), false));
+        assert!(tree.counts().is_empty());
+    }
+
+    #[test]
+    fn test_ignore_files_override_and_are_counted() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join(GITIGNORE), "*.log\n").unwrap();
+        fs::write(dir.path().join("sub").join(GITIGNORE), "!keep.log\n").unwrap();
+        fs::write(dir.path().join(".janignore"), "secret\n").unwrap();
+
+        let mut filter = Filter::new();
+        filter.exclude("*.tmp", EXCLUDE_SOURCE).unwrap();
+        let tree = TreeFilter::new(dir.path(), filter, true, &[".janignore".to_string()]);
+
+        assert!(tree.is_excluded(&dir.path().join("a.log"), false));
+        assert!(tree.is_excluded(&dir.path().join("sub/b.log"), false));
+        assert!(!tree.is_excluded(&dir.path().join("sub/keep.log"), false));
+        assert!(tree.is_excluded(&dir.path().join("sub/secret"), true));
+        assert!(tree.is_excluded(&dir.path().join("x.tmp"), false));
+
+        let counts = tree.counts();
+        assert!(counts.contains(&(GITIGNORE.to_string(), 2)));
+        assert!(counts.contains(&(".janignore".to_string(), 1)));
+        assert!(counts.contains(&(EXCLUDE_SOURCE.to_string(), 1)));
+    }
+
+    #[test]
+    fn test_path_excluded_by_parent() {
+        let dir = tempdir().unwrap();
+        let mut filter = Filter::new();
+        filter.exclude("build/", EXCLUDE_SOURCE).unwrap();
+        let tree = TreeFilter::new(dir.path(), filter, false, &[]);
+
+        let object = dir.path().join("build/out/main.o");
+        assert!(!tree.is_excluded(&object, false));
+        assert!(tree.is_path_excluded(&object, false));
+    }
+}
@@ -42,6 +42,44 @@ impl ContentHash {
             ContentHash::Sha256(_) => "SHA-256",
         }
     }
+
+    /// Rebuild a hash from its algorithm name and raw bytes
+    ///
+    /// Returns `None` if the algorithm is not compiled into this build.
+    pub fn from_bytes(algorithm: &str, bytes: [u8; 32]) -> Option<Self> {
+        match algorithm {
+            #[cfg(feature = "blake3")]
+            "BLAKE3" => Some(ContentHash::Blake3(bytes)),
+            #[cfg(feature = "sha256")]
+            "SHA-256" => Some(ContentHash::Sha256(bytes)),
+            _ => None,
+        }
+    }
+
+    /// Parse the hex form printed by `Display`, as a hash of the default algorithm
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != 64 || !hex.is_ascii() {
+            return None;
+        }
+        let mut bytes = [0u8; 32];
+        for (byte, pair) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+        }
+        Self::from_bytes(default_algorithm(), bytes)
+    }
+}
+
+/// Name of the algorithm used by [`Hasher::new`]
+pub fn default_algorithm() -> &'static str {
+    #[cfg(feature = "blake3")]
+    {
+        "BLAKE3"
+    }
+
+    #[cfg(all(feature = "sha256", not(feature = "blake3")))]
+    {
+        "SHA-256"
+    }
 }
 
 impl fmt::Display for ContentHash {
@@ -85,12 +123,14 @@ impl fmt::Display for ContentHash {
 /// # Ok(())
 /// # }
 /// ```
+#[derive(Clone)]
 pub struct Hasher {
     inner: HasherImpl,
 }
 
 /// Internal hasher implementation
 #[allow(dead_code)]
+#[derive(Clone)]
 enum HasherImpl {
     #[cfg(feature = "blake3")]
     Blake3(Box<blake3::Hasher>),
@@ -316,5 +356,24 @@ mod tests {
         let hash = hash_bytes(b"test");
         let algo = hash.algorithm();
         assert!(algo == "BLAKE3" || algo == "SHA-256");
+        assert_eq!(algo, default_algorithm());
+    }
+
+    #[test]
+    fn test_from_bytes_roundtrip() {
+        let hash = hash_bytes(b"roundtrip");
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(hash.as_bytes());
+
+        assert_eq!(ContentHash::from_bytes(hash.algorithm(), bytes), Some(hash));
+        assert_eq!(ContentHash::from_bytes("MD5", bytes), None);
+    }
+
+    #[test]
+    fn test_from_hex_roundtrip() {
+        let hash = hash_bytes(b"hex");
+        assert_eq!(ContentHash::from_hex(&hash.to_string()), Some(hash));
+        assert_eq!(ContentHash::from_hex("abc"), None);
+        assert_eq!(ContentHash::from_hex(&"zz".repeat(32)), None);
     }
 }
@@ -0,0 +1,352 @@
+//! Persistent per-tree hash index
+//!
+//! Stores the content hash of every file seen by the last scan, keyed by
+//! relative path plus a stat fingerprint (size, mtime, inode, ctime). When the
+//! fingerprint still matches, the scanner reuses the stored hash instead of
+//! reading the file again.
+//!
+//! The index is versioned and records the hash algorithm it was built with, so
+//! a format or algorithm change simply invalidates it.
+
+use crate::hash::{default_algorithm, ContentHash};
+use crate::io::{generate_temp_path, AtomicWriter, JAN_INDEX_FILE, JAN_TEMP_DIR};
+use ahash::{HashMap, HashMapExt};
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const INDEX_MAGIC: &[u8; 8] = b"JANINDEX";
+
+/// Bump whenever the on-disk layout changes
+const INDEX_VERSION: u32 = 1;
+
+/// Longest path or algorithm name an index may hold, as on Linux (`PATH_MAX`)
+const MAX_FIELD_LEN: usize = 4096;
+
+/// Files modified this close to the scan are not indexed, since a later write
+/// within the same mtime tick would go unnoticed.
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+/// Stat fingerprint used to decide whether a stored hash is still valid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexKey {
+    pub size: u64,
+    pub mtime_secs: i64,
+    pub mtime_nanos: u32,
+    pub inode: u64,
+    pub ctime_secs: i64,
+    pub ctime_nanos: u32,
+}
+
+impl IndexKey {
+    /// Build a fingerprint from file metadata
+    #[cfg(unix)]
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        Self {
+            size: metadata.len(),
+            mtime_secs: metadata.mtime(),
+            mtime_nanos: metadata.mtime_nsec() as u32,
+            inode: metadata.ino(),
+            ctime_secs: metadata.ctime(),
+            ctime_nanos: metadata.ctime_nsec() as u32,
+        }
+    }
+
+    /// Build a fingerprint from file metadata
+    #[cfg(not(unix))]
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        let (mtime_secs, mtime_nanos) = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| (d.as_secs() as i64, d.subsec_nanos()))
+            .unwrap_or((0, 0));
+        Self {
+            size: metadata.len(),
+            mtime_secs,
+            mtime_nanos,
+            inode: 0,
+            ctime_secs: 0,
+            ctime_nanos: 0,
+        }
+    }
+
+    fn mtime(&self) -> Option<SystemTime> {
+        let secs = u64::try_from(self.mtime_secs).ok()?;
+        Some(UNIX_EPOCH + Duration::new(secs, self.mtime_nanos))
+    }
+}
+
+/// In-memory view of a tree's `.jan-index`
+#[derive(Debug, Default)]
+pub struct HashIndex {
+    entries: HashMap<PathBuf, (IndexKey, ContentHash)>,
+}
+
+impl HashIndex {
+    /// Create an empty index
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the index stored under `root`.
+    ///
+    /// A missing index, an older format version or a different hash algorithm
+    /// all yield an empty index. Truncated or malformed files are reported as
+    /// `InvalidData` so the caller can warn before falling back to rehashing.
+    pub fn load(root: &Path) -> io::Result<Self> {
+        let path = root.join(JAN_INDEX_FILE);
+        let file = match File::open(&path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::new()),
+            Err(e) => return Err(e),
+        };
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != INDEX_MAGIC {
+            return Err(invalid("not a janice index"));
+        }
+        if read_u32(&mut reader)? != INDEX_VERSION {
+            return Ok(Self::new());
+        }
+        let algorithm = String::from_utf8(read_bytes(&mut reader)?)
+            .map_err(|_| invalid("bad algorithm name"))?;
+        if algorithm != default_algorithm() {
+            return Ok(Self::new());
+        }
+
+        let count = read_u64(&mut reader)?;
+        let mut entries = HashMap::with_capacity(count.min(1 << 24) as usize);
+        for _ in 0..count {
+            let rel_path = bytes_to_path(read_bytes(&mut reader)?)?;
+            let key = IndexKey {
+                size: read_u64(&mut reader)?,
+                mtime_secs: read_u64(&mut reader)? as i64,
+                mtime_nanos: read_u32(&mut reader)?,
+                inode: read_u64(&mut reader)?,
+                ctime_secs: read_u64(&mut reader)? as i64,
+                ctime_nanos: read_u32(&mut reader)?,
+            };
+            let mut bytes = [0u8; 32];
+            reader.read_exact(&mut bytes)?;
+            let hash = ContentHash::from_bytes(&algorithm, bytes)
+                .ok_or_else(|| invalid("unsupported hash algorithm"))?;
+            entries.insert(rel_path, (key, hash));
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Write the index under `root`, atomically replacing any previous one.
+    pub fn save(&self, root: &Path) -> io::Result<()> {
+        let temp_dir = root.join(JAN_TEMP_DIR);
+        fs::create_dir_all(&temp_dir)?;
+
+        let mut writer =
+            AtomicWriter::new(generate_temp_path(&temp_dir), root.join(JAN_INDEX_FILE), false)?;
+        writer.write(INDEX_MAGIC)?;
+        writer.write(&INDEX_VERSION.to_le_bytes())?;
+        write_bytes(&mut writer, default_algorithm().as_bytes())?;
+        // Paths too long to load back are left out, so they are just rehashed
+        let entries: Vec<_> = self
+            .entries
+            .iter()
+            .map(|(rel_path, entry)| (path_to_bytes(rel_path), entry))
+            .filter(|(path, _)| path.len() <= MAX_FIELD_LEN)
+            .collect();
+        writer.write(&(entries.len() as u64).to_le_bytes())?;
+
+        for (path, (key, hash)) in entries {
+            write_bytes(&mut writer, &path)?;
+            writer.write(&key.size.to_le_bytes())?;
+            writer.write(&key.mtime_secs.to_le_bytes())?;
+            writer.write(&key.mtime_nanos.to_le_bytes())?;
+            writer.write(&key.inode.to_le_bytes())?;
+            writer.write(&key.ctime_secs.to_le_bytes())?;
+            writer.write(&key.ctime_nanos.to_le_bytes())?;
+            writer.write(hash.as_bytes())?;
+        }
+        writer.commit(None)?;
+
+        // Only succeeds if nothing else is using the temp dir
+        let _ = fs::remove_dir(&temp_dir);
+        Ok(())
+    }
+
+    /// Return the stored hash if the fingerprint still matches
+    pub fn lookup(&self, rel_path: &Path, key: &IndexKey) -> Option<&ContentHash> {
+        match self.entries.get(rel_path) {
+            Some((stored, hash)) if stored == key => Some(hash),
+            _ => None,
+        }
+    }
+
+    /// Record a freshly computed hash.
+    ///
+    /// Entries modified within two seconds of `scan_start` are skipped so a
+    /// write landing in the same timestamp tick forces a rehash next time.
+    pub fn insert(
+        &mut self,
+        rel_path: PathBuf,
+        key: IndexKey,
+        hash: ContentHash,
+        scan_start: SystemTime,
+    ) {
+        let racy = match key.mtime() {
+            Some(mtime) => mtime + RACY_WINDOW >= scan_start,
+            None => true,
+        };
+        if !racy {
+            self.entries.insert(rel_path, (key, hash));
+        }
+    }
+
+    /// Number of indexed files
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the index is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Corrupt hash index: {msg}"))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_bytes(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = read_u32(reader)? as usize;
+    if len > MAX_FIELD_LEN {
+        return Err(invalid("field too long"));
+    }
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn write_bytes(writer: &mut AtomicWriter, bytes: &[u8]) -> io::Result<()> {
+    writer.write(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write(bytes)
+}
+
+#[cfg(unix)]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+fn bytes_to_path(bytes: Vec<u8>) -> io::Result<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Ok(PathBuf::from(std::ffi::OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+fn bytes_to_path(bytes: Vec<u8>) -> io::Result<PathBuf> {
+    String::from_utf8(bytes)
+        .map(PathBuf::from)
+        .map_err(|_| invalid("non-UTF-8 path"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::hash_bytes;
+    use tempfile::tempdir;
+
+    fn old_key(size: u64) -> IndexKey {
+        IndexKey {
+            size,
+            mtime_secs: 1_600_000_000,
+            mtime_nanos: 0,
+            inode: 42,
+            ctime_secs: 1_600_000_000,
+            ctime_nanos: 0,
+        }
+    }
+
+    #[test]
+    fn test_index_roundtrip() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut index = HashIndex::new();
+        let hash = hash_bytes(b"indexed");
+        index.insert(PathBuf::from("a/b.txt"), old_key(7), hash.clone(), SystemTime::now());
+        index.save(dir.path())?;
+
+        assert!(!dir.path().join(JAN_TEMP_DIR).exists(), "Temp dir should be cleaned up");
+
+        let loaded = HashIndex::load(dir.path())?;
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded.lookup(Path::new("a/b.txt"), &old_key(7)), Some(&hash));
+        assert_eq!(loaded.lookup(Path::new("a/b.txt"), &old_key(8)), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_skips_racy_entries() {
+        let mut index = HashIndex::new();
+        let now = SystemTime::now();
+        let secs = now.duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        let key = IndexKey { mtime_secs: secs, ..old_key(1) };
+
+        index.insert(PathBuf::from("fresh.txt"), key, hash_bytes(b"x"), now);
+        assert!(index.is_empty(), "Freshly modified files should not be indexed");
+    }
+
+    #[test]
+    fn test_index_version_mismatch_is_empty() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut content = INDEX_MAGIC.to_vec();
+        content.extend_from_slice(&(INDEX_VERSION + 1).to_le_bytes());
+        fs::write(dir.path().join(JAN_INDEX_FILE), content)?;
+
+        assert!(HashIndex::load(dir.path())?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_huge_field_is_error() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut content = INDEX_MAGIC.to_vec();
+        content.extend_from_slice(&INDEX_VERSION.to_le_bytes());
+        content.extend_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(dir.path().join(JAN_INDEX_FILE), content)?;
+
+        let err = HashIndex::load(dir.path()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_corrupt_is_error() -> io::Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join(JAN_INDEX_FILE), b"garbage")?;
+
+        assert!(HashIndex::load(dir.path()).is_err());
+        Ok(())
+    }
+}
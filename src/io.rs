@@ -1,11 +1,13 @@
 //! File I/O with streaming copy and metadata preservation
 
-use std::fs::{self, File, Metadata};
-use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use crate::hash::{ContentHash, Hasher};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
 // 256KB: optimal for modern SSD throughput
@@ -17,6 +19,36 @@ pub const JAN_TEMP_DIR: &str = ".jan-tmp";
 /// Janice journal file name (inside destination root)
 pub const JAN_JOURNAL_FILE: &str = ".jan-journal";
 
+/// Undo log and backups of the last transactional sync (inside destination root)
+pub const JAN_ROLLBACK_DIR: &str = ".jan-rollback";
+
+/// Lock held by the sync running into a destination (inside destination root)
+pub const JAN_LOCK_FILE: &str = ".jan-lock";
+
+/// Saved plan of an unfinished sync (inside destination root)
+pub const JAN_PLAN_FILE: &str = ".jan-plan";
+
+/// Staging directory for files received from a remote client (inside destination root)
+pub const JAN_INCOMING_DIR: &str = ".jan-incoming";
+
+/// Janice hash index file name (inside scanned root)
+pub const JAN_INDEX_FILE: &str = ".jan-index";
+
+/// What both trees held after the last two-way sync (inside both roots)
+pub const JAN_BASELINE_FILE: &str = ".jan-baseline";
+
+/// Files displaced by syncs run with a backup, one directory per sync
+/// (inside destination root)
+pub const JAN_TRASH_DIR: &str = ".jan-trash";
+
+/// Bytes copied between resume points; smaller files never record one
+pub const RESUME_CHECKPOINT: u64 = 64 * 1024 * 1024;
+
+/// Journal format version, written in its first line
+pub const JOURNAL_VERSION: u32 = 1;
+
+const JOURNAL_KIND: &str = "jan-journal";
+
 /// Monotonic counter for unique temp file names within a process
 static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
 
@@ -36,6 +68,36 @@ pub enum IoError {
     Io(#[from] io::Error),
 }
 
+/// A journal or log that can't be trusted to replay
+#[derive(Error, Debug)]
+pub enum JournalError {
+    #[error("{} is not a {kind} file", path.display())]
+    NotAJournal { path: PathBuf, kind: &'static str },
+
+    #[error("{} has {kind} format version {found}, this build reads {expected}", path.display())]
+    Version {
+        path: PathBuf,
+        kind: &'static str,
+        found: String,
+        expected: u32,
+    },
+
+    #[error("{} is corrupt at line {line}", path.display())]
+    Corrupt { path: PathBuf, line: usize },
+
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl From<JournalError> for io::Error {
+    fn from(e: JournalError) -> Self {
+        match e {
+            JournalError::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
 /// Copy a file with streaming I/O and optional metadata preservation
 ///
 /// This function copies a file from source to destination using buffered
@@ -393,6 +455,343 @@ pub fn atomic_copy_file_with_metadata(
     Ok(())
 }
 
+/// How far an interrupted copy got, and of which source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResumePoint {
+    /// Hash of the whole source file, if the scan computed one
+    pub source_hash: Option<ContentHash>,
+    pub source_size: u64,
+    pub source_mtime: SystemTime,
+    /// Bytes of the temp file known to be on disk
+    pub written: u64,
+    /// Hash of those bytes, checked before appending to them
+    pub prefix_hash: ContentHash,
+}
+
+/// A temp file kept from an interrupted copy, with its last resume point
+#[derive(Debug, Clone)]
+pub struct PartialCopy {
+    pub temp_path: PathBuf,
+    pub final_path: PathBuf,
+    pub point: ResumePoint,
+}
+
+/// Copy a file atomically, leaving resume points behind for a later run.
+///
+/// Every [`RESUME_CHECKPOINT`] bytes the temp file is synced and `checkpoint`
+/// called with how far the copy got. If the copy fails or the process dies,
+/// the temp file stays, and passing that point back as `resume` makes the
+/// next attempt hash the prefix already written and append the rest. A prefix
+/// that no longer matches its recorded hash is thrown away and the copy
+/// starts over. The caller must make sure the source is the file the point
+/// was recorded for.
+///
+/// The copy is always hashed, so `expected_hash` costs nothing extra; on a
+/// mismatch the temp file is removed rather than kept for resuming.
+///
+/// Returns how many bytes were reused from the earlier attempt.
+pub fn atomic_resumable_copy(
+    source: &Path,
+    dest: &Path,
+    temp_path: &Path,
+    resume: Option<&ResumePoint>,
+    preserve_timestamps: bool,
+    expected_hash: Option<&ContentHash>,
+    checkpoint: &mut dyn FnMut(&ResumePoint) -> io::Result<()>,
+) -> io::Result<u64> {
+    let metadata = fs::metadata(source)?;
+
+    let prefix = match resume {
+        Some(point) => hash_prefix(temp_path, point)?,
+        None => None,
+    };
+    let (mut file, mut hasher, mut written) = match prefix {
+        Some((hasher, len)) => {
+            let mut file = OpenOptions::new().write(true).open(temp_path)?;
+            // Anything past the resume point may not have reached the disk
+            file.set_len(len)?;
+            file.seek(SeekFrom::End(0))?;
+            (file, hasher, len)
+        },
+        None => (File::create(temp_path)?, Hasher::new(), 0),
+    };
+    let reused = written;
+
+    let mut reader = BufReader::with_capacity(COPY_BUFFER_SIZE, File::open(source)?);
+    reader.seek(SeekFrom::Start(written))?;
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+    let mut next_checkpoint = written + RESUME_CHECKPOINT;
+
+    {
+        let mut writer = BufWriter::with_capacity(COPY_BUFFER_SIZE, &mut file);
+        loop {
+            let bytes_read = reader.read(&mut buffer)?;
+            if bytes_read == 0 {
+                break;
+            }
+            writer.write_all(&buffer[..bytes_read])?;
+            hasher.update(&buffer[..bytes_read]);
+            written += bytes_read as u64;
+
+            if written >= next_checkpoint {
+                writer.flush()?;
+                writer.get_ref().sync_data()?;
+                checkpoint(&ResumePoint {
+                    source_hash: expected_hash.cloned(),
+                    source_size: metadata.len(),
+                    source_mtime: metadata.modified()?,
+                    written,
+                    prefix_hash: hasher.clone().finalize(),
+                })?;
+                next_checkpoint = written + RESUME_CHECKPOINT;
+            }
+        }
+        writer.flush()?;
+    }
+    file.sync_all()?;
+    drop(file);
+
+    if let Some(expected) = expected_hash {
+        let computed = hasher.finalize();
+        if computed != *expected {
+            let _ = fs::remove_file(temp_path);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Hash verification failed for {}: expected {expected}, got {computed}",
+                    dest.display(),
+                ),
+            ));
+        }
+    }
+
+    fs::rename(temp_path, dest)?;
+
+    if preserve_timestamps {
+        set_file_mtime(dest, metadata.modified()?)?;
+    }
+
+    #[cfg(unix)]
+    {
+        set_file_permissions(dest, &metadata)?;
+    }
+
+    Ok(reused)
+}
+
+/// Hash the first `point.written` bytes of `path`
+///
+/// Returns the hasher and length if they match `point.prefix_hash`, `None`
+/// if the file is missing, too short or different.
+fn hash_prefix(path: &Path, point: &ResumePoint) -> io::Result<Option<(Hasher, u64)>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if file.metadata()?.len() < point.written {
+        return Ok(None);
+    }
+
+    let mut reader = BufReader::with_capacity(COPY_BUFFER_SIZE, file).take(point.written);
+    let mut hasher = Hasher::new();
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+    loop {
+        let bytes_read = reader.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+
+    if hasher.clone().finalize() == point.prefix_hash {
+        Ok(Some((hasher, point.written)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Hard-link an existing file to `dest`, atomically via `temp_path`.
+///
+/// Both names share one inode afterwards, so timestamps and permissions are
+/// those of `existing`.
+pub fn atomic_hard_link(existing: &Path, dest: &Path, temp_path: &Path) -> io::Result<()> {
+    fs::hard_link(existing, temp_path)?;
+    if let Err(e) = fs::rename(temp_path, dest) {
+        let _ = fs::remove_file(temp_path);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Clone an existing file to `dest` sharing its data blocks (reflink), atomically
+/// via `temp_path`.
+///
+/// Returns `Ok(false)` without touching `dest` when the platform or filesystem
+/// cannot clone, so the caller can fall back to a regular copy.
+pub fn atomic_reflink(existing: &Path, dest: &Path, temp_path: &Path) -> io::Result<bool> {
+    if !clone_file(existing, temp_path)? {
+        let _ = fs::remove_file(temp_path);
+        return Ok(false);
+    }
+    if let Err(e) = fs::rename(temp_path, dest) {
+        let _ = fs::remove_file(temp_path);
+        return Err(e);
+    }
+    Ok(true)
+}
+
+/// Create a symlink at `dest` pointing to `target`, atomically via `temp_path`.
+///
+/// The link is created under the temp name and renamed into place, so an
+/// existing file or link at `dest` is replaced in one step.
+pub fn atomic_symlink(target: &Path, dest: &Path, temp_path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(target, temp_path)?;
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_file(target, temp_path)?;
+    #[cfg(not(any(unix, windows)))]
+    return Err(io::Error::new(io::ErrorKind::Unsupported, "symlinks are not supported"));
+
+    if let Err(e) = fs::rename(temp_path, dest) {
+        let _ = fs::remove_file(temp_path);
+        return Err(e);
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn clone_file(existing: &Path, temp_path: &Path) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    let source = File::open(existing)?;
+    let target = File::create(temp_path)?;
+    // SAFETY: both descriptors are open for the duration of the call
+    let rc = unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) };
+    if rc == -1 {
+        return Ok(false);
+    }
+    target.sync_all()?;
+    Ok(true)
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn clone_file(existing: &Path, temp_path: &Path) -> io::Result<bool> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let to_c = |p: &Path| {
+        CString::new(p.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    };
+    let (src, dst) = (to_c(existing)?, to_c(temp_path)?);
+    // SAFETY: both pointers come from live NUL-terminated CStrings
+    let rc = unsafe { libc::clonefile(src.as_ptr(), dst.as_ptr(), 0) };
+    Ok(rc == 0)
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios"
+)))]
+fn clone_file(_existing: &Path, _temp_path: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// Move a file within the destination tree.
+///
+/// Uses `fs::rename` so no data is rewritten. When the two paths live on
+/// different filesystems, falls back to an atomic copy through `temp_path`
+/// (which must be on the same filesystem as `dest`) followed by removal of
+/// the original.
+pub fn move_file(
+    source: &Path,
+    dest: &Path,
+    temp_path: &Path,
+    verify: bool,
+    expected_hash: Option<&crate::hash::ContentHash>,
+) -> io::Result<()> {
+    match fs::rename(source, dest) {
+        Ok(()) => Ok(()),
+        Err(e) if is_cross_device(&e) => {
+            if fs::symlink_metadata(source)?.file_type().is_symlink() {
+                atomic_symlink(&fs::read_link(source)?, dest, temp_path)?;
+            } else {
+                atomic_copy_file_with_metadata(
+                    source,
+                    dest,
+                    temp_path,
+                    true,
+                    verify,
+                    expected_hash,
+                )?;
+            }
+            remove_file_safe(source)
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// Move a whole directory within the destination tree.
+///
+/// A single `fs::rename` when possible; across filesystems, every file is moved
+/// individually (copying through `temp_dir`) and the emptied tree removed.
+pub fn move_dir(source: &Path, dest: &Path, temp_dir: &Path) -> io::Result<()> {
+    match fs::rename(source, dest) {
+        Ok(()) => Ok(()),
+        Err(e) if is_cross_device(&e) => move_tree(source, dest, &mut |from, to| {
+            move_file(from, to, &generate_temp_path(temp_dir), false, None)
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+/// Move `source` to `dest` one file at a time, then remove what is left
+///
+/// A failure partway leaves the tree split between the two; [`undo_move`]
+/// puts it back together.
+fn move_tree(
+    source: &Path,
+    dest: &Path,
+    move_one: &mut dyn FnMut(&Path, &Path) -> io::Result<()>,
+) -> io::Result<()> {
+    move_tree_contents(source, dest, move_one)?;
+    fs::remove_dir_all(source)
+}
+
+/// Recursively move every file under `from` to the same place under `to`
+fn move_tree_contents(
+    from: &Path,
+    to: &Path,
+    move_one: &mut dyn FnMut(&Path, &Path) -> io::Result<()>,
+) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            move_tree_contents(&entry.path(), &target, move_one)?;
+        } else {
+            move_one(&entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether a rename failed only because source and dest are on different mounts
+fn is_cross_device(e: &io::Error) -> bool {
+    // EXDEV on every Unix we care about
+    #[cfg(unix)]
+    return e.raw_os_error() == Some(18);
+    // ERROR_NOT_SAME_DEVICE
+    #[cfg(windows)]
+    return e.raw_os_error() == Some(17);
+    #[cfg(not(any(unix, windows)))]
+    return false;
+}
+
 /// Flush directory metadata to disk (ensures renames are persisted).
 ///
 /// No-op on Windows where directory fsync is not supported.
@@ -409,6 +808,19 @@ pub fn fsync_directory(path: &Path) -> io::Result<()> {
 /// Records pending/committed state for each file operation. On recovery,
 /// entries with P but no matching C indicate incomplete operations whose
 /// temp files should be cleaned up.
+///
+/// The file starts with a `jan-journal VERSION` line, followed by one record
+/// per line as written by `seal_record`: escaped fields, then a checksum.
+/// A last record torn by a crash is ignored; any other damage makes recovery
+/// fail with a [`JournalError`] rather than guess.
+///
+/// `MOVE` entries are the exception: their first path is the file's original
+/// location inside the destination, and recovery undoes a half-finished move
+/// by putting the file back there.
+///
+/// `R` entries record a [`ResumePoint`] for a copy still in flight. Recovery
+/// keeps the temp file of an unfinished copy with such an entry, so the next
+/// sync can continue it.
 pub struct SyncJournal {
     file: Mutex<BufWriter<File>>,
     path: PathBuf,
@@ -418,16 +830,21 @@ impl SyncJournal {
     /// Create a new journal file at the given path.
     pub fn create(path: PathBuf) -> io::Result<Self> {
         let file = File::create(&path)?;
-        let writer = BufWriter::new(file);
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "{JOURNAL_KIND} {JOURNAL_VERSION}")?;
+        writer.flush()?;
         Ok(Self { file: Mutex::new(writer), path })
     }
 
+    fn append(&self, fields: &[&str]) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        file.write_all(seal_record(fields).as_bytes())?;
+        file.flush()
+    }
+
     /// Record that an operation is about to begin.
     pub fn record_pending(&self, op: &str, temp_path: &Path, final_path: &Path) -> io::Result<()> {
-        let mut file = self.file.lock().unwrap();
-        writeln!(file, "P\t{op}\t{}\t{}", temp_path.display(), final_path.display())?;
-        file.flush()?;
-        Ok(())
+        self.append(&["P", op, &escape_path(temp_path), &escape_path(final_path)])
     }
 
     /// Record that an operation completed successfully.
@@ -437,10 +854,35 @@ impl SyncJournal {
         temp_path: &Path,
         final_path: &Path,
     ) -> io::Result<()> {
-        let mut file = self.file.lock().unwrap();
-        writeln!(file, "C\t{op}\t{}\t{}", temp_path.display(), final_path.display())?;
-        file.flush()?;
-        Ok(())
+        self.append(&["C", op, &escape_path(temp_path), &escape_path(final_path)])
+    }
+
+    /// Record how far a copy has got, so it can be resumed.
+    pub fn record_progress(
+        &self,
+        temp_path: &Path,
+        final_path: &Path,
+        point: &ResumePoint,
+    ) -> io::Result<()> {
+        let source_hash = point.source_hash.as_ref().map_or("-".to_string(), |h| h.to_string());
+        self.append(&[
+            "R",
+            &escape_path(temp_path),
+            &escape_path(final_path),
+            &source_hash,
+            &point.source_size.to_string(),
+            &format_time(point.source_mtime),
+            &point.written.to_string(),
+            &point.prefix_hash.to_string(),
+        ])
+    }
+
+    /// Leave the journal in place for the next sync to recover.
+    ///
+    /// Used when a copy failed after recording a resume point, so its temp
+    /// file isn't thrown away.
+    pub fn keep(self) -> io::Result<()> {
+        self.file.into_inner().unwrap().flush()
     }
 
     /// Clean up the journal file.
@@ -449,71 +891,469 @@ impl SyncJournal {
         remove_file_safe(&self.path)
     }
 
+    /// Close the journal of a failed sync and undo what it left unfinished,
+    /// as [`recover`](Self::recover) would next run
+    ///
+    /// If that fails too the journal is kept, for the next run to try again.
+    pub fn abort(self, temp_dir: &Path) -> io::Result<()> {
+        drop(self.file);
+        Self::recover(&self.path, temp_dir).map(drop)
+    }
+
     /// Recover from a previous interrupted sync.
     ///
     /// Reads the journal, finds P entries without matching C entries,
-    /// and cleans up their temp files. Then removes the journal and
-    /// sweeps the temp directory.
-    pub fn recover(journal_path: &Path, temp_dir: &Path) -> io::Result<()> {
+    /// and cleans up their temp files (or undoes them, for moves). Then
+    /// removes the journal and sweeps the temp directory.
+    ///
+    /// Unfinished copies that recorded a resume point keep their temp file
+    /// and are returned, for the caller to continue or delete.
+    ///
+    /// A corrupt journal is left alone and reported as an
+    /// [`io::ErrorKind::InvalidData`] error wrapping a [`JournalError`].
+    pub fn recover(journal_path: &Path, temp_dir: &Path) -> io::Result<Vec<PartialCopy>> {
         if !journal_path.exists() {
             // No journal means clean state; still sweep orphaned temps
             if temp_dir.exists() {
-                cleanup_temp_dir(temp_dir)?;
+                cleanup_temp_dir(temp_dir, &[])?;
+            }
+            return Ok(Vec::new());
+        }
+
+        let mut partials = Vec::new();
+        for entry in Self::read(journal_path)? {
+            if entry.committed {
+                continue;
+            }
+            match entry.progress {
+                _ if entry.op == "MOVE" => undo_move(&entry.temp_path, &entry.final_path)?,
+                Some(point) if entry.op == "COPY" && entry.temp_path.is_file() => {
+                    partials.push(PartialCopy {
+                        temp_path: entry.temp_path,
+                        final_path: entry.final_path,
+                        point,
+                    });
+                },
+                _ => {
+                    let _ = fs::remove_file(&entry.temp_path);
+                },
             }
-            return Ok(());
         }
 
-        let file = File::open(journal_path)?;
-        let reader = BufReader::new(file);
+        remove_file_safe(journal_path)?;
 
-        let mut pending: Vec<(String, PathBuf, PathBuf)> = Vec::new();
-        let mut committed: Vec<(String, PathBuf, PathBuf)> = Vec::new();
+        if temp_dir.exists() {
+            let keep: Vec<&Path> = partials.iter().map(|p| p.temp_path.as_path()).collect();
+            cleanup_temp_dir(temp_dir, &keep)?;
+        }
 
-        for line in reader.lines() {
-            let line = match line {
-                Ok(l) => l,
-                Err(_) => break,
-            };
+        Ok(partials)
+    }
 
-            let parts: Vec<&str> = line.split('\t').collect();
-            if parts.len() < 4 {
-                continue;
+    /// Read the operations a journal records, without acting on them
+    pub fn read(journal_path: &Path) -> io::Result<Vec<JournalEntry>> {
+        let records =
+            read_records(journal_path, JOURNAL_KIND, JOURNAL_VERSION, |fields| match fields {
+                ["R", ..] => parse_progress(fields).map(JournalRecord::Progress),
+                [kind @ ("P" | "C"), op, temp, final_path] => {
+                    let entry = (op.to_string(), unescape_path(temp)?, unescape_path(final_path)?);
+                    Some(match *kind {
+                        "P" => JournalRecord::Pending(entry),
+                        _ => JournalRecord::Committed(entry),
+                    })
+                },
+                _ => None,
+            })?;
+
+        let mut entries: Vec<JournalEntry> = Vec::new();
+        // Unfinished entries by (op, temp, final), to match commits against
+        let mut open: HashMap<(String, PathBuf, PathBuf), usize> = HashMap::new();
+        for record in records {
+            match record {
+                JournalRecord::Pending((op, temp_path, final_path)) => {
+                    open.insert((op.clone(), temp_path.clone(), final_path.clone()), entries.len());
+                    entries.push(JournalEntry {
+                        op,
+                        temp_path,
+                        final_path,
+                        committed: false,
+                        progress: None,
+                    });
+                },
+                JournalRecord::Committed(key) => {
+                    if let Some(i) = open.remove(&key) {
+                        entries[i].committed = true;
+                    }
+                },
+                JournalRecord::Progress(partial) => {
+                    let entry = entries.iter_mut().rev().find(|e| {
+                        !e.committed
+                            && e.temp_path == partial.temp_path
+                            && e.final_path == partial.final_path
+                    });
+                    if let Some(entry) = entry {
+                        entry.progress = Some(partial.point);
+                    }
+                },
             }
+        }
+        Ok(entries)
+    }
 
-            let entry = (parts[1].to_string(), PathBuf::from(parts[2]), PathBuf::from(parts[3]));
+    /// Report what an interrupted sync left behind, changing nothing
+    pub fn inspect(journal_path: &Path, temp_dir: &Path) -> io::Result<JournalReport> {
+        let entries = if journal_path.exists() {
+            Self::read(journal_path)?
+        } else {
+            Vec::new()
+        };
+        let in_flight: HashSet<&Path> =
+            entries.iter().filter(|e| !e.committed).map(|e| e.temp_path.as_path()).collect();
+        let mut orphans: Vec<PathBuf> = match fs::read_dir(temp_dir) {
+            Ok(dir) => dir
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .filter(|path| !in_flight.contains(path.as_path()))
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        orphans.sort();
+        Ok(JournalReport { entries, orphans })
+    }
 
-            match parts[0] {
-                "P" => pending.push(entry),
-                "C" => committed.push(entry),
-                _ => continue,
-            }
+    /// Forget an interrupted sync without undoing any of it
+    ///
+    /// Deletes the journal and the temp directory, partial copies included.
+    /// Half-finished moves stay wherever they got to.
+    pub fn discard(journal_path: &Path, temp_dir: &Path) -> io::Result<()> {
+        remove_file_safe(journal_path)?;
+        match fs::remove_dir_all(temp_dir) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
         }
+    }
+}
+
+/// One operation read back from a journal
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    /// `COPY`, `DELTA`, `MOVE` and so on
+    pub op: String,
+    /// The temp file being written, or for a `MOVE` the original location
+    pub temp_path: PathBuf,
+    pub final_path: PathBuf,
+    /// Whether the operation finished
+    pub committed: bool,
+    /// Last resume point of an unfinished copy
+    pub progress: Option<ResumePoint>,
+}
+
+/// What an interrupted sync left in a destination
+#[derive(Debug, Clone, Default)]
+pub struct JournalReport {
+    /// Operations in the order they started; empty without a journal
+    pub entries: Vec<JournalEntry>,
+    /// Files in the temp directory that no unfinished operation accounts for
+    pub orphans: Vec<PathBuf>,
+}
 
-        for (op, temp, final_path) in &pending {
-            let is_committed = committed
-                .iter()
-                .any(|(cop, ctemp, cfinal)| cop == op && ctemp == temp && cfinal == final_path);
+enum JournalRecord {
+    Pending((String, PathBuf, PathBuf)),
+    Committed((String, PathBuf, PathBuf)),
+    Progress(PartialCopy),
+}
 
-            if !is_committed {
-                let _ = fs::remove_file(temp);
+/// Parse the fields of an `R` journal record; `None` if they are mangled
+fn parse_progress(parts: &[&str]) -> Option<PartialCopy> {
+    let [_, temp, final_path, source_hash, size, mtime, written, prefix_hash] = parts else {
+        return None;
+    };
+    let source_hash = match *source_hash {
+        "-" => None,
+        hex => Some(ContentHash::from_hex(hex)?),
+    };
+    let source_mtime = parse_time(mtime)?;
+    Some(PartialCopy {
+        temp_path: unescape_path(temp)?,
+        final_path: unescape_path(final_path)?,
+        point: ResumePoint {
+            source_hash,
+            source_size: size.parse().ok()?,
+            source_mtime,
+            written: written.parse().ok()?,
+            prefix_hash: ContentHash::from_hex(prefix_hash)?,
+        },
+    })
+}
+
+/// Join escaped `fields` into one record line, checksum last
+///
+/// Fields must not contain tabs or line breaks; paths go through
+/// [`escape_path`] first.
+pub(crate) fn seal_record(fields: &[&str]) -> String {
+    let body = fields.join("\t");
+    let checksum = record_checksum(&body);
+    format!("{body}\t{checksum}\n")
+}
+
+fn record_checksum(body: &str) -> String {
+    let hash = blake3::hash(body.as_bytes());
+    hash.as_bytes()[..4].iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Read back a log of `KIND VERSION` followed by [`seal_record`] lines
+///
+/// `parse` turns each record's fields into a value, or `None` if they make no
+/// sense. Only a last line without its line break, as left by a crash
+/// mid-write, is skipped; everything else must check out. An empty file
+/// holds no records.
+pub(crate) fn read_records<T>(
+    path: &Path,
+    kind: &'static str,
+    version: u32,
+    mut parse: impl FnMut(&[&str]) -> Option<T>,
+) -> Result<Vec<T>, JournalError> {
+    let content = fs::read(path)?;
+    let mut lines = content.split_inclusive(|&b| b == b'\n').enumerate();
+    let Some((_, header)) = lines.next() else {
+        return Ok(Vec::new());
+    };
+
+    let header = std::str::from_utf8(header).unwrap_or("");
+    let Some((found_kind, found_version)) = header.trim_end().split_once(' ') else {
+        return Err(JournalError::NotAJournal { path: path.to_path_buf(), kind });
+    };
+    if found_kind != kind {
+        return Err(JournalError::NotAJournal { path: path.to_path_buf(), kind });
+    }
+    if found_version != version.to_string() {
+        return Err(JournalError::Version {
+            path: path.to_path_buf(),
+            kind,
+            found: found_version.to_string(),
+            expected: version,
+        });
+    }
+
+    let mut records = Vec::new();
+    for (i, line) in lines {
+        let Some(line) = line.strip_suffix(b"\n") else {
+            break;
+        };
+        let corrupt = || JournalError::Corrupt { path: path.to_path_buf(), line: i + 1 };
+        let line = std::str::from_utf8(line).map_err(|_| corrupt())?;
+        let (body, checksum) = line.rsplit_once('\t').ok_or_else(corrupt)?;
+        if record_checksum(body) != checksum {
+            return Err(corrupt());
+        }
+        let fields: Vec<&str> = body.split('\t').collect();
+        records.push(parse(&fields).ok_or_else(corrupt)?);
+    }
+    Ok(records)
+}
+
+/// Escape a path into a single record field, byte for byte on Unix
+///
+/// Backslashes and control characters are escaped, as is every byte that
+/// isn't valid UTF-8, so the field never holds a tab or line break.
+pub fn escape_path(path: &Path) -> String {
+    use std::fmt::Write as _;
+    let mut field = String::new();
+    for chunk in path_bytes(path).utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => field.push_str("\\\\"),
+                '\t' => field.push_str("\\t"),
+                '\n' => field.push_str("\\n"),
+                '\r' => field.push_str("\\r"),
+                c if c.is_ascii_control() => {
+                    let _ = write!(field, "\\x{:02x}", c as u8);
+                },
+                c => field.push(c),
             }
         }
+        for byte in chunk.invalid() {
+            let _ = write!(field, "\\x{byte:02x}");
+        }
+    }
+    field
+}
 
-        remove_file_safe(journal_path)?;
+/// Inverse of [`escape_path`]; `None` for a malformed escape
+pub(crate) fn unescape_path(field: &str) -> Option<PathBuf> {
+    let mut bytes = Vec::with_capacity(field.len());
+    let mut rest = field.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        let (&escape, tail) = rest.split_first()?;
+        rest = tail;
+        bytes.push(match escape {
+            b'\\' => b'\\',
+            b't' => b'\t',
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b'x' => {
+                let hex = std::str::from_utf8(rest.get(..2)?).ok()?;
+                rest = &rest[2..];
+                u8::from_str_radix(hex, 16).ok()?
+            },
+            _ => return None,
+        });
+    }
+    path_from_bytes(bytes)
+}
 
-        if temp_dir.exists() {
-            cleanup_temp_dir(temp_dir)?;
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> std::borrow::Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().into()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> std::borrow::Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        std::borrow::Cow::Borrowed(s) => s.as_bytes().into(),
+        std::borrow::Cow::Owned(s) => s.into_bytes().into(),
+    }
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(std::ffi::OsString::from_vec(bytes).into())
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+/// Keep a file's current content at `backup` without disturbing it
+pub(crate) fn link_or_copy(path: &Path, backup: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    if fs::hard_link(path, backup).is_ok() {
+        return Ok(());
+    }
+    if metadata.file_type().is_symlink() {
+        #[cfg(unix)]
+        return std::os::unix::fs::symlink(fs::read_link(path)?, backup);
+    }
+    fs::copy(path, backup)?;
+    set_file_mtime(backup, metadata.modified()?)
+}
+
+/// Read until `buf` is full or the input ends; returns the bytes read
+pub(crate) fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
         }
+    }
+    Ok(filled)
+}
 
-        Ok(())
+/// Timestamp as `SECS.NANOS` around the Unix epoch, negative before it
+pub(crate) fn format_time(time: SystemTime) -> String {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => format!("{}.{:09}", after.as_secs(), after.subsec_nanos()),
+        Err(e) => format!("-{}.{:09}", e.duration().as_secs(), e.duration().subsec_nanos()),
+    }
+}
+
+/// Timestamp as `YYYY-MM-DD HH:MM:SS UTC`, for people to read
+pub(crate) fn format_utc(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+    // Days to civil date, after Howard Hinnant's algorithm
+    let z = days as i64 + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        rest / 3_600,
+        rest / 60 % 60,
+        rest % 60,
+    )
+}
+
+/// Inverse of [`format_utc`]
+pub(crate) fn parse_utc(text: &str) -> Option<SystemTime> {
+    let (date, time) = text.strip_suffix(" UTC")?.split_once(' ')?;
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let mut time = time.splitn(3, ':').map(str::parse::<u64>);
+    let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
     }
+    // Civil date to days, the other half of Hinnant's algorithm
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146_097 + doe - 719_468).ok()?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(days * 86_400 + hour * 3_600 + minute * 60 + second))
 }
 
-/// Remove all files in the temp directory (orphan cleanup).
-fn cleanup_temp_dir(temp_dir: &Path) -> io::Result<()> {
+/// Inverse of [`format_time`]
+pub(crate) fn parse_time(text: &str) -> Option<SystemTime> {
+    let (secs, nanos) = text.split_once('.')?;
+    match secs.strip_prefix('-') {
+        Some(secs) => {
+            UNIX_EPOCH.checked_sub(Duration::new(secs.parse().ok()?, nanos.parse().ok()?))
+        },
+        None => UNIX_EPOCH.checked_add(Duration::new(secs.parse().ok()?, nanos.parse().ok()?)),
+    }
+}
+
+/// Put a half-moved file or directory back at its original location.
+///
+/// Move targets never exist in the destination before the sync, so if both
+/// paths are present the copy fallback finished and the new one can go. A
+/// directory caught mid-fallback has its moved files put back one by one.
+pub(crate) fn undo_move(original: &Path, moved: &Path) -> io::Result<()> {
+    // symlink_metadata so dangling links still count as present
+    let exists = |p: &Path| fs::symlink_metadata(p).is_ok();
+    let moved_is_dir = fs::symlink_metadata(moved).map(|m| m.is_dir()).unwrap_or(false);
+    match (exists(original), exists(moved)) {
+        (false, true) => fs::rename(moved, original),
+        (true, true) if moved_is_dir => {
+            move_tree_contents(moved, original, &mut |from, to| {
+                if exists(to) {
+                    remove_file_safe(from)
+                } else {
+                    fs::rename(from, to)
+                }
+            })?;
+            fs::remove_dir_all(moved)
+        },
+        (true, true) => remove_file_safe(moved),
+        _ => Ok(()),
+    }
+}
+
+/// Remove all files in the temp directory except `keep` (orphan cleanup).
+fn cleanup_temp_dir(temp_dir: &Path, keep: &[&Path]) -> io::Result<()> {
     if let Ok(entries) = fs::read_dir(temp_dir) {
         for entry in entries.flatten() {
-            let _ = fs::remove_file(entry.path());
+            let path = entry.path();
+            if !keep.contains(&path.as_path()) {
+                let _ = fs::remove_file(path);
+            }
         }
     }
     Ok(())
@@ -812,7 +1652,9 @@ mod tests {
         let orphan = temp_dir.join("999-0.tmp");
         fs::write(&orphan, b"orphaned")?;
 
-        fs::write(&journal_path, format!("P\tCOPY\t{}\tsome/file.txt\n", orphan.display()))?;
+        let journal = SyncJournal::create(journal_path.clone())?;
+        journal.record_pending("COPY", &orphan, Path::new("some/file.txt"))?;
+        journal.keep()?;
 
         SyncJournal::recover(&journal_path, &temp_dir)?;
 
@@ -822,6 +1664,103 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_sync_journal_recovery_keeps_resumable_copy() -> io::Result<()> {
+        let dir = tempdir()?;
+        let journal_path = dir.path().join(JAN_JOURNAL_FILE);
+        let temp_dir = dir.path().join(JAN_TEMP_DIR);
+        fs::create_dir_all(&temp_dir)?;
+
+        let partial = temp_dir.join("999-0.tmp");
+        let orphan = temp_dir.join("999-1.tmp");
+        fs::write(&partial, b"first half")?;
+        fs::write(&orphan, b"orphaned")?;
+        let point = ResumePoint {
+            source_hash: Some(crate::hash::hash_bytes(b"first half, second half")),
+            source_size: 23,
+            source_mtime: UNIX_EPOCH - Duration::new(10, 5),
+            written: 10,
+            prefix_hash: crate::hash::hash_bytes(b"first half"),
+        };
+
+        let final_path = dir.path().join("big.bin");
+        let journal = SyncJournal::create(journal_path.clone())?;
+        journal.record_pending("COPY", &partial, &final_path)?;
+        journal.record_progress(&partial, &final_path, &point)?;
+        journal.record_pending("COPY", &orphan, &dir.path().join("other.bin"))?;
+        journal.keep()?;
+
+        let partials = SyncJournal::recover(&journal_path, &temp_dir)?;
+
+        assert_eq!(partials.len(), 1);
+        assert_eq!(partials[0].temp_path, partial);
+        assert_eq!(partials[0].final_path, final_path);
+        assert_eq!(partials[0].point, point);
+        assert!(partial.exists(), "Resumable temp should be kept");
+        assert!(!orphan.exists(), "Orphaned temp should be cleaned up");
+        assert!(!journal_path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resumable_copy_appends_to_verified_prefix() -> io::Result<()> {
+        let dir = tempdir()?;
+        let source = dir.path().join("source.bin");
+        let dest = dir.path().join("dest.bin");
+        let temp = dir.path().join("partial.tmp");
+        let content: Vec<u8> = (0..500_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&source, &content)?;
+        let expected = crate::hash::hash_bytes(&content);
+
+        // A prefix, plus bytes past the resume point that must be dropped
+        let mut partial = content[..200_000].to_vec();
+        partial.extend_from_slice(b"unsynced tail");
+        fs::write(&temp, &partial)?;
+        let point = ResumePoint {
+            source_hash: Some(expected.clone()),
+            source_size: content.len() as u64,
+            source_mtime: fs::metadata(&source)?.modified()?,
+            written: 200_000,
+            prefix_hash: crate::hash::hash_bytes(&content[..200_000]),
+        };
+
+        let mut checkpoints = 0;
+        let reused = atomic_resumable_copy(
+            &source,
+            &dest,
+            &temp,
+            Some(&point),
+            false,
+            Some(&expected),
+            &mut |_| {
+                checkpoints += 1;
+                Ok(())
+            },
+        )?;
+        assert_eq!(reused, 200_000);
+        assert_eq!(checkpoints, 0);
+        assert_eq!(fs::read(&dest)?, content);
+        assert!(!temp.exists());
+
+        // A prefix that doesn't match its hash is rewritten from scratch
+        fs::write(&temp, vec![0u8; 200_000])?;
+        fs::remove_file(&dest)?;
+        let reused = atomic_resumable_copy(
+            &source,
+            &dest,
+            &temp,
+            Some(&point),
+            false,
+            Some(&expected),
+            &mut |_| Ok(()),
+        )?;
+        assert_eq!(reused, 0);
+        assert_eq!(fs::read(&dest)?, content);
+
+        Ok(())
+    }
+
     #[test]
     fn test_sync_journal_recovery_committed_not_cleaned() -> io::Result<()> {
         let dir = tempdir()?;
@@ -830,8 +1769,10 @@ mod tests {
         fs::create_dir_all(&temp_dir)?;
 
         // A completed operation should not try to remove already-renamed temp
-        let content = "P\tCOPY\t/tmp/fake.tmp\tfile.txt\nC\tCOPY\t/tmp/fake.tmp\tfile.txt\n";
-        fs::write(&journal_path, content)?;
+        let journal = SyncJournal::create(journal_path.clone())?;
+        journal.record_pending("COPY", Path::new("/tmp/fake.tmp"), Path::new("file.txt"))?;
+        journal.record_committed("COPY", Path::new("/tmp/fake.tmp"), Path::new("file.txt"))?;
+        journal.keep()?;
 
         SyncJournal::recover(&journal_path, &temp_dir)?;
 
@@ -840,6 +1781,260 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_sync_journal_recovery_undoes_move() -> io::Result<()> {
+        let dir = tempdir()?;
+        let journal_path = dir.path().join(JAN_JOURNAL_FILE);
+        let temp_dir = dir.path().join(JAN_TEMP_DIR);
+        let original = dir.path().join("old.txt");
+        let moved = dir.path().join("new.txt");
+
+        // Crash after the rename but before the commit record
+        fs::write(&moved, b"moved")?;
+        let journal = SyncJournal::create(journal_path.clone())?;
+        journal.record_pending("MOVE", &original, &moved)?;
+        journal.keep()?;
+
+        SyncJournal::recover(&journal_path, &temp_dir)?;
+
+        assert_eq!(fs::read(&original)?, b"moved", "Move should be undone");
+        assert!(!moved.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_journal_recovery_merges_half_moved_dir() -> io::Result<()> {
+        let dir = tempdir()?;
+        let journal_path = dir.path().join(JAN_JOURNAL_FILE);
+        let temp_dir = dir.path().join(JAN_TEMP_DIR);
+        let original = dir.path().join("old");
+        let moved = dir.path().join("new");
+
+        // Cross-device fallback interrupted after moving one of two files
+        fs::create_dir_all(&original)?;
+        fs::create_dir_all(moved.join("sub"))?;
+        fs::write(original.join("a.txt"), b"a")?;
+        fs::write(moved.join("sub/b.txt"), b"b")?;
+        let journal = SyncJournal::create(journal_path.clone())?;
+        journal.record_pending("MOVE", &original, &moved)?;
+        journal.keep()?;
+
+        SyncJournal::recover(&journal_path, &temp_dir)?;
+
+        assert_eq!(fs::read(original.join("a.txt"))?, b"a");
+        assert_eq!(fs::read(original.join("sub/b.txt"))?, b"b");
+        assert!(!moved.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_journal_abort_rejoins_dir_split_by_failed_move() -> io::Result<()> {
+        let dir = tempdir()?;
+        let temp_dir = dir.path().join(JAN_TEMP_DIR);
+        let original = dir.path().join("old");
+        let moved = dir.path().join("new");
+        fs::create_dir_all(original.join("sub"))?;
+        for name in ["a.txt", "b.txt", "sub/c.txt"] {
+            fs::write(original.join(name), name)?;
+        }
+
+        let journal = SyncJournal::create(dir.path().join(JAN_JOURNAL_FILE))?;
+        journal.record_pending("MOVE", &original, &moved)?;
+        // The fallback fails on its second file
+        let mut moves = 0;
+        let failed = move_tree(&original, &moved, &mut |from, to| {
+            moves += 1;
+            match moves {
+                2 => Err(io::Error::other("disk full")),
+                _ => fs::rename(from, to),
+            }
+        });
+        assert!(failed.is_err());
+        assert!(moved.exists(), "Some files should have moved");
+
+        journal.abort(&temp_dir)?;
+
+        for name in ["a.txt", "b.txt", "sub/c.txt"] {
+            assert_eq!(fs::read(original.join(name))?, name.as_bytes());
+        }
+        assert!(!moved.exists());
+        assert!(!dir.path().join(JAN_JOURNAL_FILE).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_journal_inspect_and_discard() -> io::Result<()> {
+        let dir = tempdir()?;
+        let journal_path = dir.path().join(JAN_JOURNAL_FILE);
+        let temp_dir = dir.path().join(JAN_TEMP_DIR);
+        fs::create_dir_all(&temp_dir)?;
+        let done = temp_dir.join("1-0.tmp");
+        let partial = temp_dir.join("1-1.tmp");
+        let orphan = temp_dir.join("1-2.tmp");
+        fs::write(&partial, b"first half")?;
+        fs::write(&orphan, b"orphaned")?;
+        let point = ResumePoint {
+            source_hash: None,
+            source_size: 20,
+            source_mtime: UNIX_EPOCH,
+            written: 10,
+            prefix_hash: crate::hash::hash_bytes(b"first half"),
+        };
+
+        let journal = SyncJournal::create(journal_path.clone())?;
+        journal.record_pending("COPY", &done, &dir.path().join("a.txt"))?;
+        journal.record_committed("COPY", &done, &dir.path().join("a.txt"))?;
+        journal.record_pending("COPY", &partial, &dir.path().join("b.bin"))?;
+        journal.record_progress(&partial, &dir.path().join("b.bin"), &point)?;
+        journal.keep()?;
+
+        let report = SyncJournal::inspect(&journal_path, &temp_dir)?;
+        let states: Vec<_> =
+            report.entries.iter().map(|e| (e.committed, e.progress.is_some())).collect();
+        assert_eq!(states, [(true, false), (false, true)]);
+        assert_eq!(report.orphans, [orphan]);
+        assert!(journal_path.exists(), "Inspecting changes nothing");
+
+        SyncJournal::discard(&journal_path, &temp_dir)?;
+        assert!(!journal_path.exists());
+        assert!(!temp_dir.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_utc() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(format_utc(time), "2024-02-29 12:34:56 UTC");
+        assert_eq!(format_utc(UNIX_EPOCH), "1970-01-01 00:00:00 UTC");
+        assert_eq!(parse_utc("2024-02-29 12:34:56 UTC"), Some(time));
+        assert_eq!(parse_utc("1970-01-01 00:00:00 UTC"), Some(UNIX_EPOCH));
+        assert_eq!(parse_utc("2024-13-01 00:00:00 UTC"), None);
+    }
+
+    #[test]
+    fn test_escape_path_round_trips_awkward_names() {
+        let names =
+            ["plain.txt", "tab\there", "line\nbreak\r", "back\\slash\\t", "bell\x07", "żółw"];
+        for name in names {
+            let field = escape_path(Path::new(name));
+            assert!(!field.contains(['\t', '\n']), "{field:?}");
+            assert_eq!(unescape_path(&field).as_deref(), Some(Path::new(name)));
+        }
+        assert_eq!(unescape_path("dangling\\"), None);
+        assert_eq!(unescape_path("bad\\x4"), None);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let raw = Path::new(std::ffi::OsStr::from_bytes(b"latin1-\xe9t\xe9"));
+            let field = escape_path(raw);
+            assert_eq!(field, "latin1-\\xe9t\\xe9");
+            assert_eq!(unescape_path(&field).as_deref(), Some(raw));
+        }
+    }
+
+    #[test]
+    fn test_sync_journal_ignores_torn_last_record() -> io::Result<()> {
+        let dir = tempdir()?;
+        let journal_path = dir.path().join(JAN_JOURNAL_FILE);
+        let temp_dir = dir.path().join(JAN_TEMP_DIR);
+        let original = dir.path().join("old\tname");
+        let moved = dir.path().join("new\nname");
+        fs::write(&moved, b"moved")?;
+
+        let journal = SyncJournal::create(journal_path.clone())?;
+        journal.record_pending("MOVE", &original, &moved)?;
+        journal.keep()?;
+        // Crash halfway through writing the next record
+        let mut file = OpenOptions::new().append(true).open(&journal_path)?;
+        file.write_all(b"P\tCOPY\t/tmp/x")?;
+
+        SyncJournal::recover(&journal_path, &temp_dir)?;
+
+        assert_eq!(fs::read(&original)?, b"moved");
+        assert!(!journal_path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_journal_corruption_is_an_error() -> io::Result<()> {
+        let dir = tempdir()?;
+        let journal_path = dir.path().join(JAN_JOURNAL_FILE);
+        let temp_dir = dir.path().join(JAN_TEMP_DIR);
+
+        let journal = SyncJournal::create(journal_path.clone())?;
+        journal.record_pending("COPY", Path::new("/tmp/a.tmp"), Path::new("a.txt"))?;
+        journal.record_pending("COPY", Path::new("/tmp/b.tmp"), Path::new("b.txt"))?;
+        journal.keep()?;
+        let content = fs::read_to_string(&journal_path)?;
+        fs::write(&journal_path, content.replacen("a.txt", "x.txt", 1))?;
+
+        let err = SyncJournal::recover(&journal_path, &temp_dir).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("corrupt at line 2"), "{err}");
+        assert!(journal_path.exists(), "A corrupt journal is left alone");
+
+        // Journals from before the format was versioned are refused too
+        fs::write(&journal_path, "P\tCOPY\t/tmp/a.tmp\ta.txt\n")?;
+        let err = SyncJournal::recover(&journal_path, &temp_dir).unwrap_err();
+        assert!(err.to_string().contains("not a jan-journal file"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_file() -> io::Result<()> {
+        let dir = tempdir()?;
+        let temp_dir = dir.path().join(JAN_TEMP_DIR);
+        fs::create_dir_all(&temp_dir)?;
+        let source = dir.path().join("a.txt");
+        let dest = dir.path().join("b.txt");
+        fs::write(&source, b"move me")?;
+
+        move_file(&source, &dest, &generate_temp_path(&temp_dir), false, None)?;
+
+        assert!(!source.exists());
+        assert_eq!(fs::read(&dest)?, b"move me");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_atomic_hard_link() -> io::Result<()> {
+        let dir = tempdir()?;
+        let existing = dir.path().join("existing.txt");
+        let dest = dir.path().join("linked.txt");
+        fs::write(&existing, b"shared")?;
+
+        atomic_hard_link(&existing, &dest, &dir.path().join("link.tmp"))?;
+
+        assert_eq!(fs::read(&dest)?, b"shared");
+        assert!(!dir.path().join("link.tmp").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_atomic_reflink_leaves_no_temp() -> io::Result<()> {
+        let dir = tempdir()?;
+        let existing = dir.path().join("existing.txt");
+        let dest = dir.path().join("clone.txt");
+        let temp_path = dir.path().join("clone.tmp");
+        fs::write(&existing, b"cloned")?;
+
+        // Most test filesystems can't clone; either outcome must be clean
+        if atomic_reflink(&existing, &dest, &temp_path)? {
+            assert_eq!(fs::read(&dest)?, b"cloned");
+        } else {
+            assert!(!dest.exists());
+        }
+        assert!(!temp_path.exists());
+
+        Ok(())
+    }
+
     #[test]
     fn test_generate_temp_path_uniqueness() {
         let dir = Path::new("/tmp/test");
@@ -1,17 +1,46 @@
 //! A file sync tool that refuses to waste your time.
 
+pub mod baseline;
+pub mod chunk;
 pub mod core;
+pub mod delta;
+pub mod filter;
 pub mod hash;
+pub mod index;
 pub mod io;
+pub mod lock;
+pub mod plan;
+pub mod remote;
+pub mod rollback;
+pub mod snapshot;
+pub mod trash;
+pub mod wire;
 
+pub use baseline::{find_conflicts, plan_two_way, sync_two_way, Baseline, Conflict, TwoWayPlan};
+pub use chunk::{atomic_chunked_copy, Chunk, ChunkStore, ChunkerConfig};
 pub use core::{
-    diff_scans, scan_directory, scan_directory_with_excludes, sync_changes, DiffResult, FileMeta,
-    ScanResult, SyncOptions,
+    diff_scans, diff_scans_with_options, resume_sync, scan_directory, scan_directory_with_excludes,
+    scan_directory_with_options, sync_changes, ConflictPolicy, DiffOptions, DiffResult, DirMeta,
+    DirRename, FileConflict, FileMeta, LocalCopyMode, Resolution, ScanOptions, ScanResult,
+    SymlinkMode, SyncOptions,
 };
+pub use delta::{atomic_delta_copy, DeltaOp, DeltaStats, Signature};
+pub use filter::{Filter, TreeFilter};
 pub use hash::{hash_bytes, hash_file, ContentHash, Hasher};
+pub use index::HashIndex;
 pub use io::{
-    atomic_copy_file_with_metadata, fsync_directory, generate_temp_path, AtomicWriter, SyncJournal,
-    JAN_JOURNAL_FILE, JAN_TEMP_DIR,
+    atomic_copy_file_with_metadata, atomic_resumable_copy, fsync_directory, generate_temp_path,
+    AtomicWriter, JournalError, PartialCopy, ResumePoint, SyncJournal, JAN_BASELINE_FILE,
+    JAN_INCOMING_DIR, JAN_INDEX_FILE, JAN_JOURNAL_FILE, JAN_TEMP_DIR, JAN_TRASH_DIR,
 };
+pub use lock::{DestLock, LockError};
+pub use plan::{SavedPlan, SyncPlan};
+pub use remote::{RemoteSession, RemoteSpec};
+pub use snapshot::{
+    latest_snapshot, plan_prune, plan_snapshot, remove_snapshot, snapshots, space_freed,
+    take_snapshot, KeepPolicy, PruneDecision, Snapshot, SnapshotPlan, SnapshotStats, SpaceFreed,
+};
+pub use trash::{Retention, Trash, TrashRun};
+pub use wire::{Hello, WireError};
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
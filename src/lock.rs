@@ -0,0 +1,312 @@
+//! Advisory lock keeping two syncs out of the same destination
+//!
+//! A sync holds `.jan-lock` in the destination root from before it touches
+//! the journal until it is done. What keeps others out is a kernel lock on
+//! the open file (`flock` on Unix, an unshared handle on Windows), which goes
+//! away with its process however that ends, so a lock is never stale. The
+//! holder's PID, host name and start time are written into the file only to
+//! tell others who has it; a crash can leave that text behind, and the next
+//! sync simply takes the lock.
+
+use crate::io::{format_time, format_utc, parse_time, remove_file_safe, JAN_LOCK_FILE};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+/// How often a waiting sync checks the lock again
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Who holds a destination's lock
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub host: String,
+    pub started: SystemTime,
+}
+
+impl LockHolder {
+    fn current() -> Self {
+        Self {
+            pid: std::process::id(),
+            host: hostname(),
+            started: SystemTime::now(),
+        }
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        let mut pid = None;
+        let mut host = None;
+        let mut started = None;
+        for line in text.lines() {
+            match line.split_once('=')? {
+                ("pid", value) => pid = value.parse().ok(),
+                ("host", value) => host = Some(value.to_string()),
+                ("started", value) => started = parse_time(value),
+                _ => {},
+            }
+        }
+        Some(Self {
+            pid: pid?,
+            host: host?,
+            started: started?,
+        })
+    }
+
+    fn to_text(&self) -> String {
+        format!("pid={}\nhost={}\nstarted={}\n", self.pid, self.host, format_time(self.started))
+    }
+
+    /// Whether the holder is known to be gone, which only a holder on this
+    /// host ever is
+    pub fn is_stale(&self) -> bool {
+        self.host == hostname() && !process_alive(self.pid)
+    }
+}
+
+impl fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "process {} on {}, since {}", self.pid, self.host, format_utc(self.started))
+    }
+}
+
+/// Errors taking a destination's lock
+#[derive(Error, Debug)]
+pub enum LockError {
+    #[error(
+        "{} is locked by {}",
+        dest(path),
+        holder.as_ref().map_or("an unknown process".to_string(), |h| h.to_string())
+    )]
+    Held {
+        path: PathBuf,
+        holder: Option<LockHolder>,
+    },
+
+    #[error("Can't lock {}", dest(path))]
+    Io { path: PathBuf, source: io::Error },
+}
+
+fn dest(lock_path: &Path) -> std::path::Display<'_> {
+    lock_path.parent().unwrap_or(lock_path).display()
+}
+
+/// The lock on a destination, released when dropped
+#[derive(Debug)]
+pub struct DestLock {
+    path: PathBuf,
+    /// The locked `.jan-lock`; closing it releases the lock
+    file: Option<File>,
+}
+
+impl DestLock {
+    /// Take the lock on `dest_root`, or fail naming whoever holds it
+    pub fn try_acquire(dest_root: &Path) -> Result<Self, LockError> {
+        let path = dest_root.join(JAN_LOCK_FILE);
+        let io_error = |source| LockError::Io { path: path.clone(), source };
+
+        loop {
+            let Some(mut file) = open_locked(&path).map_err(io_error)? else {
+                let holder = Self::holder(dest_root);
+                return Err(LockError::Held { path, holder });
+            };
+            // Its last holder may have removed it just as we opened it
+            if !is_at(&file, &path).map_err(io_error)? {
+                continue;
+            }
+            file.set_len(0)
+                .and_then(|()| file.write_all(LockHolder::current().to_text().as_bytes()))
+                .map_err(io_error)?;
+            return Ok(Self { path, file: Some(file) });
+        }
+    }
+
+    /// Take the lock on `dest_root`, waiting for it to be released if `wait`
+    pub fn acquire(dest_root: &Path, wait: bool) -> Result<Self, LockError> {
+        loop {
+            match Self::try_acquire(dest_root) {
+                Err(LockError::Held { .. }) if wait => thread::sleep(POLL_INTERVAL),
+                result => return result,
+            }
+        }
+    }
+
+    /// Who the lock file on `dest_root` names, if there is one
+    ///
+    /// It may name a holder that is gone; see [`LockHolder::is_stale`].
+    pub fn holder(dest_root: &Path) -> Option<LockHolder> {
+        let text = fs::read_to_string(dest_root.join(JAN_LOCK_FILE)).ok()?;
+        LockHolder::parse(&text)
+    }
+}
+
+impl Drop for DestLock {
+    fn drop(&mut self) {
+        // Removed while still locked, and only if it is still our file
+        #[cfg(unix)]
+        if let Some(file) = &self.file {
+            if is_at(file, &self.path).unwrap_or(false) {
+                let _ = remove_file_safe(&self.path);
+            }
+        }
+        drop(self.file.take());
+        // Elsewhere it can't be removed while open; whoever opens it first
+        // once it is closed holds it, and then removing it fails
+        #[cfg(not(unix))]
+        let _ = remove_file_safe(&self.path);
+    }
+}
+
+/// Open `path`, creating it, and lock it if nobody else has
+#[cfg(unix)]
+fn open_locked(path: &Path) -> io::Result<Option<File>> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    // SAFETY: the descriptor belongs to `file`, which outlives the call
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(Some(file));
+    }
+    match io::Error::last_os_error() {
+        e if e.raw_os_error() == Some(libc::EWOULDBLOCK) => Ok(None),
+        e => Err(e),
+    }
+}
+
+/// Open `path`, creating it, and lock it if nobody else has
+#[cfg(windows)]
+fn open_locked(path: &Path) -> io::Result<Option<File>> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    // ERROR_SHARING_VIOLATION: someone else has it open
+    const IN_USE: i32 = 32;
+    let opened = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .share_mode(0)
+        .open(path);
+    match opened {
+        Ok(file) => Ok(Some(file)),
+        Err(e) if e.raw_os_error() == Some(IN_USE) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether `file` is still the one at `path`
+#[cfg(unix)]
+fn is_at(file: &File, path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let ours = file.metadata()?;
+    match fs::metadata(path) {
+        Ok(there) => Ok(ours.dev() == there.dev() && ours.ino() == there.ino()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// An open lock file can't be removed or replaced here
+#[cfg(not(unix))]
+fn is_at(_file: &File, _path: &Path) -> io::Result<bool> {
+    Ok(true)
+}
+
+#[cfg(unix)]
+pub(crate) fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for its whole length
+    let rc = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+    if rc != 0 {
+        return "localhost".to_string();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "localhost".to_string())
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks that the process exists
+    let rc = unsafe { libc::kill(pid, 0) };
+    rc == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Without a way to ask, every holder is assumed alive
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_lock_is_exclusive_until_dropped() {
+        let dir = tempdir().unwrap();
+        let lock = DestLock::try_acquire(dir.path()).unwrap();
+
+        let err = DestLock::try_acquire(dir.path()).unwrap_err();
+        let LockError::Held { holder: Some(holder), .. } = &err else {
+            panic!("{err}");
+        };
+        assert_eq!(holder.pid, std::process::id());
+        assert!(!holder.is_stale());
+        assert!(err.to_string().contains(&format!("process {}", holder.pid)), "{err}");
+        assert_eq!(DestLock::holder(dir.path()).as_ref(), Some(holder));
+
+        drop(lock);
+        assert!(!dir.path().join(JAN_LOCK_FILE).exists());
+        DestLock::try_acquire(dir.path()).unwrap();
+    }
+
+    #[test]
+    fn test_left_over_lock_file_is_taken_over() {
+        let dir = tempdir().unwrap();
+        // A crashed holder's text, from a host whose processes can't be checked
+        let gone = LockHolder {
+            pid: 1 << 30,
+            host: "elsewhere".to_string(),
+            started: SystemTime::now(),
+        };
+        assert!(!gone.is_stale());
+        fs::write(dir.path().join(JAN_LOCK_FILE), gone.to_text()).unwrap();
+
+        let _lock = DestLock::try_acquire(dir.path()).unwrap();
+        assert_eq!(DestLock::holder(dir.path()).unwrap().pid, std::process::id());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dropping_a_lock_leaves_a_newer_holder_alone() {
+        let dir = tempdir().unwrap();
+        let old = DestLock::try_acquire(dir.path()).unwrap();
+        // Someone deletes the file by hand and another sync takes the lock
+        fs::remove_file(dir.path().join(JAN_LOCK_FILE)).unwrap();
+        let new = DestLock::try_acquire(dir.path()).unwrap();
+
+        drop(old);
+        assert!(dir.path().join(JAN_LOCK_FILE).exists());
+        assert!(matches!(DestLock::try_acquire(dir.path()), Err(LockError::Held { .. })));
+        drop(new);
+        assert!(!dir.path().join(JAN_LOCK_FILE).exists());
+    }
+}
@@ -1,24 +1,45 @@
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{Context, Result};
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use owo_colors::OwoColorize;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime};
 
-use janice::{diff_scans, scan_directory_with_excludes, sync_changes, SyncOptions};
+use janice::filter::{EXCLUDE_SOURCE, INCLUDE_SOURCE};
+use janice::io::{escape_path, JournalReport, JAN_JOURNAL_FILE, JAN_TEMP_DIR, JAN_TRASH_DIR};
+use janice::lock::{DestLock, LockError};
+use janice::plan::SavedPlan;
+use janice::remote::serve;
+use janice::rollback::{rollback, undo_state, UndoState};
+use janice::snapshot::{plan_prune, remove_snapshot, snapshots, space_freed, KeepPolicy, Snapshot};
+use janice::trash::{run_name, Retention, Trash};
+use janice::{
+    diff_scans_with_options, find_conflicts, plan_snapshot, plan_two_way, resume_sync,
+    scan_directory_with_options, sync_changes, sync_two_way, take_snapshot, Baseline,
+    ConflictPolicy, DiffOptions, DiffResult, Filter, LocalCopyMode, RemoteSession, RemoteSpec,
+    Resolution, ScanOptions, SymlinkMode, SyncJournal, SyncOptions,
+};
 
 #[derive(Parser)]
 #[command(
     name = "jan",
     version,
-    about = "Beautifully fast, simple & reliable file syncing"
+    about = "Beautifully fast, simple & reliable file syncing",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Source directory
-    source: PathBuf,
+    #[arg(required_unless_present = "server")]
+    source: Option<PathBuf>,
 
-    /// Destination directory
-    dest: PathBuf,
+    /// Destination directory, or HOST:PATH or USER@HOST:PATH to sync over ssh
+    #[arg(required_unless_present = "server")]
+    dest: Option<PathBuf>,
 
     /// Dry run (show changes without applying)
     #[arg(short = 'n', long)]
@@ -28,6 +49,14 @@ struct Cli {
     #[arg(short, long)]
     delete: bool,
 
+    /// With -d, also delete destination files the filter rules exclude
+    #[arg(long, requires = "delete")]
+    delete_excluded: bool,
+
+    /// Never delete destination paths matching PATTERN (can be used multiple times)
+    #[arg(long, value_name = "PATTERN")]
+    protect: Vec<String>,
+
     /// Skip confirmation prompt
     #[arg(short, long)]
     yes: bool,
@@ -44,13 +73,414 @@ struct Cli {
     #[arg(short = 'j', long, value_name = "THREADS")]
     threads: Option<usize>,
 
-    /// Exclude files matching glob patterns (can be used multiple times)
+    #[command(flatten)]
+    rules: FilterArgs,
+
+    /// Verify file integrity after copying (BLAKE3 hash check)
+    #[arg(long)]
+    verify: bool,
+
+    /// Ignore the stored hash index and re-hash every file
+    #[arg(long)]
+    rehash: bool,
+
+    /// Keep a hash index in SOURCE too, so unchanged files aren't read again
+    /// next run (jan otherwise never writes to SOURCE)
+    #[arg(long)]
+    index_source: bool,
+
+    /// Skip hashing files whose size and mtime already match the destination
+    #[arg(long)]
+    quick: bool,
+
+    /// Rebuild modified files from the blocks of the old copy that still match
+    #[arg(long)]
+    delta: bool,
+
+    /// Build new and modified files from chunks already anywhere in DEST
+    #[arg(long)]
+    chunks: bool,
+
+    /// How to duplicate content that already exists in the destination
+    #[arg(long, value_enum, value_name = "MODE", default_value = "copy")]
+    local_copy: LocalCopyArg,
+
+    /// How to handle symlinks in the source
+    #[arg(long, value_enum, value_name = "MODE", default_value = "preserve")]
+    links: LinksArg,
+
+    /// Remote shell used to reach HOST:PATH destinations
+    #[arg(long, value_name = "COMMAND", default_value = "ssh")]
+    rsh: String,
+
+    /// Name or path of jan on the remote host
+    #[arg(long, value_name = "PROGRAM", default_value = "jan")]
+    remote_jan: String,
+
+    /// Keep everything the sync overwrites or deletes, and restore it all if
+    /// the sync fails; `jan rollback DEST` undoes it later
+    #[arg(long)]
+    transactional: bool,
+
+    /// Move files the sync deletes or overwrites to DEST/.jan-trash/TIME/
+    /// instead of losing them; `jan trash` lists and restores them
+    #[arg(long)]
+    backup: bool,
+
+    /// Like --backup, with the trash in DIR instead (outside DEST)
+    #[arg(long, value_name = "DIR")]
+    backup_dir: Option<PathBuf>,
+
+    /// After syncing, purge trash runs older than DAYS
+    #[arg(long, value_name = "DAYS")]
+    trash_keep_days: Option<u64>,
+
+    /// After syncing, purge the oldest trash runs until the trash fits in SIZE,
+    /// e.g. 500M or 2G
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    trash_max_size: Option<u64>,
+
+    /// How to settle files changed in DEST since the last sync that changed in
+    /// SOURCE too [default: source; with --bidirectional, leave them alone]
+    #[arg(long, value_enum, value_name = "POLICY")]
+    conflict: Option<ConflictArg>,
+
+    /// Sync both ways: what changed on either side since the last two-way
+    /// sync is copied to the other, deletes included (local directories only)
+    #[arg(long, conflicts_with_all = ["resume", "quick", "delete_excluded"])]
+    bidirectional: bool,
+
+    /// Finish an interrupted sync into DEST from its saved plan, with the
+    /// options it was started with, instead of scanning again
+    #[arg(long)]
+    resume: bool,
+
+    /// If another sync is running into DEST, wait for it to finish
+    #[arg(long, overrides_with = "no_wait")]
+    wait: bool,
+
+    /// If another sync is running into DEST, fail at once (the default)
+    #[arg(long, overrides_with = "wait")]
+    no_wait: bool,
+
+    /// Serve a remote client on stdin/stdout with ROOT as the destination
+    #[arg(long, value_name = "ROOT", exclusive = true)]
+    server: Option<PathBuf>,
+}
+
+/// Which source files take part, read back in order by [`build_filter`]
+#[derive(Args)]
+struct FilterArgs {
+    /// Exclude files matching PATTERN (can be used multiple times)
     #[arg(short, long, value_name = "PATTERN")]
     exclude: Vec<String>,
 
-    /// Verify file integrity after copying (BLAKE3 hash check)
+    /// Include files matching PATTERN even if a later rule excludes them
+    /// (rules apply in command-line order, first match wins)
+    #[arg(short = 'I', long, value_name = "PATTERN")]
+    include: Vec<String>,
+
+    /// Read filter rules ("+ PATTERN", "- PATTERN", ": NAME") from FILE
+    #[arg(long, value_name = "FILE")]
+    filter_from: Vec<String>,
+
+    /// Read more filter rules from every directory's NAME file
+    #[arg(long, value_name = "NAME")]
+    filter_merge: Vec<String>,
+
+    /// Honour .gitignore files and .git/info/exclude
+    #[arg(long)]
+    respect_gitignore: bool,
+
+    /// Honour per-directory ignore files with this name, e.g. .janignore
+    /// (can be used multiple times)
+    #[arg(long, value_name = "NAME")]
+    ignore_file: Vec<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Undo the last transactional sync into DEST, or one that was interrupted
+    Rollback {
+        /// Destination directory
+        dest: PathBuf,
+
+        /// Skip confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+    },
+
+    /// Inspect or settle what an interrupted sync left in DEST
+    Journal {
+        #[command(subcommand)]
+        action: JournalAction,
+    },
+
+    /// List, restore or purge what syncs with --backup kept from DEST
+    Trash {
+        #[command(subcommand)]
+        action: TrashAction,
+    },
+
+    /// Take a snapshot of SOURCE in DESTROOT, hard-linking every file the
+    /// latest snapshot there already has
+    Backup(BackupArgs),
+
+    /// Delete the snapshots in DESTROOT that no --keep rule keeps
+    Prune(PruneArgs),
+}
+
+#[derive(Args)]
+struct BackupArgs {
+    /// Source directory
+    source: PathBuf,
+
+    /// Directory holding the snapshots, one per backup
+    #[arg(value_name = "DESTROOT")]
+    dest_root: PathBuf,
+
+    /// Dry run (show what changed since the latest snapshot)
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+
+    /// Quiet mode (no progress)
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Verbose output
+    #[arg(short, long)]
+    verbose: bool,
+
+    #[command(flatten)]
+    rules: FilterArgs,
+
+    /// Verify copied files against their scanned hash
     #[arg(long)]
     verify: bool,
+
+    /// Ignore the stored hash index and re-hash every file
+    #[arg(long)]
+    rehash: bool,
+
+    /// Keep a hash index in SOURCE too, so unchanged files aren't read again
+    /// next run (jan otherwise never writes to SOURCE)
+    #[arg(long)]
+    index_source: bool,
+
+    /// How to handle symlinks in the source
+    #[arg(long, value_enum, value_name = "MODE", default_value = "preserve")]
+    links: LinksArg,
+
+    /// If another backup into DESTROOT is running, wait for it to finish
+    #[arg(long)]
+    wait: bool,
+}
+
+#[derive(Args)]
+struct PruneArgs {
+    /// Directory holding the snapshots
+    #[arg(value_name = "DESTROOT")]
+    dest_root: PathBuf,
+
+    /// Keep the N newest snapshots
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    keep_last: usize,
+
+    /// Keep the newest snapshot of each of the N latest hours that have one
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    keep_hourly: usize,
+
+    /// Keep the newest snapshot of each of the N latest days that have one
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    keep_daily: usize,
+
+    /// Keep the newest snapshot of each of the N latest weeks that have one
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    keep_weekly: usize,
+
+    /// Keep the newest snapshot of each of the N latest months that have one
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    keep_monthly: usize,
+
+    /// Keep the newest snapshot of each of the N latest years that have one
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    keep_yearly: usize,
+
+    /// Dry run (show what would be deleted and the space it frees)
+    #[arg(short = 'n', long)]
+    dry_run: bool,
+
+    /// Skip confirmation prompt
+    #[arg(short, long)]
+    yes: bool,
+
+    /// Quiet mode (no listing)
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// If a backup into DESTROOT is running, wait for it to finish
+    #[arg(long)]
+    wait: bool,
+}
+
+#[derive(Subcommand)]
+enum JournalAction {
+    /// List journaled operations, orphaned temp files and any saved plan
+    Show {
+        /// Destination directory
+        dest: PathBuf,
+
+        /// Print JSON instead
+        #[arg(long)]
+        json: bool,
+    },
+    /// Clean up as the next sync would: undo half-done moves, keep partial
+    /// copies that can resume, delete the other temp files
+    Recover {
+        /// Destination directory
+        dest: PathBuf,
+    },
+    /// Undo every unfinished operation and delete all temp files, or roll
+    /// back an interrupted transactional sync entirely
+    Rollback {
+        /// Destination directory
+        dest: PathBuf,
+
+        /// Skip confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Delete the journal and temp files without undoing anything
+    Discard {
+        /// Destination directory
+        dest: PathBuf,
+
+        /// Skip confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum TrashAction {
+    /// List the runs in the trash, or the files one of them kept
+    List {
+        /// Destination directory
+        dest: PathBuf,
+
+        /// Run to list the files of
+        run: Option<String>,
+
+        /// Trash directory, if the syncs used --backup-dir
+        #[arg(long, value_name = "DIR")]
+        backup_dir: Option<PathBuf>,
+    },
+    /// Put the files a run kept back into DEST, replacing what is there now
+    Restore {
+        /// Destination directory
+        dest: PathBuf,
+
+        /// Run to restore from
+        run: String,
+
+        /// Only restore these files and directories, relative to DEST
+        paths: Vec<PathBuf>,
+
+        /// Trash directory, if the syncs used --backup-dir
+        #[arg(long, value_name = "DIR")]
+        backup_dir: Option<PathBuf>,
+
+        /// Skip confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Delete runs beyond --keep-days or --max-size, or every run without either
+    Purge {
+        /// Destination directory
+        dest: PathBuf,
+
+        /// Delete runs older than DAYS
+        #[arg(long, value_name = "DAYS")]
+        keep_days: Option<u64>,
+
+        /// Delete the oldest runs until the trash fits in SIZE, e.g. 500M or 2G
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        max_size: Option<u64>,
+
+        /// Trash directory, if the syncs used --backup-dir
+        #[arg(long, value_name = "DIR")]
+        backup_dir: Option<PathBuf>,
+
+        /// Skip confirmation prompt
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LocalCopyArg {
+    /// Copy from the existing destination file
+    Copy,
+    /// Hard-link to the existing destination file
+    Hardlink,
+    /// Clone blocks where the filesystem supports it, else copy
+    Reflink,
+}
+
+impl From<LocalCopyArg> for LocalCopyMode {
+    fn from(arg: LocalCopyArg) -> Self {
+        match arg {
+            LocalCopyArg::Copy => LocalCopyMode::Copy,
+            LocalCopyArg::Hardlink => LocalCopyMode::Hardlink,
+            LocalCopyArg::Reflink => LocalCopyMode::Reflink,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ConflictArg {
+    /// Overwrite the destination's changes
+    Source,
+    /// Keep the destination's changes
+    Dest,
+    /// Keep whichever version was modified last
+    Newer,
+    /// Move the destination's version aside to NAME.conflict-HOST-DATE
+    KeepBoth,
+    /// Sync nothing if there are conflicts
+    Abort,
+}
+
+impl From<ConflictArg> for ConflictPolicy {
+    fn from(arg: ConflictArg) -> Self {
+        match arg {
+            ConflictArg::Source => ConflictPolicy::SourceWins,
+            ConflictArg::Dest => ConflictPolicy::DestWins,
+            ConflictArg::Newer => ConflictPolicy::Newer,
+            ConflictArg::KeepBoth => ConflictPolicy::KeepBoth,
+            ConflictArg::Abort => ConflictPolicy::Abort,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LinksArg {
+    /// Recreate symlinks as symlinks
+    Preserve,
+    /// Copy what symlinks point to
+    Follow,
+    /// Preserve symlinks, skipping absolute ones and ones leaving the source
+    Safe,
+}
+
+impl From<LinksArg> for SymlinkMode {
+    fn from(arg: LinksArg) -> Self {
+        match arg {
+            LinksArg::Preserve => SymlinkMode::Preserve,
+            LinksArg::Follow => SymlinkMode::Follow,
+            LinksArg::Safe => SymlinkMode::Safe,
+        }
+    }
 }
 
 fn main() {
@@ -61,7 +491,34 @@ fn main() {
 }
 
 fn run() -> Result<()> {
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Some(root) = &cli.server {
+        return serve(root);
+    }
+    match &cli.command {
+        Some(Command::Rollback { dest, yes }) => {
+            let _lock = lock_dest(dest, false, false)?;
+            return undo_sync(dest, *yes);
+        },
+        Some(Command::Journal { action }) => return journal(action),
+        Some(Command::Trash { action }) => return trash(action),
+        Some(Command::Backup(args)) => {
+            let matches = matches.subcommand_matches("backup").expect("backup was parsed");
+            return backup(args, matches);
+        },
+        Some(Command::Prune(args)) => return prune(args),
+        None => {},
+    }
+    let (Some(source), Some(dest)) = (cli.source.clone(), cli.dest.clone()) else {
+        unreachable!("clap requires SOURCE and DEST without --server");
+    };
+    let remote_spec = dest.to_str().and_then(RemoteSpec::parse);
+    let filter = build_filter(&matches)?;
+    let mut protect = Filter::new();
+    for pattern in &cli.protect {
+        protect.include(pattern, "--protect")?;
+    }
 
     // Configure thread pool if specified
     if let Some(t) = cli.threads {
@@ -72,50 +529,982 @@ fn run() -> Result<()> {
     }
 
     // Validate paths
-    if !cli.source.exists() {
-        anyhow::bail!("Source does not exist: {}", cli.source.display());
+    if !source.exists() {
+        anyhow::bail!("Source does not exist: {}", source.display());
+    }
+    if cli.resume && remote_spec.is_some() {
+        anyhow::bail!("--resume needs a local destination");
     }
-    if !cli.dest.exists() {
-        anyhow::bail!("Destination does not exist: {}", cli.dest.display());
+    // Hashes a quick scan leaves out are filled in by reading the files
+    if cli.quick && remote_spec.is_some() {
+        anyhow::bail!("--quick needs a local destination");
     }
+    if cli.bidirectional {
+        if remote_spec.is_some() {
+            anyhow::bail!("--bidirectional needs a local destination");
+        }
+        return sync_both_ways(&cli, &source, &dest, filter, protect);
+    }
+    let mut remote = match &remote_spec {
+        Some(spec) => Some(RemoteSession::connect(spec, &cli.rsh, &cli.remote_jan)?),
+        None if !dest.exists() => anyhow::bail!("Destination does not exist: {}", dest.display()),
+        None => None,
+    };
+
+    // Held until the sync is done, so no other run races this one in DEST
+    let _lock = match remote.as_mut() {
+        Some(session) => {
+            session.lock(cli.wait)?;
+            None
+        },
+        None => Some(lock_dest(&dest, cli.wait, cli.quiet)?),
+    };
+
+    let backup_dir = backup_dir(&cli, &[&dest], remote.is_some())?;
+
+    // A sync that didn't finish left its plan behind
+    if remote.is_none() {
+        let saved = SavedPlan::load(&dest)?;
+        if cli.resume {
+            let saved = saved
+                .with_context(|| format!("No interrupted sync to resume in {}", dest.display()))?;
+            return resume(&cli, &source, &dest, saved);
+        }
+        if let Some(saved) = saved {
+            if offer_resume(&cli, &source, &dest, &saved)? {
+                return resume(&cli, &source, &dest, saved);
+            }
+        }
+
+        // Not resuming a transactional sync means undoing it
+        let interrupted = undo_state(&dest) == UndoState::Interrupted;
+        if interrupted {
+            if cli.dry_run {
+                println!(
+                    "An interrupted transactional sync into {} will be rolled back first.",
+                    dest.display()
+                );
+            } else {
+                let stats = rollback(&dest)?;
+                if !cli.quiet {
+                    println!(
+                        "Rolled back an interrupted sync: {} restored, {} removed",
+                        stats.restored, stats.removed,
+                    );
+                }
+            }
+        } else if dest.join(JAN_JOURNAL_FILE).exists() && !cli.quiet {
+            println!(
+                "Cleaning up after an interrupted sync first (`jan journal show {}` lists it)",
+                dest.display(),
+            );
+        }
+    }
+
+    let scan_options = ScanOptions {
+        filter: filter.clone(),
+        exclude_patterns: Vec::new(),
+        respect_gitignore: cli.rules.respect_gitignore,
+        ignore_files: cli.rules.ignore_file.clone(),
+        use_index: true,
+        save_index: cli.index_source && !cli.dry_run,
+        rehash: cli.rehash,
+        symlinks: cli.links.into(),
+        quick: cli.quick,
+    };
 
     // Scan source
     if cli.verbose && !cli.quiet {
-        println!("Scanning: {}", cli.source.display());
+        println!("Scanning: {}", source.display());
     }
-    let src = scan_directory_with_excludes(&cli.source, &cli.exclude)?;
+    let src = scan_directory_with_options(&source, &scan_options)?;
 
     if cli.verbose && !cli.quiet {
         println!("{} files, {}", src.files.len(), format_bytes(src.total_size()));
+        print_excluded(&src.excluded);
     }
 
     // Scan destination
     if cli.verbose && !cli.quiet {
-        println!("Scanning: {}", cli.dest.display());
+        println!("Scanning: {}", dest.display());
     }
-    let dst = scan_directory_with_excludes(&cli.dest, &cli.exclude)?;
+    // The destination is always seen as-is, links included. With
+    // --delete-excluded it is not filtered at all, so excluded files show up
+    // as removed.
+    let dest_options = if cli.delete_excluded {
+        ScanOptions {
+            symlinks: SymlinkMode::Preserve,
+            use_index: true,
+            save_index: !cli.dry_run,
+            rehash: cli.rehash,
+            quick: cli.quick,
+            ..ScanOptions::default()
+        }
+    } else {
+        ScanOptions {
+            symlinks: SymlinkMode::Preserve,
+            save_index: !cli.dry_run,
+            ..scan_options.clone()
+        }
+    };
+    let dst = match remote.as_mut() {
+        Some(session) => session.scan(&dest_options)?,
+        None => scan_directory_with_options(&dest, &dest_options)?,
+    };
 
     if cli.verbose && !cli.quiet {
         println!("{} files, {}", dst.files.len(), format_bytes(dst.total_size()));
+        print_excluded(&dst.excluded);
     }
 
     // Compute diff
-    let diff = diff_scans(&src, &dst)?;
+    let mut diff = diff_scans_with_options(&src, &dst, &DiffOptions { protect })?;
+
+    // Without a baseline from an earlier sync, there is no telling what
+    // changed in DEST since
+    let baseline = match remote {
+        Some(_) => None,
+        None => Baseline::load(&source, &dest)?,
+    };
+    let policy = cli.conflict.map_or(ConflictPolicy::SourceWins, Into::into);
+    if let Some(baseline) = &baseline {
+        find_conflicts(&mut diff, &dst, baseline, policy);
+    }
+    if policy == ConflictPolicy::Abort && !diff.conflicts.is_empty() {
+        for conflict in &diff.conflicts {
+            println!("{} {}", "Conflict:".red(), conflict.source.path.display());
+        }
+        anyhow::bail!(
+            "{} files changed in {} since the last sync; nothing was synced",
+            diff.conflicts.len(),
+            dest.display(),
+        );
+    }
 
     // Check if there are any changes
-    let changes = diff.added.len() + diff.modified.len() + diff.renamed.len();
-    if changes == 0 && (!cli.delete || diff.removed.is_empty()) {
+    let changes = diff.added.len()
+        + diff.modified.len()
+        + diff.renamed.len()
+        + diff.copied.len()
+        + diff.renamed_dirs.len()
+        + diff.added_dirs.len()
+        + diff.modified_dirs.len()
+        + diff.conflicts.len();
+    if changes == 0 && (!cli.delete || (diff.removed.is_empty() && diff.removed_dirs.is_empty())) {
+        // So changes made in DEST from now on are noticed
+        if remote.is_none() && !cli.dry_run {
+            Baseline::after_sync(&src, &dst, &diff, false, baseline.as_ref())?
+                .save_in(&dest)
+                .context("Can't save the baseline")?;
+            purge_trash(&cli, &dest)?;
+        }
+        if !cli.quiet {
+            println!("In sync");
+        }
+        return Ok(());
+    }
+
+    let sync_options = SyncOptions {
+        delete_removed: cli.delete,
+        preserve_timestamps: true,
+        verify_after_copy: cli.verify,
+        local_copy: cli.local_copy.into(),
+        filter: if cli.delete_excluded {
+            Filter::new()
+        } else {
+            filter
+        },
+        delta: cli.delta,
+        chunks: cli.chunks,
+        transactional: cli.transactional,
+        backup_dir,
+    };
+    apply(&cli, &diff, cli.delete, || match remote {
+        Some(mut session) => {
+            session.sync(&source, &src, &diff, &sync_options)?;
+            session.close()
+        },
+        None => {
+            sync_changes(&source, &dest, &diff, &sync_options)?;
+            Baseline::after_sync(&src, &dst, &diff, cli.delete, baseline.as_ref())?
+                .save_in(&dest)
+                .context("Can't save the baseline")?;
+            purge_trash(&cli, &dest)
+        },
+    })
+}
+
+/// Carry what changed in either tree since their last two-way sync to the other
+fn sync_both_ways(cli: &Cli, a: &Path, b: &Path, filter: Filter, protect: Filter) -> Result<()> {
+    if !b.exists() {
+        anyhow::bail!("Destination does not exist: {}", b.display());
+    }
+    // Both trees are written to, so both are locked
+    let _locks = (lock_dest(a, cli.wait, cli.quiet)?, lock_dest(b, cli.wait, cli.quiet)?);
+    let backup_dir = backup_dir(cli, &[a, b], false)?;
+    for root in [a, b] {
+        if undo_state(root) == UndoState::Interrupted {
+            anyhow::bail!(
+                "A transactional sync into {} was interrupted; `jan rollback {}` undoes it",
+                root.display(),
+                root.display(),
+            );
+        }
+    }
+
+    let scan_options = ScanOptions {
+        filter: filter.clone(),
+        exclude_patterns: Vec::new(),
+        respect_gitignore: cli.rules.respect_gitignore,
+        ignore_files: cli.rules.ignore_file.clone(),
+        use_index: true,
+        // Both trees are synced into, so both keep their index
+        save_index: !cli.dry_run,
+        rehash: cli.rehash,
+        symlinks: cli.links.into(),
+        quick: false,
+    };
+    let mut scans = Vec::with_capacity(2);
+    for root in [a, b] {
+        if cli.verbose && !cli.quiet {
+            println!("Scanning: {}", root.display());
+        }
+        let scan = scan_directory_with_options(root, &scan_options)?;
+        if cli.verbose && !cli.quiet {
+            println!("{} files, {}", scan.files.len(), format_bytes(scan.total_size()));
+            print_excluded(&scan.excluded);
+        }
+        scans.push(scan);
+    }
+
+    let baseline = Baseline::load(a, b)?;
+    if baseline.is_none() && !cli.quiet {
+        println!("First two-way sync of these trees: merging them, deleting nothing");
+    }
+    let policy = cli.conflict.map(ConflictPolicy::from);
+    let options = DiffOptions { protect };
+    let plan = plan_two_way(&scans[0], &scans[1], baseline.as_ref(), &options, policy)?;
+
+    if !cli.quiet {
+        let (a_name, b_name) = (a.display().to_string(), b.display().to_string());
+        for conflict in &plan.conflicts {
+            let what = match (&conflict.a, &conflict.b) {
+                (Some(_), Some(_)) => "changed on both sides",
+                (Some(_), None) => "deleted in the second tree, changed in the first",
+                (None, _) => "deleted in the first tree, changed in the second",
+            };
+            println!(
+                "{} {} ({what}; {})",
+                "Conflict:".red(),
+                conflict.path.display(),
+                settled(&conflict.resolution, &a_name, &b_name),
+            );
+        }
+    }
+    if policy == Some(ConflictPolicy::Abort) && !plan.conflicts.is_empty() {
+        anyhow::bail!(
+            "{} files changed on both sides since the last sync; nothing was synced",
+            plan.conflicts.len(),
+        );
+    }
+    // Conflicts left alone keep the trees apart, so the run can't pass for a
+    // clean one, and is asked about again next time
+    let unresolved =
+        plan.conflicts.iter().filter(|c| c.resolution == Resolution::Unresolved).count();
+    let left_alone = || match unresolved {
+        0 => Ok(()),
+        n => anyhow::bail!(
+            "{n} files changed on both sides were left alone; --conflict settles them"
+        ),
+    };
+    if plan.is_empty() {
+        if !cli.dry_run {
+            sync_two_way(&plan, &SyncOptions::default())?;
+            purge_trash(cli, a)?;
+            purge_trash(cli, b)?;
+        }
+        left_alone()?;
         if !cli.quiet {
             println!("In sync");
         }
         return Ok(());
     }
 
+    if !cli.quiet {
+        for (from, to, diff) in [(a, b, &plan.a_to_b), (b, a, &plan.b_to_a)] {
+            print!("{} -> {}: ", from.display(), to.display());
+            print_diff_summary(diff, true, cli.verbose);
+        }
+    }
+    let sync_options = SyncOptions {
+        delete_removed: true,
+        preserve_timestamps: true,
+        verify_after_copy: cli.verify,
+        local_copy: cli.local_copy.into(),
+        filter,
+        delta: cli.delta,
+        chunks: cli.chunks,
+        transactional: cli.transactional,
+        backup_dir,
+    };
+    run_confirmed(cli, &[&plan.a_to_b, &plan.b_to_a], || {
+        sync_two_way(&plan, &sync_options)?;
+        purge_trash(cli, a)?;
+        purge_trash(cli, b)
+    })?;
+    left_alone()
+}
+
+/// Where this sync keeps the files it displaces from `dests`, if anywhere
+///
+/// The default trash is named relative to the destination, so each of two
+/// trees and a remote one get their own.
+fn backup_dir(cli: &Cli, dests: &[&Path], remote: bool) -> Result<Option<PathBuf>> {
+    let Some(dir) = &cli.backup_dir else {
+        if !cli.backup {
+            return Ok(None);
+        }
+        let run = match dests.first() {
+            Some(dest) if !remote => Trash::of(dest).new_run(),
+            _ => run_name(SystemTime::now()),
+        };
+        return Ok(Some(Path::new(JAN_TRASH_DIR).join(run)));
+    };
+    if remote {
+        anyhow::bail!("--backup-dir needs a local destination; --backup keeps the trash in DEST");
+    }
+    let dir = std::path::absolute(dir)?;
+    let resolved = fs::canonicalize(&dir).unwrap_or_else(|_| dir.clone());
+    for dest in dests {
+        if fs::canonicalize(dest).is_ok_and(|dest| resolved.starts_with(dest)) {
+            anyhow::bail!(
+                "--backup-dir can't be inside {}; --backup keeps the trash there",
+                dest.display(),
+            );
+        }
+    }
+    let run = Trash::new(&dir).new_run();
+    Ok(Some(dir.join(run)))
+}
+
+/// The trash `dest`'s syncs keep displaced files in
+fn trash_of(backup_dir: Option<&Path>, dest: &Path) -> Trash {
+    match backup_dir {
+        Some(dir) => Trash::new(dir),
+        None => Trash::of(dest),
+    }
+}
+
+/// Hold the trash of `dest` to --trash-keep-days and --trash-max-size
+fn purge_trash(cli: &Cli, dest: &Path) -> Result<()> {
+    let retention = Retention {
+        max_age: cli.trash_keep_days.map(|days| Duration::from_secs(days * 86_400)),
+        max_size: cli.trash_max_size,
+    };
+    if retention == Retention::default() {
+        return Ok(());
+    }
+    let purged = trash_of(cli.backup_dir.as_deref(), dest).purge(&retention)?;
+    if !purged.is_empty() && !cli.quiet {
+        let size = purged.iter().map(|run| run.size).sum();
+        println!("Purged {} trash runs, {}", purged.len(), format_bytes(size));
+    }
+    Ok(())
+}
+
+/// How a conflict between `source` and `dest` is settled, for display
+fn settled(resolution: &Resolution, source: &str, dest: &str) -> String {
+    match resolution {
+        Resolution::Source => format!("{source} wins"),
+        Resolution::Dest => format!("{dest} wins"),
+        Resolution::KeepBoth(aside) => format!("{dest}'s version kept as {}", aside.display()),
+        Resolution::Unresolved => "left alone".to_string(),
+    }
+}
+
+/// Finish the interrupted sync from `source` whose plan was left in `dest`
+fn resume(cli: &Cli, source: &Path, dest: &Path, saved: SavedPlan) -> Result<()> {
+    let source = fs::canonicalize(source)?;
+    if saved.plan.source_root != source {
+        anyhow::bail!(
+            "The interrupted sync into {} was from {}, not {}",
+            dest.display(),
+            saved.plan.source_root.display(),
+            source.display(),
+        );
+    }
+    saved.check_source().context("Can't resume; sync again without --resume")?;
+
+    if !cli.quiet {
+        println!("Resuming: {} of {} steps left", saved.pending(), saved.plan.step_count());
+    }
+    let previous = Baseline::load(&source, dest)?;
+    let remaining = saved.remaining();
+    apply(cli, &remaining, saved.plan.options.delete_removed, || {
+        resume_sync(dest, &saved)?;
+        let dest_options = ScanOptions {
+            filter: saved.plan.options.filter.clone(),
+            respect_gitignore: cli.rules.respect_gitignore,
+            ignore_files: cli.rules.ignore_file.clone(),
+            use_index: true,
+            save_index: true,
+            ..ScanOptions::default()
+        };
+        let written = scan_directory_with_options(dest, &dest_options)?;
+        Baseline::after_resume(&source, &written, &saved.plan.diff, previous.as_ref())?
+            .save_in(dest)
+            .context("Can't save the baseline")
+    })
+}
+
+/// Offer to finish an interrupted sync from `source` instead of starting over
+fn offer_resume(cli: &Cli, source: &Path, dest: &Path, saved: &SavedPlan) -> Result<bool> {
+    let same_source = fs::canonicalize(source).is_ok_and(|s| s == saved.plan.source_root);
+    if !same_source || cli.quiet {
+        return Ok(false);
+    }
+
+    let found = format!(
+        "An earlier sync into {} was interrupted with {} of {} steps done.",
+        dest.display(),
+        saved.done.len(),
+        saved.plan.step_count(),
+    );
+    if cli.yes || cli.dry_run {
+        println!("{found} Use --resume to finish it instead of syncing again.");
+        return Ok(false);
+    }
+    print!("{found} Resume it? [Y/n] ");
+    std::io::Write::flush(&mut std::io::stdout())?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    Ok(!input.trim().eq_ignore_ascii_case("n"))
+}
+
+/// Take the lock on a local `dest`, waiting for its holder if `wait`
+fn lock_dest(dest: &Path, wait: bool, quiet: bool) -> Result<DestLock> {
+    match DestLock::try_acquire(dest) {
+        Err(LockError::Held { holder, .. }) if wait => {
+            if !quiet {
+                let holder = holder.map_or("holder unknown".to_string(), |h| h.to_string());
+                println!("Waiting for the sync into {} to finish ({holder})", dest.display());
+            }
+            Ok(DestLock::acquire(dest, true)?)
+        },
+        result => Ok(result?),
+    }
+}
+
+/// Put `dest` back the way it was before its last transactional sync
+fn undo_sync(dest: &Path, yes: bool) -> Result<()> {
+    let what = match undo_state(dest) {
+        UndoState::None => {
+            anyhow::bail!("No transactional sync to roll back in {}", dest.display())
+        },
+        UndoState::Interrupted => "the interrupted sync",
+        UndoState::Complete => "the last sync",
+    };
+    if !yes && !confirm(&format!("Roll back {what} into {}?", dest.display()))? {
+        return Ok(());
+    }
+
+    let stats = rollback(dest)?;
+    println!(
+        "{} {} restored, {} removed",
+        "Rolled back.".green().bold(),
+        stats.restored,
+        stats.removed,
+    );
+    Ok(())
+}
+
+/// Ask `question`, true if the answer is yes
+fn confirm(question: &str) -> Result<bool> {
+    print!("{question} [y/N] ");
+    std::io::Write::flush(&mut std::io::stdout())?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    Ok(input.trim().eq_ignore_ascii_case("y"))
+}
+
+fn journal(action: &JournalAction) -> Result<()> {
+    let (JournalAction::Show { dest, .. }
+    | JournalAction::Recover { dest }
+    | JournalAction::Rollback { dest, .. }
+    | JournalAction::Discard { dest, .. }) = action;
+    if !dest.is_dir() {
+        anyhow::bail!("Destination does not exist: {}", dest.display());
+    }
+    // Looking is fine while a sync runs; changing things is not
+    let _lock = match action {
+        JournalAction::Show { .. } => None,
+        _ => Some(lock_dest(dest, false, false)?),
+    };
+
+    match action {
+        JournalAction::Show { dest, json } => {
+            let report =
+                SyncJournal::inspect(&dest.join(JAN_JOURNAL_FILE), &dest.join(JAN_TEMP_DIR))
+                    .context("Can't read journal")?;
+            let saved = SavedPlan::load(dest)?;
+            if *json {
+                print_journal_json(dest, &report, saved.as_ref());
+            } else {
+                print_journal(dest, &report, saved.as_ref());
+            }
+            Ok(())
+        },
+        JournalAction::Recover { dest } => {
+            let report =
+                SyncJournal::inspect(&dest.join(JAN_JOURNAL_FILE), &dest.join(JAN_TEMP_DIR))
+                    .context("Can't read journal")?;
+            let partials =
+                SyncJournal::recover(&dest.join(JAN_JOURNAL_FILE), &dest.join(JAN_TEMP_DIR))
+                    .context("Journal recovery failed")?;
+            let unfinished = report.entries.iter().filter(|e| !e.committed);
+            let moves = unfinished.clone().filter(|e| e.op == "MOVE").count();
+            let removed = unfinished.count() - moves - partials.len() + report.orphans.len();
+            println!(
+                "{} {moves} moves undone, {} partial copies kept, {removed} temp files removed",
+                "Recovered.".green().bold(),
+                partials.len(),
+            );
+            Ok(())
+        },
+        JournalAction::Rollback { dest, yes } => {
+            if undo_state(dest) == UndoState::Interrupted {
+                return undo_sync(dest, *yes);
+            }
+            let report =
+                SyncJournal::inspect(&dest.join(JAN_JOURNAL_FILE), &dest.join(JAN_TEMP_DIR))
+                    .context("Can't read journal")?;
+            let partials =
+                SyncJournal::recover(&dest.join(JAN_JOURNAL_FILE), &dest.join(JAN_TEMP_DIR))
+                    .context("Journal recovery failed")?;
+            for partial in &partials {
+                fs::remove_file(&partial.temp_path)
+                    .with_context(|| format!("Can't remove {}", partial.temp_path.display()))?;
+            }
+            let unfinished = report.entries.iter().filter(|e| !e.committed);
+            let moves = unfinished.clone().filter(|e| e.op == "MOVE").count();
+            let removed = unfinished.count() - moves + report.orphans.len();
+            println!(
+                "{} {moves} moves undone, {removed} temp files removed",
+                "Rolled back.".green().bold(),
+            );
+            Ok(())
+        },
+        JournalAction::Discard { dest, yes } => {
+            let report =
+                SyncJournal::inspect(&dest.join(JAN_JOURNAL_FILE), &dest.join(JAN_TEMP_DIR))
+                    .unwrap_or_default();
+            let unfinished = report.entries.iter().filter(|e| !e.committed).count();
+            let question = format!(
+                "Delete the journal of {} and its {unfinished} unfinished operations, undoing nothing?",
+                dest.display(),
+            );
+            if !*yes && !confirm(&question)? {
+                return Ok(());
+            }
+            SyncJournal::discard(&dest.join(JAN_JOURNAL_FILE), &dest.join(JAN_TEMP_DIR))
+                .context("Can't discard journal")?;
+            println!("{}", "Discarded.".green().bold());
+            Ok(())
+        },
+    }
+}
+
+fn trash(action: &TrashAction) -> Result<()> {
+    let (TrashAction::List { dest, backup_dir, .. }
+    | TrashAction::Restore { dest, backup_dir, .. }
+    | TrashAction::Purge { dest, backup_dir, .. }) = action;
+    if !dest.is_dir() {
+        anyhow::bail!("Destination does not exist: {}", dest.display());
+    }
+    let trash = trash_of(backup_dir.as_deref(), dest);
+    let _lock = match action {
+        TrashAction::List { .. } => None,
+        _ => Some(lock_dest(dest, false, false)?),
+    };
+
+    match action {
+        TrashAction::List { run: Some(run), .. } => {
+            for file in trash.files(run)? {
+                println!("{}", file.display());
+            }
+            Ok(())
+        },
+        TrashAction::List { run: None, .. } => {
+            let runs = trash.runs()?;
+            if runs.is_empty() {
+                println!("Nothing in the trash of {}", dest.display());
+                return Ok(());
+            }
+            println!(
+                "Trash of {} in {}: {} runs, {}",
+                dest.display(),
+                trash.root().display(),
+                runs.len(),
+                format_bytes(runs.iter().map(|run| run.size).sum()),
+            );
+            for run in &runs {
+                println!("  {}  {:>6} files  {:>9}", run.name, run.files, format_bytes(run.size));
+            }
+            Ok(())
+        },
+        TrashAction::Restore { run, paths, yes, .. } => {
+            let found = trash.run(run)?;
+            let question = format!(
+                "Restore {} from run {run} into {}, replacing what is there?",
+                match paths.len() {
+                    0 => format!("all {} files", found.files),
+                    _ => "the given paths".to_string(),
+                },
+                dest.display(),
+            );
+            if !*yes && !confirm(&question)? {
+                return Ok(());
+            }
+            let restored = trash.restore(run, dest, paths)?;
+            println!("{} {} files", "Restored.".green().bold(), restored.len());
+            Ok(())
+        },
+        TrashAction::Purge { keep_days, max_size, yes, .. } => {
+            let retention = Retention {
+                max_age: keep_days.map(|days| Duration::from_secs(days * 86_400)),
+                max_size: *max_size,
+            };
+            let purged = if retention == Retention::default() {
+                let question = format!("Delete everything in {}?", trash.root().display());
+                if !*yes && !confirm(&question)? {
+                    return Ok(());
+                }
+                trash.purge_all()?
+            } else {
+                trash.purge(&retention)?
+            };
+            let size = purged.iter().map(|run| run.size).sum();
+            println!("{} {} runs, {}", "Purged.".green().bold(), purged.len(), format_bytes(size),);
+            Ok(())
+        },
+    }
+}
+
+/// Snapshot `args.source` into `args.dest_root`, linking what didn't change
+fn backup(args: &BackupArgs, matches: &ArgMatches) -> Result<()> {
+    if !args.source.is_dir() {
+        anyhow::bail!("Source does not exist: {}", args.source.display());
+    }
+    let dest_root = &args.dest_root;
+    fs::create_dir_all(dest_root)
+        .with_context(|| format!("Can't create {}", dest_root.display()))?;
+    let _lock = lock_dest(dest_root, args.wait, args.quiet)?;
+
+    let scan_options = ScanOptions {
+        filter: build_filter(matches)?,
+        exclude_patterns: Vec::new(),
+        respect_gitignore: args.rules.respect_gitignore,
+        ignore_files: args.rules.ignore_file.clone(),
+        use_index: true,
+        save_index: args.index_source && !args.dry_run,
+        rehash: args.rehash,
+        symlinks: args.links.into(),
+        quick: false,
+    };
+    if args.verbose && !args.quiet {
+        println!("Scanning: {}", args.source.display());
+    }
+    let plan = plan_snapshot(&args.source, dest_root, &scan_options)?;
+    if args.verbose && !args.quiet {
+        print_excluded(&plan.source.excluded);
+    }
+
+    if !args.quiet {
+        match &plan.previous {
+            Some(previous) => {
+                print!("Since {}: ", previous.name);
+                print_diff_summary(&plan.diff, false, args.verbose);
+            },
+            None => println!(
+                "First snapshot: {} files, {}",
+                plan.source.files.len(),
+                format_bytes(plan.source.total_size()),
+            ),
+        }
+    }
+    if args.dry_run {
+        if !args.quiet {
+            println!("(dry run)");
+        }
+        return Ok(());
+    }
+
+    let start_time = Instant::now();
+    let (snapshot, stats) = take_snapshot(&plan, dest_root, args.verify)?;
+    if !args.quiet {
+        println!(
+            "{} {}: {} copied ({} files), {} linked ({} files) in {:.2}s",
+            "Done.".green().bold(),
+            snapshot.path.display(),
+            format_bytes(stats.copied_bytes),
+            stats.copied,
+            format_bytes(stats.linked_bytes),
+            stats.linked,
+            start_time.elapsed().as_secs_f64(),
+        );
+    }
+    Ok(())
+}
+
+/// Delete the snapshots in `args.dest_root` that no keep rule keeps
+fn prune(args: &PruneArgs) -> Result<()> {
+    let policy = KeepPolicy {
+        last: args.keep_last,
+        hourly: args.keep_hourly,
+        daily: args.keep_daily,
+        weekly: args.keep_weekly,
+        monthly: args.keep_monthly,
+        yearly: args.keep_yearly,
+    };
+    if policy.is_empty() {
+        anyhow::bail!("Nothing would be kept; give at least one --keep-* rule");
+    }
+    let dest_root = &args.dest_root;
+    if !dest_root.is_dir() {
+        anyhow::bail!("Destination does not exist: {}", dest_root.display());
+    }
+    let _lock = lock_dest(dest_root, args.wait, args.quiet)?;
+
+    let decisions = plan_prune(snapshots(dest_root)?, &policy);
+    let doomed: Vec<&Snapshot> =
+        decisions.iter().filter(|d| !d.keep()).map(|d| &d.snapshot).collect();
+    let freed = space_freed(&doomed)?;
+    if !args.quiet {
+        let mut freed_alone = freed.per_snapshot.iter();
+        for decision in &decisions {
+            if decision.keep() {
+                let reasons = decision.reasons.join(", ");
+                println!("  {}  {}  {reasons}", "keep  ".green(), decision.snapshot.name);
+            } else {
+                let alone = freed_alone.next().copied().unwrap_or_default();
+                println!(
+                    "  {}  {}  {} only here",
+                    "delete".red(),
+                    decision.snapshot.name,
+                    format_bytes(alone),
+                );
+            }
+        }
+        println!(
+            "{} of {} snapshots to delete, freeing {}",
+            doomed.len(),
+            decisions.len(),
+            format_bytes(freed.total),
+        );
+    }
+    if doomed.is_empty() {
+        return Ok(());
+    }
+    if args.dry_run {
+        if !args.quiet {
+            println!("(dry run)");
+        }
+        return Ok(());
+    }
+    let question = format!("Delete {} snapshots in {}?", doomed.len(), dest_root.display());
+    if !args.yes && !confirm(&question)? {
+        return Ok(());
+    }
+
+    for snapshot in &doomed {
+        remove_snapshot(dest_root, snapshot)?;
+    }
+    if !args.quiet {
+        println!(
+            "{} {} snapshots, {} freed",
+            "Pruned.".green().bold(),
+            doomed.len(),
+            format_bytes(freed.total),
+        );
+    }
+    Ok(())
+}
+
+/// `path` relative to `root` where it lies inside, for display
+fn relative_to<'a>(root: &Path, path: &'a Path) -> std::path::Display<'a> {
+    path.strip_prefix(root).unwrap_or(path).display()
+}
+
+fn print_journal(dest: &Path, report: &JournalReport, saved: Option<&SavedPlan>) {
+    let unfinished = report.entries.iter().filter(|e| !e.committed).count();
+    if report.entries.is_empty() {
+        println!("No journal in {}", dest.display());
+    } else {
+        println!(
+            "Journal of an interrupted sync into {}: {} operations, {unfinished} unfinished",
+            dest.display(),
+            report.entries.len(),
+        );
+    }
+    for entry in &report.entries {
+        let state = if entry.committed {
+            format!("{:<8}", "done").green().to_string()
+        } else {
+            format!("{:<8}", "pending").yellow().to_string()
+        };
+        let target = match entry.op.as_str() {
+            "MOVE" => format!(
+                "{} -> {}",
+                relative_to(dest, &entry.temp_path),
+                relative_to(dest, &entry.final_path),
+            ),
+            _ => relative_to(dest, &entry.final_path).to_string(),
+        };
+        let resume = match (&entry.progress, entry.committed) {
+            (Some(point), false) => format!(
+                " (can resume from {} of {})",
+                format_bytes(point.written),
+                format_bytes(point.source_size),
+            ),
+            _ => String::new(),
+        };
+        println!("  {state} {:<8} {target}{resume}", entry.op);
+    }
+
+    if !report.orphans.is_empty() {
+        println!("Orphaned temp files: {}", report.orphans.len());
+        for orphan in &report.orphans {
+            println!("  {}", relative_to(dest, orphan));
+        }
+    }
+    if let Some(saved) = saved {
+        println!(
+            "Saved plan: {} of {} steps done; `jan {} {} --resume` finishes it",
+            saved.done.len(),
+            saved.plan.step_count(),
+            saved.plan.source_root.display(),
+            dest.display(),
+        );
+    }
+    match DestLock::holder(dest) {
+        Some(holder) if holder.is_stale() => println!(
+            "A lock was left by a process that no longer runs ({holder}); the next sync takes it over"
+        ),
+        Some(holder) => println!("A sync is running right now: {holder}"),
+        None => {},
+    }
+    match undo_state(dest) {
+        UndoState::Interrupted => println!(
+            "A transactional sync was interrupted; `jan rollback {}` undoes it",
+            dest.display(),
+        ),
+        UndoState::Complete => {
+            println!("The last sync was transactional; `jan rollback {}` undoes it", dest.display(),)
+        },
+        UndoState::None => {},
+    }
+}
+
+fn print_journal_json(dest: &Path, report: &JournalReport, saved: Option<&SavedPlan>) {
+    // A display string can't tell a lossy name from a real one, so each path
+    // also comes byte for byte, escaped as in the journal
+    let path = |key: &str, p: &Path| {
+        format!(
+            "\"{key}\":{},\"{key}_bytes\":{}",
+            json_string(&p.to_string_lossy()),
+            json_string(&escape_path(p)),
+        )
+    };
+    let entries: Vec<String> = report
+        .entries
+        .iter()
+        .map(|entry| {
+            let resume = match &entry.progress {
+                Some(point) => format!(
+                    "{{\"written\":{},\"source_size\":{}}}",
+                    point.written, point.source_size,
+                ),
+                None => "null".to_string(),
+            };
+            format!(
+                "{{\"op\":{},{},{},\"committed\":{},\"resume\":{resume}}}",
+                json_string(&entry.op),
+                path("temp", &entry.temp_path),
+                path("final", &entry.final_path),
+                entry.committed,
+            )
+        })
+        .collect();
+    let orphans: Vec<String> =
+        report.orphans.iter().map(|p| format!("{{{}}}", path("path", p))).collect();
+    let plan = match saved {
+        Some(saved) => format!(
+            "{{{},\"steps\":{},\"done\":{}}}",
+            path("source", &saved.plan.source_root),
+            saved.plan.step_count(),
+            saved.done.len(),
+        ),
+        None => "null".to_string(),
+    };
+    let lock = match DestLock::holder(dest) {
+        Some(holder) => {
+            format!("{{\"pid\":{},\"host\":{}}}", holder.pid, json_string(&holder.host),)
+        },
+        None => "null".to_string(),
+    };
+    let rollback = match undo_state(dest) {
+        UndoState::None => "none",
+        UndoState::Interrupted => "interrupted",
+        UndoState::Complete => "complete",
+    };
+    println!(
+        "{{{},\"operations\":[{}],\"orphans\":[{}],\
+         \"plan\":{plan},\"rollback\":\"{rollback}\",\"lock\":{lock}}}",
+        path("dest", dest),
+        entries.join(","),
+        orphans.join(","),
+    );
+}
+
+/// `text` as a quoted JSON string
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c < ' ' => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Show `diff`, ask before applying it with `sync`, and report how it went
+fn apply(
+    cli: &Cli,
+    diff: &DiffResult,
+    delete: bool,
+    sync: impl FnOnce() -> Result<()>,
+) -> Result<()> {
     // Display summary
     if !cli.quiet {
-        print_diff_summary(&diff, cli.delete, cli.verbose);
+        print_diff_summary(diff, delete, cli.verbose);
     }
+    run_confirmed(cli, &[diff], sync)
+}
 
+/// Ask before running `sync`, which applies `diffs`, and report how it went
+fn run_confirmed(
+    cli: &Cli,
+    diffs: &[&DiffResult],
+    sync: impl FnOnce() -> Result<()>,
+) -> Result<()> {
     // Dry run - exit after showing changes
     if cli.dry_run {
         if !cli.quiet {
@@ -137,23 +1526,22 @@ fn run() -> Result<()> {
 
     // Sync
     let start_time = Instant::now();
-    sync_changes(
-        &cli.source,
-        &cli.dest,
-        &diff,
-        &SyncOptions {
-            delete_removed: cli.delete,
-            preserve_timestamps: true,
-            verify_after_copy: cli.verify,
-        },
-    )?;
+    sync()?;
     let elapsed = start_time.elapsed();
 
     if !cli.quiet {
-        let copied_bytes: u64 = diff.added.iter().map(|f| f.size).sum::<u64>()
-            + diff.modified.iter().map(|f| f.size).sum::<u64>();
-        let renamed_bytes: u64 = diff.renamed.iter().map(|(old, _)| old.size).sum();
-        let total_bytes = copied_bytes + renamed_bytes;
+        let copied_bytes: u64 =
+            diffs.iter().flat_map(|diff| diff.files_to_copy()).map(|f| f.size).sum();
+        let renamed_bytes: u64 = diffs
+            .iter()
+            .map(|diff| {
+                diff.renamed.iter().map(|(old, _)| old.size).sum::<u64>()
+                    + diff.renamed_dirs.iter().map(|d| d.total_size()).sum::<u64>()
+            })
+            .sum();
+        let reused_bytes: u64 =
+            diffs.iter().flat_map(|diff| &diff.copied).map(|(_, new)| new.size).sum();
+        let total_bytes = copied_bytes + renamed_bytes + reused_bytes;
 
         if total_bytes > 0 {
             let bytes_per_sec = if elapsed.as_secs_f64() > 0.0 {
@@ -162,24 +1550,21 @@ fn run() -> Result<()> {
                 0.0
             };
 
+            let mut moved = vec![format!("{} copied", format_bytes(copied_bytes))];
             if renamed_bytes > 0 {
-                println!(
-                    "{} {} copied, {} renamed in {:.2}s ({}/s)",
-                    "Done.".green().bold(),
-                    format_bytes(copied_bytes),
-                    format_bytes(renamed_bytes),
-                    elapsed.as_secs_f64(),
-                    format_bytes(bytes_per_sec as u64)
-                );
-            } else {
-                println!(
-                    "{} {} copied in {:.2}s ({}/s)",
-                    "Done.".green().bold(),
-                    format_bytes(copied_bytes),
-                    elapsed.as_secs_f64(),
-                    format_bytes(bytes_per_sec as u64)
-                );
+                moved.push(format!("{} renamed", format_bytes(renamed_bytes)));
             }
+            if reused_bytes > 0 {
+                moved.push(format!("{} reused", format_bytes(reused_bytes)));
+            }
+
+            println!(
+                "{} {} in {:.2}s ({}/s)",
+                "Done.".green().bold(),
+                moved.join(", "),
+                elapsed.as_secs_f64(),
+                format_bytes(bytes_per_sec as u64)
+            );
         } else {
             println!("{}", "Done".green());
         }
@@ -205,6 +1590,57 @@ fn format_bytes(bytes: u64) -> String {
     }
 }
 
+/// Byte count written like `500M` or `2G`, in the units of [`format_bytes`]
+fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let digits = text.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = match text[digits.len()..].to_ascii_uppercase().trim_end_matches('B') {
+        "" => 1u64,
+        "K" | "KI" => 1 << 10,
+        "M" | "MI" => 1 << 20,
+        "G" | "GI" => 1 << 30,
+        "T" | "TI" => 1 << 40,
+        _ => return Err(format!("unknown unit in {text:?}; use K, M, G or T")),
+    };
+    let number: f64 = digits.parse().map_err(|_| format!("not a size: {text:?}"))?;
+    if !number.is_finite() || number < 0.0 {
+        return Err(format!("not a size: {text:?}"));
+    }
+    Ok((number * unit as f64) as u64)
+}
+
+/// Assemble the filter rules in the order they were given on the command line
+fn build_filter(matches: &ArgMatches) -> Result<Filter> {
+    let mut rules: Vec<(usize, &str, &str)> = Vec::new();
+    for id in ["include", "exclude", "filter_from", "filter_merge"] {
+        if let (Some(indices), Some(values)) =
+            (matches.indices_of(id), matches.get_many::<String>(id))
+        {
+            rules.extend(indices.zip(values).map(|(i, v)| (i, id, v.as_str())));
+        }
+    }
+    rules.sort_by_key(|(index, _, _)| *index);
+
+    let mut filter = Filter::new();
+    for (_, id, value) in rules {
+        match id {
+            "include" => filter.include(value, INCLUDE_SOURCE)?,
+            "exclude" => filter.exclude(value, EXCLUDE_SOURCE)?,
+            "filter_from" => filter.add_file(Path::new(value))?,
+            _ => filter.dir_merge(value),
+        }
+    }
+    Ok(filter)
+}
+
+fn print_excluded(excluded: &[(String, usize)]) {
+    if !excluded.is_empty() {
+        let parts: Vec<String> =
+            excluded.iter().map(|(source, count)| format!("{count} by {source}")).collect();
+        println!("Excluded: {}", parts.join(", "));
+    }
+}
+
 fn print_diff_summary(diff: &janice::DiffResult, delete: bool, verbose: bool) {
     let mut parts = Vec::new();
 
@@ -217,9 +1653,38 @@ fn print_diff_summary(diff: &janice::DiffResult, delete: bool, verbose: bool) {
     if !diff.renamed.is_empty() {
         parts.push(format!("{} renamed", diff.renamed.len()).cyan().to_string());
     }
+    if !diff.renamed_dirs.is_empty() {
+        let files: usize = diff.renamed_dirs.iter().map(|d| d.files.len()).sum();
+        parts.push(
+            format!("{} dirs moved ({files} files)", diff.renamed_dirs.len())
+                .cyan()
+                .to_string(),
+        );
+    }
+    if !diff.copied.is_empty() {
+        parts.push(format!("{} duplicated", diff.copied.len()).blue().to_string());
+    }
+    if !diff.added_dirs.is_empty() {
+        parts.push(format!("{} dirs created", diff.added_dirs.len()).green().to_string());
+    }
+    if !diff.modified_dirs.is_empty() {
+        parts.push(format!("{} dirs updated", diff.modified_dirs.len()).yellow().to_string());
+    }
     if delete && !diff.removed.is_empty() {
         parts.push(format!("{} deleted", diff.removed.len()).red().to_string());
     }
+    if delete && !diff.removed_dirs.is_empty() {
+        parts.push(format!("{} dirs removed", diff.removed_dirs.len()).red().to_string());
+    }
+    if delete && !diff.protected.is_empty() {
+        parts.push(format!("{} protected", diff.protected.len()));
+    }
+    if !diff.conflicts.is_empty() {
+        parts.push(format!("{} conflicts", diff.conflicts.len()).magenta().to_string());
+    }
+    if parts.is_empty() {
+        parts.push("no changes".to_string());
+    }
 
     println!("{}", parts.join(", "));
 
@@ -234,6 +1699,16 @@ fn print_diff_summary(diff: &janice::DiffResult, delete: bool, verbose: bool) {
             }
         }
 
+        if !diff.added_dirs.is_empty() {
+            println!("New dirs:");
+            for dir in diff.added_dirs.iter().take(5) {
+                println!("  {}/", dir.path.display());
+            }
+            if diff.added_dirs.len() > 5 {
+                println!("  ... {} more", diff.added_dirs.len() - 5);
+            }
+        }
+
         if !diff.modified.is_empty() {
             println!("Modified:");
             for file in diff.modified.iter().take(5) {
@@ -244,6 +1719,21 @@ fn print_diff_summary(diff: &janice::DiffResult, delete: bool, verbose: bool) {
             }
         }
 
+        if !diff.renamed_dirs.is_empty() {
+            println!("Moved dirs:");
+            for dir in diff.renamed_dirs.iter().take(5) {
+                println!(
+                    "  {}/ -> {}/ ({} files)",
+                    dir.old.display(),
+                    dir.new.display(),
+                    dir.files.len()
+                );
+            }
+            if diff.renamed_dirs.len() > 5 {
+                println!("  ... {} more", diff.renamed_dirs.len() - 5);
+            }
+        }
+
         if !diff.renamed.is_empty() {
             println!("Renamed:");
             for (old, new) in diff.renamed.iter().take(5) {
@@ -254,6 +1744,16 @@ fn print_diff_summary(diff: &janice::DiffResult, delete: bool, verbose: bool) {
             }
         }
 
+        if !diff.copied.is_empty() {
+            println!("Duplicated:");
+            for (existing, new) in diff.copied.iter().take(5) {
+                println!("  {} -> {}", existing.path.display(), new.path.display());
+            }
+            if diff.copied.len() > 5 {
+                println!("  ... {} more", diff.copied.len() - 5);
+            }
+        }
+
         if delete && !diff.removed.is_empty() {
             println!("Deleted:");
             for file in diff.removed.iter().take(5) {
@@ -263,5 +1763,29 @@ fn print_diff_summary(diff: &janice::DiffResult, delete: bool, verbose: bool) {
                 println!("  ... {} more", diff.removed.len() - 5);
             }
         }
+
+        if !diff.conflicts.is_empty() {
+            println!("Conflicts:");
+            for conflict in diff.conflicts.iter().take(5) {
+                println!(
+                    "  {} ({})",
+                    conflict.source.path.display(),
+                    settled(&conflict.resolution, "source", "destination"),
+                );
+            }
+            if diff.conflicts.len() > 5 {
+                println!("  ... {} more", diff.conflicts.len() - 5);
+            }
+        }
+
+        if delete && !diff.protected.is_empty() {
+            println!("Protected:");
+            for file in diff.protected.iter().take(5) {
+                println!("  {}", file.path.display());
+            }
+            if diff.protected.len() > 5 {
+                println!("  ... {} more", diff.protected.len() - 5);
+            }
+        }
     }
 }
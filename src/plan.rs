@@ -0,0 +1,354 @@
+//! Saved sync plans, so an interrupted run can continue where it stopped
+//!
+//! Before touching the destination, [`sync_changes`](crate::core::sync_changes)
+//! writes the diff it is about to apply, the source root and the sync options
+//! to `.jan-plan` in the destination, then appends a mark as each step
+//! finishes. A run that dies leaves the file behind, and
+//! [`resume_sync`](crate::core::resume_sync) applies what is left without
+//! scanning either tree again. A clean finish removes it.
+//!
+//! The file is a series of [`wire`](crate::wire) frames: a header with the
+//! format version, the plan split over as many frames as it needs, then one
+//! frame per completed [`Step`]. A mark cut short by a crash is ignored, so
+//! its step simply runs again.
+
+use crate::core::{DiffResult, FileMeta, SyncOptions};
+use crate::io::{remove_file_safe, JAN_PLAN_FILE};
+use crate::wire::{
+    from_bytes, read_chunked, read_frame, to_bytes, write_chunked, write_frame, WireError,
+};
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Plan file format version
+pub const PLAN_VERSION: u32 = 5;
+
+const MAGIC: [u8; 8] = *b"JAN-PLAN";
+
+/// One resumable step of a sync, by its position in the plan's diff
+///
+/// Deletes and directory work are cheap to repeat and have no step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Step {
+    /// `diff.renamed_dirs[i]`
+    DirMove(usize),
+    /// `diff.copied[i]`
+    LocalCopy(usize),
+    /// `diff.files_to_copy()[i]`: new, modified, then conflicts settled in
+    /// the source's favour
+    Copy(usize),
+    /// `diff.renamed[i]`
+    Rename(usize),
+}
+
+/// A diff with everything needed to apply it again
+#[derive(Debug, Clone)]
+pub struct SyncPlan {
+    /// Canonical source root
+    pub source_root: PathBuf,
+    pub diff: DiffResult,
+    pub options: SyncOptions,
+}
+
+impl SyncPlan {
+    /// Number of steps the plan is made of
+    pub fn step_count(&self) -> usize {
+        let diff = &self.diff;
+        diff.renamed_dirs.len()
+            + diff.copied.len()
+            + diff.files_to_copy().len()
+            + diff.renamed.len()
+    }
+}
+
+/// A plan read back from a destination, with the steps already done
+#[derive(Debug, Clone)]
+pub struct SavedPlan {
+    pub plan: SyncPlan,
+    pub done: HashSet<Step>,
+}
+
+impl SavedPlan {
+    /// Load the plan an interrupted sync left in `dest_root`, if any
+    ///
+    /// A plan that was never completely written is ignored: nothing was
+    /// applied before it was.
+    pub fn load(dest_root: &Path) -> Result<Option<Self>> {
+        let path = dest_root.join(JAN_PLAN_FILE);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Can't read {}", path.display())),
+        };
+        let mut reader = BufReader::new(file);
+        let corrupt = |e: WireError| anyhow::anyhow!("Corrupt sync plan {}: {e}", path.display());
+
+        let header = match read_frame(&mut reader) {
+            Ok(header) => header,
+            Err(e) if e.is_eof() => return Ok(None),
+            Err(e) => return Err(corrupt(e)),
+        };
+        if header.len() != MAGIC.len() + 4 || header[..MAGIC.len()] != MAGIC {
+            anyhow::bail!("{} is not a jan sync plan", path.display());
+        }
+        let version = u32::from_le_bytes(header[MAGIC.len()..].try_into().unwrap());
+        if version != PLAN_VERSION {
+            anyhow::bail!(
+                "Sync plan {} has format version {version}, this build reads {PLAN_VERSION}",
+                path.display(),
+            );
+        }
+        let plan = match read_chunked(&mut reader) {
+            Ok(payload) => from_bytes(&payload).map_err(corrupt)?,
+            Err(e) if e.is_eof() => return Ok(None),
+            Err(e) => return Err(corrupt(e)),
+        };
+
+        let mut done = HashSet::new();
+        // Marks end at the first one that is missing or torn
+        while let Ok(payload) = read_frame(&mut reader) {
+            match from_bytes(&payload) {
+                Ok(step) => done.insert(step),
+                Err(_) => break,
+            };
+        }
+        Ok(Some(Self { plan, done }))
+    }
+
+    /// Number of steps still to run
+    pub fn pending(&self) -> usize {
+        self.plan.step_count() - self.done.len()
+    }
+
+    /// The part of the diff still to apply, for showing the user
+    pub fn remaining(&self) -> DiffResult {
+        let diff = &self.plan.diff;
+        let added_len = diff.added.len();
+        // Conflicts left alone have no step, so they always remain
+        let mut copy = added_len + diff.modified.len();
+        let conflicts = diff
+            .conflicts
+            .iter()
+            .filter(|c| {
+                if !c.resolution.takes_source() {
+                    return true;
+                }
+                copy += 1;
+                !self.done.contains(&Step::Copy(copy - 1))
+            })
+            .cloned()
+            .collect();
+        DiffResult {
+            renamed_dirs: self.keep(&diff.renamed_dirs, Step::DirMove),
+            copied: self.keep(&diff.copied, Step::LocalCopy),
+            added: self.keep(&diff.added, Step::Copy),
+            modified: self.keep(&diff.modified, |i| Step::Copy(added_len + i)),
+            renamed: self.keep(&diff.renamed, Step::Rename),
+            conflicts,
+            ..diff.clone()
+        }
+    }
+
+    fn keep<T: Clone>(&self, items: &[T], step: impl Fn(usize) -> Step) -> Vec<T> {
+        items
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !self.done.contains(&step(*i)))
+            .map(|(_, item)| item.clone())
+            .collect()
+    }
+
+    /// Fail if a source file still to be copied is no longer the one planned
+    ///
+    /// Compares size and mtime, or the target of a link, without hashing.
+    pub fn check_source(&self) -> Result<()> {
+        let diff = &self.plan.diff;
+        for (i, file) in diff.files_to_copy().into_iter().enumerate() {
+            let path = self.plan.source_root.join(&file.path);
+            if !self.done.contains(&Step::Copy(i)) && !unchanged(&path, file) {
+                anyhow::bail!("{} changed since the sync was planned", path.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn unchanged(path: &Path, file: &FileMeta) -> bool {
+    match &file.symlink_target {
+        Some(target) => fs::read_link(path).is_ok_and(|t| t == *target),
+        None => fs::metadata(path).is_ok_and(|m| {
+            m.is_file() && m.len() == file.size && m.modified().is_ok_and(|t| t == file.mtime)
+        }),
+    }
+}
+
+/// Writer appending step marks to a destination's plan file
+pub struct PlanLog {
+    file: Mutex<BufWriter<File>>,
+    path: PathBuf,
+}
+
+impl PlanLog {
+    /// Write `plan` to `dest_root`, replacing any earlier one
+    pub fn create(dest_root: &Path, plan: &SyncPlan) -> io::Result<Self> {
+        let path = dest_root.join(JAN_PLAN_FILE);
+        let mut writer = BufWriter::new(File::create(&path)?);
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&PLAN_VERSION.to_le_bytes());
+        write_frame(&mut writer, &header).map_err(io::Error::other)?;
+        write_chunked(&mut writer, &to_bytes(plan)).map_err(io::Error::other)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        Ok(Self { file: Mutex::new(writer), path })
+    }
+
+    /// Keep marking steps in the plan already in `dest_root`
+    pub fn reopen(dest_root: &Path) -> io::Result<Self> {
+        let path = dest_root.join(JAN_PLAN_FILE);
+        let file = OpenOptions::new().append(true).open(&path)?;
+        Ok(Self {
+            file: Mutex::new(BufWriter::new(file)),
+            path,
+        })
+    }
+
+    /// Record that `step` finished
+    pub fn mark(&self, step: Step) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        write_frame(&mut *file, &to_bytes(&step)).map_err(io::Error::other)?;
+        file.flush()
+    }
+
+    /// Remove the plan once the sync is complete
+    pub fn remove(self) -> io::Result<()> {
+        drop(self.file);
+        remove_file_safe(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::hash_bytes;
+    use crate::wire::CHUNK_SIZE;
+    use std::time::SystemTime;
+    use tempfile::tempdir;
+
+    fn file(path: &str, mtime: SystemTime, content: &[u8]) -> FileMeta {
+        FileMeta {
+            path: PathBuf::from(path),
+            size: content.len() as u64,
+            mtime,
+            hash: Some(hash_bytes(content)),
+            permissions: None,
+            symlink_target: None,
+        }
+    }
+
+    fn empty_diff() -> DiffResult {
+        DiffResult {
+            added: vec![],
+            removed: vec![],
+            modified: vec![],
+            renamed: vec![],
+            copied: vec![],
+            renamed_dirs: vec![],
+            added_dirs: vec![],
+            removed_dirs: vec![],
+            modified_dirs: vec![],
+            protected: vec![],
+            conflicts: vec![],
+        }
+    }
+
+    #[test]
+    fn test_plan_round_trip_with_marks() -> Result<()> {
+        let dir = tempdir()?;
+        let now = SystemTime::now();
+        let plan = SyncPlan {
+            source_root: PathBuf::from("/src"),
+            diff: DiffResult {
+                added: vec![file("a", now, b"a"), file("b", now, b"b")],
+                modified: vec![file("c", now, b"c")],
+                ..empty_diff()
+            },
+            options: SyncOptions::default(),
+        };
+
+        let log = PlanLog::create(dir.path(), &plan)?;
+        log.mark(Step::Copy(0))?;
+        log.mark(Step::Copy(2))?;
+        drop(log);
+        // A torn mark from a crash mid-write
+        let mut file = OpenOptions::new().append(true).open(dir.path().join(JAN_PLAN_FILE))?;
+        file.write_all(&[9, 0, 0, 0, 1])?;
+
+        let saved = SavedPlan::load(dir.path())?.unwrap();
+        assert_eq!(saved.plan.source_root, plan.source_root);
+        assert_eq!(saved.done, HashSet::from([Step::Copy(0), Step::Copy(2)]));
+        assert_eq!(saved.pending(), 1);
+        let remaining = saved.remaining();
+        assert_eq!(remaining.added, vec![plan.diff.added[1].clone()]);
+        assert!(remaining.modified.is_empty());
+
+        PlanLog::reopen(dir.path())?.remove()?;
+        assert!(SavedPlan::load(dir.path())?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_larger_than_a_frame() -> Result<()> {
+        let dir = tempdir()?;
+        let now = SystemTime::now();
+        let added = (0..40_000).map(|i| file(&format!("dir/file-{i}"), now, b"x")).collect();
+        let plan = SyncPlan {
+            source_root: PathBuf::from("/src"),
+            diff: DiffResult { added, ..empty_diff() },
+            options: SyncOptions::default(),
+        };
+        assert!(to_bytes(&plan).len() > 2 * CHUNK_SIZE);
+
+        PlanLog::create(dir.path(), &plan)?.mark(Step::Copy(39_999))?;
+        let content = fs::read(dir.path().join(JAN_PLAN_FILE))?;
+        let mut reader = content.as_slice();
+        while !reader.is_empty() {
+            assert!(read_frame(&mut reader)?.len() <= CHUNK_SIZE);
+        }
+
+        let saved = SavedPlan::load(dir.path())?.unwrap();
+        assert_eq!(saved.plan.diff.added, plan.diff.added);
+        assert_eq!(saved.done, HashSet::from([Step::Copy(39_999)]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_source_notices_changed_files() -> Result<()> {
+        let src = tempdir()?;
+        fs::write(src.path().join("a"), b"alpha")?;
+        let mtime = fs::metadata(src.path().join("a"))?.modified()?;
+        let mut saved = SavedPlan {
+            plan: SyncPlan {
+                source_root: src.path().to_path_buf(),
+                diff: DiffResult {
+                    added: vec![file("a", mtime, b"alpha")],
+                    ..empty_diff()
+                },
+                options: SyncOptions::default(),
+            },
+            done: HashSet::new(),
+        };
+        saved.check_source()?;
+
+        fs::write(src.path().join("a"), b"alpha, longer")?;
+        assert!(saved.check_source().is_err());
+
+        // Files already copied don't matter any more
+        saved.done.insert(Step::Copy(0));
+        saved.check_source()?;
+        Ok(())
+    }
+}
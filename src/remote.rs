@@ -0,0 +1,639 @@
+//! SSH transport: sync into a destination held by a `jan --server` peer
+//!
+//! The client spawns `ssh HOST jan --server PATH` (or any other command that
+//! ends up running it) and talks over the child's stdin and stdout in
+//! [`wire`](crate::wire) frames. The server scans and hashes its own tree, so
+//! only the scan result crosses the network. File data is staged under
+//! `.jan-incoming` on the server, as literal bytes or as a delta against the
+//! file it replaces, and then applied with the same [`sync_changes`] a local
+//! sync uses, journal and all.
+
+use crate::core::{
+    scan_directory_with_options, sync_changes, DiffResult, DirMeta, FileMeta, ScanOptions,
+    ScanResult, SyncOptions,
+};
+use crate::delta::{apply_op, DeltaOp, Signature};
+use crate::io::{generate_temp_path, set_file_mtime, AtomicWriter, JAN_INCOMING_DIR};
+use crate::lock::DestLock;
+use crate::wire::{
+    from_bytes, read_chunked, to_bytes, write_chunked, Decoder, Encoder, Hello, Negotiated, Wire,
+    WireError, CAP_DELTA,
+};
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+// Literal data is sent in pieces of this size
+const DATA_CHUNK: usize = 256 * 1024;
+
+/// A destination written as `[user@]host:path`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteSpec {
+    /// Everything before the colon, passed to ssh as is
+    pub host: String,
+    pub path: String,
+}
+
+impl RemoteSpec {
+    /// Parse `[user@]host:path`; `None` for anything that looks like a local path
+    ///
+    /// As with rsync, a colon only means a host when no `/` comes before it.
+    /// Single letters are taken as Windows drives.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (host, path) = spec.split_once(':')?;
+        if host.is_empty() || host.contains('/') || (cfg!(windows) && host.len() == 1) {
+            return None;
+        }
+        Some(Self {
+            host: host.to_string(),
+            path: if path.is_empty() {
+                ".".to_string()
+            } else {
+                path.to_string()
+            },
+        })
+    }
+
+    /// Command running `remote_jan --server PATH` on the host through `rsh`
+    ///
+    /// `rsh` may carry its own arguments, e.g. `ssh -p 2222`. The path is
+    /// quoted for the remote shell.
+    pub fn command(&self, rsh: &str, remote_jan: &str) -> Result<Command> {
+        let mut words = rsh.split_whitespace();
+        let program = words.next().context("Empty remote shell command")?;
+        let mut command = Command::new(program);
+        command
+            .args(words)
+            .arg(&self.host)
+            .arg(remote_jan)
+            .arg("--server")
+            .arg(shell_quote(&self.path));
+        Ok(command)
+    }
+}
+
+fn shell_quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+/// Requests from the client
+enum Request {
+    Scan(ScanOptions),
+    /// Block signature of a destination file, for a delta
+    Signature(PathBuf),
+    /// Stage a file's content; followed by [`Piece`] frames up to `End`
+    Stage {
+        file: FileMeta,
+        delta: bool,
+    },
+    /// Apply a diff using the staged files
+    Sync {
+        diff: Box<DiffResult>,
+        dirs: Vec<DirMeta>,
+        options: SyncOptions,
+    },
+    Quit,
+    /// Take the destination's lock, waiting for another sync if `wait`
+    Lock {
+        wait: bool,
+    },
+}
+
+impl Wire for Request {
+    fn encode(&self, enc: &mut Encoder) {
+        match self {
+            Request::Scan(options) => {
+                enc.u8(0);
+                enc.put(options);
+            },
+            Request::Signature(path) => {
+                enc.u8(1);
+                enc.put(path);
+            },
+            Request::Stage { file, delta } => {
+                enc.u8(2);
+                enc.put(file);
+                enc.bool(*delta);
+            },
+            Request::Sync { diff, dirs, options } => {
+                enc.u8(3);
+                enc.put(diff.as_ref());
+                enc.put(dirs);
+                enc.put(options);
+            },
+            Request::Quit => enc.u8(4),
+            Request::Lock { wait } => {
+                enc.u8(5);
+                enc.bool(*wait);
+            },
+        }
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        match dec.u8()? {
+            0 => Ok(Request::Scan(dec.get()?)),
+            1 => Ok(Request::Signature(dec.get()?)),
+            2 => Ok(Request::Stage { file: dec.get()?, delta: dec.bool()? }),
+            3 => Ok(Request::Sync {
+                diff: Box::new(dec.get()?),
+                dirs: dec.get()?,
+                options: dec.get()?,
+            }),
+            4 => Ok(Request::Quit),
+            5 => Ok(Request::Lock { wait: dec.bool()? }),
+            tag => Err(WireError::InvalidTag { what: "request", tag }),
+        }
+    }
+}
+
+/// One frame of staged file content
+enum Piece {
+    Op(DeltaOp),
+    End,
+}
+
+impl Wire for Piece {
+    fn encode(&self, enc: &mut Encoder) {
+        match self {
+            Piece::Op(op) => {
+                enc.u8(0);
+                enc.put(op);
+            },
+            Piece::End => enc.u8(1),
+        }
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        match dec.u8()? {
+            0 => Ok(Piece::Op(dec.get()?)),
+            1 => Ok(Piece::End),
+            tag => Err(WireError::InvalidTag { what: "piece", tag }),
+        }
+    }
+}
+
+/// Answers from the server
+enum Response {
+    Ok,
+    Scan(Box<ScanResult>),
+    Signature(Signature),
+    Error(String),
+}
+
+impl Wire for Response {
+    fn encode(&self, enc: &mut Encoder) {
+        match self {
+            Response::Ok => enc.u8(0),
+            Response::Scan(scan) => {
+                enc.u8(1);
+                enc.put(scan.as_ref());
+            },
+            Response::Signature(signature) => {
+                enc.u8(2);
+                enc.put(signature);
+            },
+            Response::Error(message) => {
+                enc.u8(3);
+                enc.put(message);
+            },
+        }
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        match dec.u8()? {
+            0 => Ok(Response::Ok),
+            1 => Ok(Response::Scan(Box::new(dec.get()?))),
+            2 => Ok(Response::Signature(dec.get()?)),
+            3 => Ok(Response::Error(dec.get()?)),
+            tag => Err(WireError::InvalidTag { what: "response", tag }),
+        }
+    }
+}
+
+// Chunked, so a scan or diff of any size fits; short messages, the Hello
+// among them, are still single frames
+fn send<T: Wire>(writer: &mut impl Write, message: &T) -> Result<(), WireError> {
+    write_chunked(writer, &to_bytes(message))
+}
+
+fn receive<T: Wire>(reader: &mut impl Read) -> Result<T, WireError> {
+    from_bytes(&read_chunked(reader)?)
+}
+
+/// Client end of a connection to a `jan --server` peer
+pub struct RemoteSession {
+    child: Child,
+    input: BufWriter<ChildStdin>,
+    output: BufReader<ChildStdout>,
+    /// Protocol version and capabilities agreed with the server
+    negotiated: Negotiated,
+    closed: bool,
+}
+
+impl RemoteSession {
+    /// Start `command`, which must run `jan --server` with the destination,
+    /// and exchange [`Hello`]s with it
+    ///
+    /// Its stderr is passed through, so remote warnings reach the user.
+    pub fn spawn(mut command: Command) -> Result<Self> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .with_context(|| format!("Can't start {:?}", command.get_program()))?;
+        let mut input = BufWriter::new(child.stdin.take().context("No stdin for remote")?);
+        let mut output = BufReader::new(child.stdout.take().context("No stdout for remote")?);
+
+        let ours = Hello::local();
+        send(&mut input, &ours)?;
+        input.flush()?;
+        let theirs: Hello = receive(&mut output).context("No handshake from remote")?;
+        let negotiated = ours.negotiate(&theirs).context("Can't talk to remote jan")?;
+
+        Ok(Self {
+            child,
+            input,
+            output,
+            negotiated,
+            closed: false,
+        })
+    }
+
+    /// Connect to `spec` through `rsh` (usually `ssh`)
+    pub fn connect(spec: &RemoteSpec, rsh: &str, remote_jan: &str) -> Result<Self> {
+        Self::spawn(spec.command(rsh, remote_jan)?)
+    }
+
+    /// Lock the remote destination for the rest of the session
+    ///
+    /// Fails naming the holder if another sync has it, unless `wait`.
+    /// Staging or syncing without it takes the lock without waiting.
+    pub fn lock(&mut self, wait: bool) -> Result<()> {
+        match self.call(&Request::Lock { wait })? {
+            Response::Ok => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Scan the remote tree
+    pub fn scan(&mut self, options: &ScanOptions) -> Result<ScanResult> {
+        match self.call(&Request::Scan(options.clone()))? {
+            Response::Scan(scan) => Ok(*scan),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Apply `diff`, sending the content of every file it writes
+    ///
+    /// With `options.delta`, modified files are sent as deltas against the
+    /// remote copy if the server can apply them.
+    pub fn sync(
+        &mut self,
+        source_root: &Path,
+        source: &ScanResult,
+        diff: &DiffResult,
+        options: &SyncOptions,
+    ) -> Result<()> {
+        let delta = options.delta && self.negotiated.has(CAP_DELTA);
+        // Everything past the new files replaces a remote copy
+        for (i, file) in diff.files_to_copy().into_iter().enumerate() {
+            let delta = delta && i >= diff.added.len();
+            if file.symlink_target.is_none() {
+                self.stage(source_root, file, delta)
+                    .with_context(|| format!("Can't send {}", file.path.display()))?;
+            }
+        }
+
+        let request = Request::Sync {
+            diff: Box::new(diff.clone()),
+            dirs: source.dirs.clone(),
+            options: options.clone(),
+        };
+        match self.call(&request)? {
+            Response::Ok => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Say goodbye and wait for the server to exit
+    pub fn close(mut self) -> Result<()> {
+        self.closed = true;
+        send(&mut self.input, &Request::Quit)?;
+        self.input.flush()?;
+        let status = self.child.wait()?;
+        if !status.success() {
+            anyhow::bail!("Remote exited with {status}");
+        }
+        Ok(())
+    }
+
+    fn stage(&mut self, source_root: &Path, file: &FileMeta, delta: bool) -> Result<()> {
+        let signature = if delta {
+            match self.call(&Request::Signature(file.path.clone()))? {
+                Response::Signature(signature) => Some(signature),
+                other => return Err(unexpected(other)),
+            }
+        } else {
+            None
+        };
+
+        send(&mut self.input, &Request::Stage { file: file.clone(), delta })?;
+        let source =
+            BufReader::with_capacity(DATA_CHUNK, File::open(source_root.join(&file.path))?);
+        let input = &mut self.input;
+        match &signature {
+            Some(signature) => {
+                signature
+                    .diff(source, |op| send(input, &Piece::Op(op)).map_err(io::Error::other))?;
+            },
+            None => send_literal(source, input)?,
+        }
+        match self.call(&Piece::End)? {
+            Response::Ok => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    fn call<T: Wire>(&mut self, message: &T) -> Result<Response> {
+        send(&mut self.input, message)?;
+        self.input.flush()?;
+        match receive(&mut self.output).context("Lost connection to remote")? {
+            Response::Error(message) => Err(anyhow::anyhow!("Remote: {message}")),
+            response => Ok(response),
+        }
+    }
+}
+
+impl Drop for RemoteSession {
+    fn drop(&mut self) {
+        if !self.closed {
+            let _ = send(&mut self.input, &Request::Quit);
+            let _ = self.input.flush();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+fn unexpected(response: Response) -> anyhow::Error {
+    let kind = match response {
+        Response::Ok => "ok",
+        Response::Scan(_) => "scan",
+        Response::Signature(_) => "signature",
+        Response::Error(_) => "error",
+    };
+    anyhow::anyhow!("Unexpected {kind} reply from remote")
+}
+
+fn send_literal(mut source: impl Read, output: &mut impl Write) -> Result<(), WireError> {
+    let mut buf = vec![0u8; DATA_CHUNK];
+    loop {
+        let n = source.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        send(output, &Piece::Op(DeltaOp::Literal(buf[..n].to_vec())))?;
+    }
+}
+
+/// Serve one client on stdin/stdout with `root` as the destination
+pub fn serve(root: &Path) -> Result<()> {
+    let stdin = io::stdin().lock();
+    let stdout = io::stdout().lock();
+    serve_on(root, BufReader::new(stdin), BufWriter::new(stdout))
+}
+
+/// Serve one client over any pair of streams until it quits or hangs up
+pub fn serve_on(root: &Path, mut input: impl Read, mut output: impl Write) -> Result<()> {
+    let incoming = root.join(JAN_INCOMING_DIR);
+    // The client speaks first; answer with our Hello even if we can't agree,
+    // so its error says why
+    let theirs: Hello = receive(&mut input).context("No handshake from client")?;
+    let ours = Hello::local();
+    send(&mut output, &ours)?;
+    output.flush()?;
+    ours.negotiate(&theirs).context("Can't talk to client")?;
+
+    let mut signatures: Vec<(PathBuf, Signature)> = Vec::new();
+    let mut lock: Option<DestLock> = None;
+    // Staging belongs to whoever holds the lock, so it is cleared on taking it
+    let take_lock = |lock: &mut Option<DestLock>, wait: bool| -> Result<()> {
+        if lock.is_none() {
+            let held = DestLock::acquire(root, wait)?;
+            if incoming.exists() {
+                fs::remove_dir_all(&incoming)
+                    .with_context(|| format!("Can't clear {}", incoming.display()))?;
+            }
+            *lock = Some(held);
+        }
+        Ok(())
+    };
+
+    let result = loop {
+        let request: Request = match receive(&mut input) {
+            Ok(request) => request,
+            // The client went away without saying goodbye
+            Err(e) if e.is_eof() => break Ok(()),
+            Err(e) => break Err(e.into()),
+        };
+
+        let response = match request {
+            Request::Quit => break Ok(()),
+            Request::Lock { wait } => take_lock(&mut lock, wait).map(|()| Response::Ok),
+            Request::Scan(options) => scan_directory_with_options(root, &options)
+                .map(|scan| Response::Scan(Box::new(scan))),
+            Request::Signature(path) => checked_path(&path).and_then(|path| {
+                let signature = Signature::of_file(&root.join(path))?;
+                signatures.push((path.to_path_buf(), signature.clone()));
+                Ok(Response::Signature(signature))
+            }),
+            Request::Stage { file, delta } => {
+                // Its pieces follow, so there is no answering with an error
+                if let Err(e) = take_lock(&mut lock, false) {
+                    break Err(e);
+                }
+                let signature = match delta {
+                    true => signatures
+                        .iter()
+                        .position(|(path, _)| *path == file.path)
+                        .map(|i| signatures.swap_remove(i).1),
+                    false => None,
+                };
+                match stage_file(root, &incoming, &file, signature.as_ref(), &mut input) {
+                    Ok(staged) => staged.map(|()| Response::Ok),
+                    Err(e) => break Err(e.into()),
+                }
+            },
+            Request::Sync { diff, dirs, options } => {
+                // A backup directory only ever lies inside the served tree
+                let result = take_lock(&mut lock, false)
+                    .and_then(|()| {
+                        options
+                            .backup_dir
+                            .as_deref()
+                            .map_or(Ok(()), |dir| checked_path(dir).map(|_| ()))
+                    })
+                    .and_then(|()| restore_staged_dirs(&incoming, &dirs))
+                    .and_then(|()| sync_changes(&incoming, root, &diff, &options))
+                    .map(|()| Response::Ok);
+                let _ = fs::remove_dir_all(&incoming);
+                result
+            },
+        };
+
+        let response = response.unwrap_or_else(|e| Response::Error(format!("{e:#}")));
+        if let Err(e) = send(&mut output, &response).and_then(|()| Ok(output.flush()?)) {
+            break Err(e.into());
+        }
+    };
+
+    if lock.is_some() {
+        let _ = fs::remove_dir_all(&incoming);
+    }
+    result
+}
+
+/// Reject paths that could step outside the tree
+fn checked_path(path: &Path) -> Result<&Path> {
+    if path.as_os_str().is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_)))
+    {
+        anyhow::bail!("Refusing path {}", path.display());
+    }
+    Ok(path)
+}
+
+/// Receive one file's pieces into the staging area, with its mtime and mode
+///
+/// The outer error means the request stream itself broke; the inner one that
+/// this file couldn't be staged, after its pieces were still read off.
+fn stage_file(
+    root: &Path,
+    incoming: &Path,
+    file: &FileMeta,
+    signature: Option<&Signature>,
+    input: &mut impl Read,
+) -> Result<Result<()>, WireError> {
+    let mut end_seen = false;
+    let mut broken: Option<WireError> = None;
+
+    let written = (|| -> Result<()> {
+        let path = checked_path(&file.path)?;
+        let staged = incoming.join(path);
+        let parent = staged.parent().unwrap_or(incoming);
+        fs::create_dir_all(parent)?;
+        let mut basis = match signature {
+            Some(_) => Some(File::open(root.join(path))?),
+            None => None,
+        };
+
+        let mut writer = AtomicWriter::new(generate_temp_path(incoming), staged.clone(), false)?;
+        let mut scratch = Vec::new();
+        loop {
+            let op = match receive::<Piece>(input) {
+                Ok(Piece::Op(op)) => op,
+                Ok(Piece::End) => {
+                    end_seen = true;
+                    break;
+                },
+                Err(e) => {
+                    let message = e.to_string();
+                    broken = Some(e);
+                    anyhow::bail!(message);
+                },
+            };
+            match (op, signature, basis.as_mut()) {
+                (DeltaOp::Literal(bytes), _, _) => writer.write(&bytes)?,
+                (op, Some(signature), Some(basis)) => {
+                    apply_op(signature, basis, &op, &mut writer, &mut scratch)?
+                },
+                (DeltaOp::Block(_), _, _) => anyhow::bail!("Block reference without a signature"),
+            }
+        }
+        writer.commit(None)?;
+
+        set_file_mtime(&staged, file.mtime)?;
+        #[cfg(unix)]
+        if let Some(mode) = file.permissions {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&staged, fs::Permissions::from_mode(mode))?;
+        }
+        Ok(())
+    })();
+
+    if let Some(e) = broken {
+        return Err(e);
+    }
+    // Drain whatever a failed write left unread, so the next request lines up
+    while !end_seen {
+        end_seen = matches!(receive::<Piece>(input)?, Piece::End);
+    }
+    Ok(written)
+}
+
+/// Give staged directories their source mtime and mode, for `sync_changes` to copy
+fn restore_staged_dirs(incoming: &Path, dirs: &[DirMeta]) -> Result<()> {
+    for dir in dirs {
+        let path = incoming.join(checked_path(&dir.path)?);
+        fs::create_dir_all(&path)?;
+    }
+    // Deepest first, so a parent made read-only can't lock out its children
+    let mut deepest_first: Vec<&DirMeta> = dirs.iter().collect();
+    deepest_first.sort_by_key(|d| std::cmp::Reverse(d.path.components().count()));
+    for dir in deepest_first {
+        let path = incoming.join(&dir.path);
+        #[cfg(unix)]
+        if let Some(mode) = dir.permissions {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+        }
+        File::open(&path)?.set_modified(dir.mtime)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_remote_spec() {
+        let spec = RemoteSpec::parse("me@example.com:/srv/backup").unwrap();
+        assert_eq!(spec.host, "me@example.com");
+        assert_eq!(spec.path, "/srv/backup");
+        assert_eq!(RemoteSpec::parse("box:").unwrap().path, ".");
+
+        assert_eq!(RemoteSpec::parse("/mnt/backup"), None);
+        assert_eq!(RemoteSpec::parse("./odd:name"), None);
+        assert_eq!(RemoteSpec::parse(":path"), None);
+    }
+
+    #[test]
+    fn test_remote_command_quotes_path() {
+        let spec = RemoteSpec::parse("box:/it's here").unwrap();
+        let command = spec.command("ssh -p 2222", "jan").unwrap();
+        let args: Vec<_> = command.get_args().map(|a| a.to_string_lossy().into_owned()).collect();
+        assert_eq!(command.get_program(), "ssh");
+        assert_eq!(args, ["-p", "2222", "box", "jan", "--server", r"'/it'\''s here'"]);
+    }
+
+    #[test]
+    fn test_server_refuses_incompatible_client() {
+        let root = tempfile::tempdir().unwrap();
+        let client = Hello {
+            min_version: 99,
+            max_version: 99,
+            ..Hello::local()
+        };
+        let mut input = Vec::new();
+        send(&mut input, &client).unwrap();
+
+        let mut output = Vec::new();
+        let err = serve_on(root.path(), input.as_slice(), &mut output).unwrap_err();
+        assert!(format!("{err:#}").contains("No common protocol version"), "{err:#}");
+        // The client still gets our Hello, so it can say why too
+        let reply: Hello = receive(&mut output.as_slice()).unwrap();
+        assert_eq!(reply, Hello::local());
+    }
+}
@@ -0,0 +1,447 @@
+//! Undo log for transactional syncs
+//!
+//! With [`SyncOptions::transactional`](crate::core::SyncOptions), every change
+//! to the destination is written to `.jan-rollback/log` before it is made.
+//! Files about to be overwritten are hard-linked into the rollback area first
+//! (copied where links aren't possible), deleted files are moved there rather
+//! than removed, and the metadata of every directory the sync may touch, and
+//! the destination's baseline, are recorded up front. [`rollback`] replays
+//! the log backwards, so a failed sync, or one the user regrets later, leaves
+//! the destination exactly as it was before.
+//!
+//! The area is kept after a successful sync until the next one replaces it.
+//! A log without its closing `DONE` belongs to a sync that was interrupted.
+//! The log uses the journal's record format, see [`SyncJournal`].
+
+use crate::io::{
+    escape_path, format_time, link_or_copy, parse_time, read_records, remove_file_safe,
+    seal_record, undo_move, unescape_path, JournalError, SyncJournal, JAN_BASELINE_FILE,
+    JAN_JOURNAL_FILE, JAN_PLAN_FILE, JAN_ROLLBACK_DIR, JAN_TEMP_DIR,
+};
+use ahash::{HashMap, HashMapExt};
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+const LOG_FILE: &str = "log";
+const FILES_DIR: &str = "files";
+
+/// Undo log format version
+pub const UNDO_LOG_VERSION: u32 = 2;
+
+const UNDO_LOG_KIND: &str = "jan-rollback";
+
+/// What the rollback area of a destination holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UndoState {
+    /// No transactional sync to undo
+    None,
+    /// A transactional sync was cut short
+    Interrupted,
+    /// The last sync was transactional and finished
+    Complete,
+}
+
+/// Whether `dest_root` has a transactional sync that can be rolled back
+pub fn undo_state(dest_root: &Path) -> UndoState {
+    let log = dest_root.join(JAN_ROLLBACK_DIR).join(LOG_FILE);
+    if fs::symlink_metadata(&log).is_err() {
+        return UndoState::None;
+    }
+    // Anything unreadable is left for `rollback` to report
+    match read_log(&log).as_deref().map(<[UndoRecord]>::last) {
+        Ok(Some(UndoRecord::Done)) => UndoState::Complete,
+        _ => UndoState::Interrupted,
+    }
+}
+
+/// Write-ahead record of the changes a transactional sync makes
+pub struct UndoLog {
+    file: Mutex<BufWriter<File>>,
+    root: PathBuf,
+    files: PathBuf,
+    next_backup: AtomicU64,
+}
+
+impl UndoLog {
+    /// Start a new log in `dest_root`, replacing that of an earlier sync
+    ///
+    /// The mtime and mode of those of `dirs` that exist are recorded before
+    /// the log itself changes anything, and the baseline is kept, so that of
+    /// the sync being undone goes with it.
+    pub fn create<'a>(
+        dest_root: &Path,
+        dirs: impl IntoIterator<Item = &'a Path>,
+    ) -> io::Result<Self> {
+        let mut snapshot = Vec::new();
+        for dir in dirs {
+            if let Ok(metadata) = fs::symlink_metadata(dir) {
+                if metadata.is_dir() {
+                    snapshot.push((dir, dir_meta(&metadata)?));
+                }
+            }
+        }
+
+        let area = dest_root.join(JAN_ROLLBACK_DIR);
+        if area.exists() {
+            fs::remove_dir_all(&area)?;
+        }
+        fs::create_dir_all(area.join(FILES_DIR))?;
+        let mut file = File::create(area.join(LOG_FILE))?;
+        writeln!(file, "{UNDO_LOG_KIND} {UNDO_LOG_VERSION}")?;
+        let log = Self::new(dest_root, file, 0);
+        for (dir, (mtime, mode)) in snapshot {
+            log.record(&["DIR", &log.relative(dir), &mtime, &mode])?;
+        }
+        let baseline = dest_root.join(JAN_BASELINE_FILE);
+        match fs::symlink_metadata(&baseline) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => log.record(&["BASELINE"])?,
+            Err(e) => return Err(e),
+            Ok(metadata) => {
+                let backup = log.next_backup();
+                log.record(&["BASELINE", &log.relative(&backup)])?;
+                link_or_copy(&baseline, &backup, &metadata)?;
+            },
+        }
+        Ok(log)
+    }
+
+    /// Keep logging into the area of an interrupted sync being resumed
+    pub fn reopen(dest_root: &Path) -> io::Result<Self> {
+        let area = dest_root.join(JAN_ROLLBACK_DIR);
+        let files = area.join(FILES_DIR);
+        fs::create_dir_all(&files)?;
+        let next = fs::read_dir(&files)?
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u64>().ok())
+            .max()
+            .map_or(0, |n| n + 1);
+        let log = area.join(LOG_FILE);
+        // Drop a record torn by the crash, or the next one would join it
+        let content = fs::read(&log)?;
+        let whole = content.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        let file = OpenOptions::new().append(true).open(&log)?;
+        file.set_len(whole as u64)?;
+        Ok(Self::new(dest_root, file, next))
+    }
+
+    fn new(dest_root: &Path, file: File, next_backup: u64) -> Self {
+        Self {
+            file: Mutex::new(BufWriter::new(file)),
+            root: dest_root.to_path_buf(),
+            files: dest_root.join(JAN_ROLLBACK_DIR).join(FILES_DIR),
+            next_backup: AtomicU64::new(next_backup),
+        }
+    }
+
+    fn record(&self, fields: &[&str]) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        file.write_all(seal_record(fields).as_bytes())?;
+        file.flush()
+    }
+
+    fn relative(&self, path: &Path) -> String {
+        escape_path(path.strip_prefix(&self.root).unwrap_or(path))
+    }
+
+    /// `fs::create_dir_all`, logging each directory it creates
+    pub fn create_dir_all(&self, dir: &Path) -> io::Result<()> {
+        let missing: Vec<&Path> = dir.ancestors().take_while(|d| !d.exists()).collect();
+        for created in missing.iter().rev() {
+            self.record(&["MKDIR", &self.relative(created)])?;
+        }
+        fs::create_dir_all(dir)
+    }
+
+    /// Call before writing `path`: keep its current content, or note it is new
+    pub fn before_write(&self, path: &Path) -> io::Result<()> {
+        match fs::symlink_metadata(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.record(&["CREATE", &self.relative(path)])
+            },
+            Err(e) => Err(e),
+            Ok(metadata) => {
+                let backup = self.next_backup();
+                self.record(&["BACKUP", &self.relative(path), &self.relative(&backup)])?;
+                link_or_copy(path, &backup, &metadata)
+            },
+        }
+    }
+
+    /// Call before moving `old` to `new`
+    pub fn record_move(&self, old: &Path, new: &Path) -> io::Result<()> {
+        self.record(&["MOVE", &self.relative(old), &self.relative(new)])
+    }
+
+    /// Delete `path` by moving it into the rollback area
+    pub fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let backup = self.next_backup();
+        self.record(&["BACKUP", &self.relative(path), &self.relative(&backup)])?;
+        match fs::rename(path, &backup) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Remove the empty directory `path`, remembering its metadata
+    pub fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        let (mtime, mode) = dir_meta(&fs::symlink_metadata(path)?)?;
+        self.record(&["RMDIR", &self.relative(path), &mtime, &mode])?;
+        fs::remove_dir(path)
+    }
+
+    /// Mark the sync as finished; the log now undoes all of it
+    pub fn finish(self) -> io::Result<()> {
+        self.record(&["DONE"])
+    }
+
+    fn next_backup(&self) -> PathBuf {
+        self.files.join(self.next_backup.fetch_add(1, Ordering::Relaxed).to_string())
+    }
+}
+
+fn dir_meta(metadata: &fs::Metadata) -> io::Result<(String, String)> {
+    let mtime = format_time(metadata.modified()?);
+    #[cfg(unix)]
+    let mode = {
+        use std::os::unix::fs::PermissionsExt;
+        format!("{:o}", metadata.permissions().mode() & 0o7777)
+    };
+    #[cfg(not(unix))]
+    let mode = "-".to_string();
+    Ok((mtime, mode))
+}
+
+/// What a rollback put back
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RollbackStats {
+    /// Files and directories restored or moved back
+    pub restored: usize,
+    /// Files and directories the sync had created, now removed
+    pub removed: usize,
+}
+
+/// Undo the last transactional sync into `dest_root`
+///
+/// Works on finished and interrupted syncs alike. Any leftover journal is
+/// recovered first, and the rollback area, the saved plan and partial copies
+/// are gone afterwards.
+pub fn rollback(dest_root: &Path) -> Result<RollbackStats> {
+    let area = dest_root.join(JAN_ROLLBACK_DIR);
+    let log_path = area.join(LOG_FILE);
+    if fs::symlink_metadata(&log_path).is_err() {
+        anyhow::bail!("Nothing to roll back in {}", dest_root.display());
+    }
+    let records = read_log(&log_path)?;
+
+    let partials =
+        SyncJournal::recover(&dest_root.join(JAN_JOURNAL_FILE), &dest_root.join(JAN_TEMP_DIR))
+            .context("Journal recovery failed")?;
+    for partial in partials {
+        let _ = fs::remove_file(partial.temp_path);
+    }
+
+    let mut stats = RollbackStats::default();
+    // Walking backwards, the last record seen for a directory is its oldest state
+    let mut dir_meta: HashMap<PathBuf, (SystemTime, Option<u32>)> = HashMap::new();
+
+    for record in records.into_iter().rev() {
+        match record {
+            UndoRecord::Create(created) => {
+                let created = dest_root.join(created);
+                if fs::symlink_metadata(&created).is_ok_and(|m| !m.is_dir()) {
+                    remove_file_safe(&created)
+                        .with_context(|| format!("Can't remove {}", created.display()))?;
+                    stats.removed += 1;
+                }
+            },
+            UndoRecord::Backup(original, backup) => {
+                let (original, backup) = (dest_root.join(original), dest_root.join(backup));
+                if fs::symlink_metadata(&backup).is_ok() {
+                    if let Some(parent) = original.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::rename(&backup, &original)
+                        .with_context(|| format!("Can't restore {}", original.display()))?;
+                    stats.restored += 1;
+                }
+            },
+            UndoRecord::Move(old, new) => {
+                let (old, new) = (dest_root.join(old), dest_root.join(new));
+                if fs::symlink_metadata(&new).is_ok() {
+                    if let Some(parent) = old.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    undo_move(&old, &new)
+                        .with_context(|| format!("Can't move back {}", old.display()))?;
+                    stats.restored += 1;
+                }
+            },
+            UndoRecord::Mkdir(dir) => {
+                // Still holding files the sync didn't create means it isn't ours to remove
+                if fs::remove_dir(dest_root.join(dir)).is_ok() {
+                    stats.removed += 1;
+                }
+            },
+            UndoRecord::Dir { path, mtime, mode, removed } => {
+                let dir = dest_root.join(path);
+                if removed && !dir.exists() {
+                    fs::create_dir_all(&dir)
+                        .with_context(|| format!("Can't recreate {}", dir.display()))?;
+                    stats.restored += 1;
+                }
+                dir_meta.insert(dir, (mtime, mode));
+            },
+            // Not counted: the user never sees the baseline as a change
+            UndoRecord::Baseline(backup) => {
+                let baseline = dest_root.join(JAN_BASELINE_FILE);
+                match backup.map(|backup| dest_root.join(backup)) {
+                    Some(backup) if fs::symlink_metadata(&backup).is_ok() => {
+                        fs::rename(&backup, &baseline)
+                            .with_context(|| format!("Can't restore {}", baseline.display()))?;
+                    },
+                    Some(_) => {},
+                    None => remove_file_safe(&baseline)
+                        .with_context(|| format!("Can't remove {}", baseline.display()))?,
+                }
+            },
+            UndoRecord::Done => {},
+        }
+    }
+
+    // Nothing is left to undo but metadata, which removing these would change
+    fs::remove_dir_all(&area).with_context(|| format!("Can't remove {}", area.display()))?;
+    let _ = remove_file_safe(&dest_root.join(JAN_PLAN_FILE));
+    let _ = fs::remove_dir_all(dest_root.join(JAN_TEMP_DIR));
+
+    // Directory metadata last and deepest first, once nothing else moves in them
+    let mut dir_meta: Vec<_> = dir_meta.into_iter().collect();
+    dir_meta.sort_by_key(|(dir, _)| std::cmp::Reverse(dir.components().count()));
+    for (dir, (mtime, mode)) in dir_meta {
+        if !dir.is_dir() {
+            continue;
+        }
+        #[cfg(unix)]
+        if let Some(mode) = mode {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&dir, fs::Permissions::from_mode(mode))?;
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+        File::open(&dir)
+            .and_then(|f| f.set_modified(mtime))
+            .with_context(|| format!("Can't restore mtime of {}", dir.display()))?;
+    }
+
+    Ok(stats)
+}
+
+/// One change in the undo log, with paths relative to the destination
+enum UndoRecord {
+    /// A file the sync created
+    Create(PathBuf),
+    /// An overwritten or deleted file, and where its old content is kept
+    Backup(PathBuf, PathBuf),
+    /// A file or directory moved from the first path to the second
+    Move(PathBuf, PathBuf),
+    /// A directory the sync created
+    Mkdir(PathBuf),
+    /// A directory's metadata before the sync, and whether the sync removed it
+    Dir {
+        path: PathBuf,
+        mtime: SystemTime,
+        mode: Option<u32>,
+        removed: bool,
+    },
+    /// Where the baseline from before the sync is kept, if there was one
+    Baseline(Option<PathBuf>),
+    /// The sync finished
+    Done,
+}
+
+fn read_log(path: &Path) -> Result<Vec<UndoRecord>, JournalError> {
+    read_records(path, UNDO_LOG_KIND, UNDO_LOG_VERSION, |fields| {
+        Some(match fields {
+            ["CREATE", path] => UndoRecord::Create(unescape_path(path)?),
+            ["BACKUP", original, backup] => {
+                UndoRecord::Backup(unescape_path(original)?, unescape_path(backup)?)
+            },
+            ["MOVE", old, new] => UndoRecord::Move(unescape_path(old)?, unescape_path(new)?),
+            ["MKDIR", path] => UndoRecord::Mkdir(unescape_path(path)?),
+            [kind @ ("RMDIR" | "DIR"), path, mtime, mode] => UndoRecord::Dir {
+                path: unescape_path(path)?,
+                mtime: parse_time(mtime)?,
+                mode: u32::from_str_radix(mode, 8).ok(),
+                removed: *kind == "RMDIR",
+            },
+            ["BASELINE"] => UndoRecord::Baseline(None),
+            ["BASELINE", backup] => UndoRecord::Baseline(Some(unescape_path(backup)?)),
+            ["DONE"] => UndoRecord::Done,
+            _ => return None,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_rollback_undoes_logged_changes() -> Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path();
+        fs::create_dir(root.join("docs"))?;
+        fs::write(root.join("docs/kept.txt"), b"original")?;
+        fs::write(root.join("gone\tfile.txt"), b"deleted")?;
+        fs::write(root.join("old.txt"), b"moved")?;
+        let docs_mtime = fs::metadata(root.join("docs"))?.modified()?;
+
+        let log = UndoLog::create(root, [root, &root.join("docs")])?;
+        log.before_write(&root.join("docs/kept.txt"))?;
+        // Syncs replace files rather than writing into them
+        fs::write(root.join("new.tmp"), b"overwritten")?;
+        fs::rename(root.join("new.tmp"), root.join("docs/kept.txt"))?;
+        log.create_dir_all(&root.join("new/deeper"))?;
+        log.before_write(&root.join("new/deeper/added.txt"))?;
+        fs::write(root.join("new/deeper/added.txt"), b"added")?;
+        log.record_move(&root.join("old.txt"), &root.join("new/moved.txt"))?;
+        fs::rename(root.join("old.txt"), root.join("new/moved.txt"))?;
+        log.remove_file(&root.join("gone\tfile.txt"))?;
+        log.finish()?;
+        assert_eq!(undo_state(root), UndoState::Complete);
+
+        let stats = rollback(root)?;
+
+        assert_eq!(fs::read(root.join("docs/kept.txt"))?, b"original");
+        assert_eq!(fs::read(root.join("gone\tfile.txt"))?, b"deleted");
+        assert_eq!(fs::read(root.join("old.txt"))?, b"moved");
+        assert!(!root.join("new").exists());
+        assert_eq!(fs::metadata(root.join("docs"))?.modified()?, docs_mtime);
+        assert_eq!(stats, RollbackStats { restored: 3, removed: 3 });
+        assert_eq!(undo_state(root), UndoState::None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unfinished_log_is_interrupted() -> Result<()> {
+        let dir = tempdir()?;
+        let log = UndoLog::create(dir.path(), [])?;
+        log.before_write(&dir.path().join("new.txt"))?;
+        drop(log);
+        assert_eq!(undo_state(dir.path()), UndoState::Interrupted);
+
+        // Killed mid-record, then resumed
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(dir.path().join(JAN_ROLLBACK_DIR).join(LOG_FILE))?;
+        file.write_all(b"CREATE\tha")?;
+        let log = UndoLog::reopen(dir.path())?;
+        log.finish()?;
+        assert_eq!(undo_state(dir.path()), UndoState::Complete);
+        Ok(())
+    }
+}
@@ -0,0 +1,633 @@
+//! Time-machine-style snapshots of a tree
+//!
+//! `jan backup SRC DESTROOT` keeps a complete copy of the source per run, in
+//! a directory of DESTROOT named for the minute it was taken, e.g.
+//! `2026-10-16T0200` (UTC). Only what changed since the latest snapshot is
+//! copied: every file whose hash, mtime and permissions match a file of that
+//! snapshot is hard-linked to it instead, so a snapshot costs the space of
+//! its changes while staying a plain tree to copy files out of.
+//!
+//! A snapshot is built in DESTROOT's `.jan-tmp` and renamed into place when
+//! complete, so a snapshot directory that exists is never half-written. Each
+//! one keeps a `.jan-index` of its hashes, so the next backup compares
+//! against it without reading it all again.
+//!
+//! `jan prune` deletes old snapshots by [`KeepPolicy`], restic style. Since
+//! snapshots share files, deleting one frees only what no other links to;
+//! [`space_freed`] counts exactly that.
+
+use crate::core::{
+    content_differs, diff_scans, scan_directory_with_options, DiffResult, FileMeta, ScanOptions,
+    ScanResult, SymlinkMode,
+};
+use crate::hash::{hash_file, ContentHash};
+use crate::index::{HashIndex, IndexKey};
+use crate::io::{
+    atomic_copy_file_with_metadata, atomic_hard_link, atomic_symlink, format_utc, fsync_directory,
+    generate_temp_path, parse_utc, remove_dir_recursive, set_file_mtime, JAN_TEMP_DIR,
+};
+use ahash::{HashMap, HashMapExt};
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One complete snapshot in a backup root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Directory name, from the time the snapshot was taken
+    pub name: String,
+    pub taken: SystemTime,
+    pub path: PathBuf,
+}
+
+/// What a backup is about to do
+#[derive(Debug, Clone)]
+pub struct SnapshotPlan {
+    pub source: ScanResult,
+    /// The latest snapshot, which unchanged files are linked to
+    pub previous: Option<Snapshot>,
+    /// What `previous` holds; empty without one
+    pub previous_scan: ScanResult,
+    /// The source against `previous`, for display; without one, all is new
+    pub diff: DiffResult,
+}
+
+/// What taking a snapshot wrote
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SnapshotStats {
+    /// Files hard-linked to the previous snapshot
+    pub linked: usize,
+    pub linked_bytes: u64,
+    /// Files and links copied from the source
+    pub copied: usize,
+    pub copied_bytes: u64,
+}
+
+/// Every snapshot in `dest_root`, oldest first
+///
+/// Entries that aren't directories named like a snapshot are not jan's and
+/// left out.
+pub fn snapshots(dest_root: &Path) -> Result<Vec<Snapshot>> {
+    let entries = match fs::read_dir(dest_root) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        entries => entries.with_context(|| format!("Can't list {}", dest_root.display()))?,
+    };
+    let mut found = Vec::new();
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Can't list {}", dest_root.display()))?;
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        let Some((taken, seq)) = parse_snapshot_name(&name) else {
+            continue;
+        };
+        if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+            continue;
+        }
+        found.push((seq, Snapshot { name, taken, path: entry.path() }));
+    }
+    found.sort_by_key(|(seq, snapshot)| (snapshot.taken, *seq));
+    Ok(found.into_iter().map(|(_, snapshot)| snapshot).collect())
+}
+
+/// The newest snapshot in `dest_root`, if there is one
+pub fn latest_snapshot(dest_root: &Path) -> Result<Option<Snapshot>> {
+    Ok(snapshots(dest_root)?.pop())
+}
+
+/// Name of a snapshot taken at `time`: `YYYY-MM-DDTHHMM` in UTC
+pub fn snapshot_name(time: SystemTime) -> String {
+    let utc = format_utc(time);
+    let (date, clock) = utc.split_once(' ').unwrap_or((&utc, ""));
+    let minutes: String = clock.chars().take(5).filter(|c| *c != ':').collect();
+    format!("{date}T{minutes}")
+}
+
+/// When a snapshot was taken, and where it comes among those of that minute
+fn parse_snapshot_name(name: &str) -> Option<(SystemTime, u32)> {
+    let (stamp, rest) = (name.get(..15)?, &name[15..]);
+    let seq = match rest {
+        "" => 1,
+        rest => rest.strip_prefix('-')?.parse().ok()?,
+    };
+    let (date, clock) = stamp.split_once('T')?;
+    if clock.len() != 4 || !clock.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let taken = parse_utc(&format!("{date} {}:{}:00 UTC", &clock[..2], &clock[2..]))?;
+    Some((taken, seq))
+}
+
+/// Name for a snapshot taken at `time`, not yet taken in `dest_root`
+fn new_snapshot_name(dest_root: &Path, time: SystemTime) -> String {
+    let base = snapshot_name(time);
+    let mut name = base.clone();
+    let mut n = 1;
+    while fs::symlink_metadata(dest_root.join(&name)).is_ok() {
+        n += 1;
+        name = format!("{base}-{n}");
+    }
+    name
+}
+
+/// Scan `source_root` and compare it with the latest snapshot in `dest_root`
+pub fn plan_snapshot(
+    source_root: &Path,
+    dest_root: &Path,
+    options: &ScanOptions,
+) -> Result<SnapshotPlan> {
+    let source = scan_directory_with_options(source_root, options)?;
+    let previous = latest_snapshot(dest_root)?;
+    let previous_scan = match &previous {
+        // Snapshots are seen as they are, links included. Quick, since
+        // pruning older snapshots changes the ctime of the files they shared
+        // and with it what the index vouches for.
+        Some(snapshot) => scan_directory_with_options(
+            &snapshot.path,
+            &ScanOptions {
+                use_index: true,
+                symlinks: SymlinkMode::Preserve,
+                quick: true,
+                ..ScanOptions::default()
+            },
+        )?,
+        None => ScanResult {
+            root: dest_root.to_path_buf(),
+            files: Vec::new(),
+            dirs: Vec::new(),
+            excluded: Vec::new(),
+            scan_time: SystemTime::now(),
+        },
+    };
+    let diff = diff_scans(&source, &previous_scan)?;
+    Ok(SnapshotPlan { source, previous, previous_scan, diff })
+}
+
+/// Take a new snapshot in `dest_root` as `plan` says
+///
+/// Files matching one in the previous snapshot are linked to it, the rest
+/// copied from the source, checked against their scanned hash if `verify`.
+/// The snapshot appears in `dest_root` only once all of it is written; a
+/// backup interrupted before leaves nothing but temp files, which the next
+/// one removes.
+pub fn take_snapshot(
+    plan: &SnapshotPlan,
+    dest_root: &Path,
+    verify: bool,
+) -> Result<(Snapshot, SnapshotStats)> {
+    let temp_dir = dest_root.join(JAN_TEMP_DIR);
+    remove_dir_recursive(&temp_dir)
+        .with_context(|| format!("Can't clear {}", temp_dir.display()))?;
+    fs::create_dir_all(&temp_dir)
+        .with_context(|| format!("Can't create {}", temp_dir.display()))?;
+
+    let taken = SystemTime::now();
+    let name = new_snapshot_name(dest_root, taken);
+    let staging = temp_dir.join(&name);
+    fs::create_dir(&staging).with_context(|| format!("Can't create {}", staging.display()))?;
+    for dir in &plan.source.dirs {
+        let path = staging.join(&dir.path);
+        fs::create_dir_all(&path).with_context(|| format!("Can't create {}", path.display()))?;
+    }
+
+    // Regular files of the previous snapshot, to link unchanged ones to
+    let regular = |f: &&FileMeta| f.symlink_target.is_none();
+    let previous_by_path: HashMap<&Path, &FileMeta> = plan
+        .previous_scan
+        .files
+        .iter()
+        .filter(regular)
+        .map(|f| (f.path.as_path(), f))
+        .collect();
+    let mut previous_by_hash: HashMap<&ContentHash, Vec<&FileMeta>> = HashMap::new();
+    let mut unhashed_by_size: HashMap<u64, Vec<&FileMeta>> = HashMap::new();
+    for file in plan.previous_scan.files.iter().filter(regular) {
+        match &file.hash {
+            Some(hash) => previous_by_hash.entry(hash).or_default().push(file),
+            None => unhashed_by_size.entry(file.size).or_default().push(file),
+        }
+    }
+    // A link shares its inode, so everything it carries must match. At the
+    // same path, size and mtime will do where the index no longer vouches
+    // for a hash, as the diff judged it.
+    let linkable = |file: &FileMeta| {
+        let same_meta =
+            |old: &&FileMeta| old.mtime == file.mtime && old.permissions == file.permissions;
+        let at_path = previous_by_path
+            .get(file.path.as_path())
+            .copied()
+            .filter(|old| same_meta(old) && !content_differs(file, old));
+        let hashed = || {
+            let candidates = previous_by_hash.get(file.hash.as_ref()?)?;
+            candidates.iter().copied().find(same_meta)
+        };
+        // Moved files are only worth hashing if all else matches
+        let unhashed = || {
+            let candidates = unhashed_by_size.get(&file.size)?;
+            candidates.iter().copied().filter(same_meta).find(|old| {
+                hash_file(&plan.previous_scan.root.join(&old.path)).ok().as_ref()
+                    == file.hash.as_ref()
+            })
+        };
+        at_path.or_else(hashed).or_else(unhashed)
+    };
+
+    let linked: Vec<bool> = plan
+        .source
+        .files
+        .par_iter()
+        .map(|file| {
+            let target = staging.join(&file.path);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Can't create {}", parent.display()))?;
+            }
+            if let Some(link) = &file.symlink_target {
+                atomic_symlink(link, &target, &generate_temp_path(&temp_dir))
+                    .with_context(|| format!("Can't create {}", target.display()))?;
+                return Ok(false);
+            }
+            if let Some(old) = linkable(file) {
+                let existing = plan.previous_scan.root.join(&old.path);
+                // Past the filesystem's link limit, a copy will do
+                if atomic_hard_link(&existing, &target, &generate_temp_path(&temp_dir)).is_ok() {
+                    return Ok(true);
+                }
+            }
+            let source = plan.source.root.join(&file.path);
+            let expected_hash = if verify { file.hash.as_ref() } else { None };
+            atomic_copy_file_with_metadata(
+                &source,
+                &target,
+                &generate_temp_path(&temp_dir),
+                true,
+                verify,
+                expected_hash,
+            )
+            .with_context(|| format!("Can't copy {}", source.display()))?;
+            Ok(false)
+        })
+        .collect::<Result<_>>()?;
+
+    let mut stats = SnapshotStats::default();
+    for (file, linked) in plan.source.files.iter().zip(linked) {
+        if linked {
+            stats.linked += 1;
+            stats.linked_bytes += file.size;
+        } else {
+            stats.copied += 1;
+            stats.copied_bytes += file.size;
+        }
+    }
+
+    // Directory metadata last, once nothing is written into them anymore
+    for dir in &plan.source.dirs {
+        let path = staging.join(&dir.path);
+        if let Err(e) = set_file_mtime(&path, dir.mtime) {
+            eprintln!("Warning: can't set mtime of {}: {e}", path.display());
+        }
+        #[cfg(unix)]
+        if let Some(mode) = dir.permissions {
+            use std::os::unix::fs::PermissionsExt;
+            if let Err(e) = fs::set_permissions(&path, fs::Permissions::from_mode(mode)) {
+                eprintln!("Warning: can't set permissions of {}: {e}", path.display());
+            }
+        }
+    }
+
+    // Files that changed since the scan were written as they are now, which
+    // the scanned hash doesn't vouch for
+    let mut index = HashIndex::new();
+    for file in plan.source.files.iter().filter(regular) {
+        let (Some(hash), Ok(metadata)) =
+            (&file.hash, fs::symlink_metadata(staging.join(&file.path)))
+        else {
+            continue;
+        };
+        if metadata.len() == file.size && metadata.modified().is_ok_and(|m| m == file.mtime) {
+            let key = IndexKey::from_metadata(&metadata);
+            index.insert(file.path.clone(), key, hash.clone(), plan.source.scan_time);
+        }
+    }
+    if let Err(e) = index.save(&staging) {
+        eprintln!("Warning: failed to write hash index for {name}: {e}");
+    }
+
+    let path = dest_root.join(&name);
+    fs::rename(&staging, &path)
+        .with_context(|| format!("Can't publish snapshot {}", path.display()))?;
+    fsync_directory(dest_root)?;
+    let _ = fs::remove_dir(&temp_dir);
+
+    // As listing will see it, to the minute
+    let taken = parse_snapshot_name(&name).map_or(taken, |(taken, _)| taken);
+    Ok((Snapshot { name, taken, path }, stats))
+}
+
+/// How many snapshots pruning keeps, counted from the newest
+///
+/// `last` keeps that many snapshots outright; each other rule keeps the
+/// newest snapshot of that many distinct hours, days, ISO weeks, months or
+/// years (UTC) that have one. A snapshot stays if any rule keeps it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeepPolicy {
+    pub last: usize,
+    pub hourly: usize,
+    pub daily: usize,
+    pub weekly: usize,
+    pub monthly: usize,
+    pub yearly: usize,
+}
+
+impl KeepPolicy {
+    /// Whether the policy keeps nothing at all
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Whether pruning keeps a snapshot, and by which rules
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PruneDecision {
+    pub snapshot: Snapshot,
+    /// Rules keeping it, e.g. `["last", "daily"]`; empty if it goes
+    pub reasons: Vec<&'static str>,
+}
+
+impl PruneDecision {
+    pub fn keep(&self) -> bool {
+        !self.reasons.is_empty()
+    }
+}
+
+/// Decide which of `snapshots` (oldest first, as [`snapshots`] lists them)
+/// `policy` keeps, in the same order
+pub fn plan_prune(snapshots: Vec<Snapshot>, policy: &KeepPolicy) -> Vec<PruneDecision> {
+    let rules = [
+        ("last", policy.last),
+        ("hourly", policy.hourly),
+        ("daily", policy.daily),
+        ("weekly", policy.weekly),
+        ("monthly", policy.monthly),
+        ("yearly", policy.yearly),
+    ];
+    let mut decisions: Vec<PruneDecision> = snapshots
+        .into_iter()
+        .map(|snapshot| PruneDecision { snapshot, reasons: Vec::new() })
+        .collect();
+    for (rule, count) in rules {
+        let mut kept = 0;
+        let mut last_period = None;
+        for decision in decisions.iter_mut().rev() {
+            if kept == count {
+                break;
+            }
+            let period = period(rule, &decision.snapshot);
+            if last_period.as_ref() != Some(&period) {
+                decision.reasons.push(rule);
+                kept += 1;
+                last_period = Some(period);
+            }
+        }
+    }
+    decisions
+}
+
+/// The period `snapshot` falls in, of which `rule` keeps one snapshot
+fn period(rule: &str, snapshot: &Snapshot) -> String {
+    let utc = format_utc(snapshot.taken);
+    match rule {
+        "hourly" => utc[..13].to_string(),
+        "daily" => utc[..10].to_string(),
+        // Weeks start on Monday; the epoch was a Thursday
+        "weekly" => {
+            let days =
+                snapshot.taken.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() / 86_400);
+            ((days + 3) / 7).to_string()
+        },
+        "monthly" => utc[..7].to_string(),
+        "yearly" => utc[..4].to_string(),
+        _ => snapshot.name.clone(),
+    }
+}
+
+/// Bytes deleting snapshots would free
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpaceFreed {
+    /// For each snapshot, what it alone links to
+    pub per_snapshot: Vec<u64>,
+    /// For all of them together, which adds what only they share
+    pub total: u64,
+}
+
+/// How much space deleting `snapshots` frees
+///
+/// A file only frees its bytes once every link to it is gone, so those still
+/// linked from a snapshot that stays, or from anywhere else, count for
+/// nothing.
+pub fn space_freed(snapshots: &[&Snapshot]) -> Result<SpaceFreed> {
+    let mut freed = SpaceFreed {
+        per_snapshot: vec![0; snapshots.len()],
+        total: 0,
+    };
+    let mut inodes: HashMap<(u64, u64), InodeLinks> = HashMap::new();
+    for (i, snapshot) in snapshots.iter().enumerate() {
+        walk_files(&snapshot.path, &mut |metadata| match inode(metadata) {
+            Some((id, links)) => {
+                let entry = inodes.entry(id).or_insert(InodeLinks {
+                    size: metadata.len(),
+                    links,
+                    seen: 0,
+                    only_in: Some(i),
+                });
+                entry.seen += 1;
+                if entry.only_in != Some(i) {
+                    entry.only_in = None;
+                }
+            },
+            // No telling what else links to it; as if nothing did
+            None => {
+                freed.per_snapshot[i] += metadata.len();
+                freed.total += metadata.len();
+            },
+        })
+        .with_context(|| format!("Can't read {}", snapshot.path.display()))?;
+    }
+
+    for inode in inodes.into_values().filter(|inode| inode.seen >= inode.links) {
+        freed.total += inode.size;
+        if let Some(i) = inode.only_in {
+            freed.per_snapshot[i] += inode.size;
+        }
+    }
+    Ok(freed)
+}
+
+/// One file's links among the snapshots being deleted
+struct InodeLinks {
+    size: u64,
+    /// All its links, anywhere
+    links: u64,
+    /// Links found in the snapshots
+    seen: u64,
+    /// The snapshot holding every link found, if only one does
+    only_in: Option<usize>,
+}
+
+/// Delete `snapshot` from `dest_root`
+///
+/// It is moved into `.jan-tmp` first, so a deletion cut short never leaves
+/// a partial snapshot to be listed or linked to. Files are never touched,
+/// since other snapshots share them; read-only directories are made
+/// writable to be emptied.
+pub fn remove_snapshot(dest_root: &Path, snapshot: &Snapshot) -> Result<()> {
+    let temp_dir = dest_root.join(JAN_TEMP_DIR);
+    fs::create_dir_all(&temp_dir)
+        .with_context(|| format!("Can't create {}", temp_dir.display()))?;
+    let doomed = generate_temp_path(&temp_dir);
+    fs::rename(&snapshot.path, &doomed)
+        .with_context(|| format!("Can't remove {}", snapshot.path.display()))?;
+    fsync_directory(dest_root)?;
+
+    let removed = match fs::remove_dir_all(&doomed) {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            make_dirs_writable(&doomed).and_then(|()| fs::remove_dir_all(&doomed))
+        },
+        result => result,
+    };
+    removed.with_context(|| format!("Can't remove {}", snapshot.path.display()))?;
+    let _ = fs::remove_dir(&temp_dir);
+    Ok(())
+}
+
+/// Call `visit` with the metadata of every file under `dir`, links included
+fn walk_files(dir: &Path, visit: &mut dyn FnMut(&fs::Metadata)) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = fs::symlink_metadata(entry.path())?;
+        if metadata.is_dir() {
+            walk_files(&entry.path(), visit)?;
+        } else {
+            visit(&metadata);
+        }
+    }
+    Ok(())
+}
+
+/// A file's device and inode, and how many links it has
+#[cfg(unix)]
+fn inode(metadata: &fs::Metadata) -> Option<((u64, u64), u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some(((metadata.dev(), metadata.ino()), metadata.nlink()))
+}
+
+/// A file's device and inode, and how many links it has
+#[cfg(not(unix))]
+fn inode(_metadata: &fs::Metadata) -> Option<((u64, u64), u64)> {
+    None
+}
+
+fn make_dirs_writable(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::symlink_metadata(dir)?.permissions().mode();
+        fs::set_permissions(dir, fs::Permissions::from_mode(mode | 0o700))?;
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            make_dirs_writable(&entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_snapshot_names_round_trip() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(snapshot_name(time), "2024-02-29T1234");
+        let minute = UNIX_EPOCH + Duration::from_secs(1_709_210_040);
+        assert_eq!(parse_snapshot_name("2024-02-29T1234"), Some((minute, 1)));
+        assert_eq!(parse_snapshot_name("2024-02-29T1234-3"), Some((minute, 3)));
+        assert_eq!(parse_snapshot_name("2024-02-29T12:34"), None);
+        assert_eq!(parse_snapshot_name("2024-02-29T1234.old"), None);
+        assert_eq!(parse_snapshot_name(".jan-tmp"), None);
+    }
+
+    fn snapshot_at(time: &str) -> Snapshot {
+        let taken = parse_utc(&format!("{time}:00 UTC")).unwrap();
+        let name = snapshot_name(taken);
+        Snapshot { path: PathBuf::from(&name), name, taken }
+    }
+
+    fn kept(policy: &KeepPolicy, times: &[&str]) -> Vec<(String, Vec<&'static str>)> {
+        let snapshots = times.iter().map(|time| snapshot_at(time)).collect();
+        plan_prune(snapshots, policy)
+            .into_iter()
+            .filter(PruneDecision::keep)
+            .map(|decision| (decision.snapshot.name, decision.reasons))
+            .collect()
+    }
+
+    #[test]
+    fn test_prune_keeps_newest_of_each_period() {
+        let times = [
+            "2024-12-30 02:00", // Monday, the week of the Sunday after
+            "2025-01-05 02:00",
+            "2025-01-06 02:00", // Monday
+            "2025-01-06 14:00",
+            "2025-01-07 02:00",
+            "2025-01-07 03:00",
+        ];
+        let last = |name: &str| (name.to_string(), vec!["last"]);
+        let policy = KeepPolicy { last: 2, ..KeepPolicy::default() };
+        assert_eq!(kept(&policy, &times), [last("2025-01-07T0200"), last("2025-01-07T0300")]);
+
+        let policy = KeepPolicy {
+            daily: 3,
+            weekly: 2,
+            yearly: 5,
+            ..KeepPolicy::default()
+        };
+        assert_eq!(
+            kept(&policy, &times),
+            [
+                ("2024-12-30T0200".to_string(), vec!["yearly"]),
+                ("2025-01-05T0200".to_string(), vec!["daily", "weekly"]),
+                ("2025-01-06T1400".to_string(), vec!["daily"]),
+                ("2025-01-07T0300".to_string(), vec!["daily", "weekly", "yearly"]),
+            ]
+        );
+
+        let policy = KeepPolicy { hourly: 24, ..KeepPolicy::default() };
+        assert_eq!(kept(&policy, &times).len(), 6);
+        assert!(kept(&KeepPolicy::default(), &times).is_empty());
+    }
+
+    #[test]
+    fn test_snapshots_sort_by_time_then_sequence() {
+        let dir = tempdir().unwrap();
+        for name in ["2024-03-01T0200", "2024-02-29T1234-2", "2024-02-29T1234", "notes"] {
+            fs::create_dir(dir.path().join(name)).unwrap();
+        }
+        fs::write(dir.path().join("2024-03-02T0200"), b"not a directory").unwrap();
+
+        let names: Vec<_> = snapshots(dir.path())
+            .unwrap()
+            .into_iter()
+            .map(|snapshot| snapshot.name)
+            .collect();
+        assert_eq!(names, ["2024-02-29T1234", "2024-02-29T1234-2", "2024-03-01T0200"]);
+        assert_eq!(latest_snapshot(dir.path()).unwrap().unwrap().name, "2024-03-01T0200");
+    }
+}
@@ -0,0 +1,329 @@
+//! Files displaced by syncs, kept instead of lost
+//!
+//! A sync with a [`backup_dir`](crate::core::SyncOptions::backup_dir) moves
+//! every file it deletes there, and links every file it overwrites there
+//! first, both at their path relative to the destination. Each sync gets a
+//! directory of its own, a run named for the second it started, under a
+//! trash root: `.jan-trash` in the destination unless another is given.
+//! [`Trash`] lists those runs, puts files back from them and purges old ones.
+
+use crate::io::{
+    format_utc, generate_temp_path, move_file, parse_utc, JAN_TEMP_DIR, JAN_TRASH_DIR,
+};
+use anyhow::{Context, Result};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// The files one sync displaced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashRun {
+    /// Directory name, from the time the sync started
+    pub name: String,
+    pub started: SystemTime,
+    pub files: usize,
+    /// Total bytes of the files
+    pub size: u64,
+}
+
+/// How much a trash keeps; runs beyond either limit are purged oldest first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// Runs started longer ago than this go
+    pub max_age: Option<Duration>,
+    /// Then the oldest runs go until the rest fit in this many bytes
+    pub max_size: Option<u64>,
+}
+
+/// A trash root and the runs in it
+#[derive(Debug, Clone)]
+pub struct Trash {
+    root: PathBuf,
+}
+
+impl Trash {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// The default trash of `dest_root`
+    pub fn of(dest_root: &Path) -> Self {
+        Self::new(dest_root.join(JAN_TRASH_DIR))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Name for the run of a sync starting now, not yet taken in this trash
+    pub fn new_run(&self) -> String {
+        let base = run_name(SystemTime::now());
+        let mut name = base.clone();
+        let mut n = 1;
+        while fs::symlink_metadata(self.root.join(&name)).is_ok() {
+            n += 1;
+            name = format!("{base}-{n}");
+        }
+        name
+    }
+
+    /// Every run in the trash, oldest first
+    ///
+    /// Directories that aren't named like a run are not jan's and left out.
+    pub fn runs(&self) -> Result<Vec<TrashRun>> {
+        let entries = match fs::read_dir(&self.root) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            entries => entries.with_context(|| format!("Can't list {}", self.root.display()))?,
+        };
+        let mut runs = Vec::new();
+        for entry in entries {
+            let entry = entry.with_context(|| format!("Can't list {}", self.root.display()))?;
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            let Some((started, seq)) = parse_run_name(&name) else {
+                continue;
+            };
+            if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+                continue;
+            }
+            let (files, size) = tally(&entry.path())
+                .with_context(|| format!("Can't read {}", entry.path().display()))?;
+            runs.push((seq, TrashRun { name, started, files, size }));
+        }
+        runs.sort_by_key(|(seq, run)| (run.started, *seq));
+        Ok(runs.into_iter().map(|(_, run)| run).collect())
+    }
+
+    /// The run called `name`
+    pub fn run(&self, name: &str) -> Result<TrashRun> {
+        self.runs()?
+            .into_iter()
+            .find(|run| run.name == name)
+            .with_context(|| format!("No run {name} in {}", self.root.display()))
+    }
+
+    /// Files kept by the run called `name`, relative to the destination
+    pub fn files(&self, name: &str) -> Result<Vec<PathBuf>> {
+        let run = self.run(name)?;
+        let dir = self.root.join(&run.name);
+        let mut files = Vec::new();
+        collect_files(&dir, Path::new(""), &mut files)
+            .with_context(|| format!("Can't read {}", dir.display()))?;
+        files.sort();
+        Ok(files)
+    }
+
+    /// Move files from the run called `name` back into `dest_root`
+    ///
+    /// Only those at or under `paths` go back, or all of them if none are
+    /// given. Whatever is at their place now is replaced. Directories left
+    /// empty in the run are removed, and so is the run once it is.
+    pub fn restore(&self, name: &str, dest_root: &Path, paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+        let dir = self.root.join(name);
+        let files: Vec<PathBuf> = self
+            .files(name)?
+            .into_iter()
+            .filter(|file| paths.is_empty() || paths.iter().any(|p| file.starts_with(p)))
+            .collect();
+        if files.is_empty() && !paths.is_empty() {
+            anyhow::bail!("Nothing under the given paths in run {name}");
+        }
+
+        let temp_dir = dest_root.join(JAN_TEMP_DIR);
+        fs::create_dir_all(&temp_dir)
+            .with_context(|| format!("Can't create {}", temp_dir.display()))?;
+        let result = files.iter().try_for_each(|file| {
+            let (kept, target) = (dir.join(file), dest_root.join(file));
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Can't create {}", parent.display()))?;
+            }
+            move_file(&kept, &target, &generate_temp_path(&temp_dir), false, None)
+                .with_context(|| format!("Can't restore {}", target.display()))
+        });
+        let _ = fs::remove_dir(&temp_dir);
+        result?;
+
+        remove_empty_dirs(&dir);
+        Ok(files)
+    }
+
+    /// Remove the runs beyond `retention`, returning them
+    pub fn purge(&self, retention: &Retention) -> Result<Vec<TrashRun>> {
+        let runs = self.runs()?;
+        let mut total: u64 = runs.iter().map(|run| run.size).sum();
+        let now = SystemTime::now();
+        let mut purged = Vec::new();
+        for run in runs {
+            let age = now.duration_since(run.started).unwrap_or_default();
+            let too_old = retention.max_age.is_some_and(|max| age > max);
+            let too_big = retention.max_size.is_some_and(|max| total > max);
+            if too_old || too_big {
+                self.remove(&run)?;
+                total -= run.size;
+                purged.push(run);
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Remove every run, returning them
+    pub fn purge_all(&self) -> Result<Vec<TrashRun>> {
+        let runs = self.runs()?;
+        runs.iter().try_for_each(|run| self.remove(run))?;
+        Ok(runs)
+    }
+
+    fn remove(&self, run: &TrashRun) -> Result<()> {
+        let dir = self.root.join(&run.name);
+        fs::remove_dir_all(&dir).with_context(|| format!("Can't remove {}", dir.display()))
+    }
+}
+
+/// Name of the run of a sync started at `time`: `YYYY-MM-DDTHH-MM-SS` in
+/// UTC, with nothing a filesystem may refuse
+pub fn run_name(time: SystemTime) -> String {
+    let utc = format_utc(time);
+    utc.trim_end_matches(" UTC").replacen(' ', "T", 1).replace(':', "-")
+}
+
+/// When a run started, and where it comes among runs started that second
+fn parse_run_name(name: &str) -> Option<(SystemTime, u32)> {
+    let (stamp, rest) = (name.get(..19)?, &name[19..]);
+    let seq = match rest {
+        "" => 1,
+        rest => rest.strip_prefix('-')?.parse().ok()?,
+    };
+    let (date, time) = stamp.split_once('T')?;
+    let started = parse_utc(&format!("{date} {} UTC", time.replace('-', ":")))?;
+    Some((started, seq))
+}
+
+/// File count and total size of everything under `dir`
+fn tally(dir: &Path) -> io::Result<(usize, u64)> {
+    let mut total = (0, 0);
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            let (files, size) = tally(&entry.path())?;
+            total = (total.0 + files, total.1 + size);
+        } else {
+            total = (total.0 + 1, total.1 + metadata.len());
+        }
+    }
+    Ok(total)
+}
+
+fn collect_files(dir: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            collect_files(&entry.path(), &path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Remove `dir` and the directories under it, if they hold no files
+fn remove_empty_dirs(dir: &Path) {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                remove_empty_dirs(&entry.path());
+            }
+        }
+    }
+    let _ = fs::remove_dir(dir);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+    use tempfile::tempdir;
+
+    const DAY: Duration = Duration::from_secs(86_400);
+
+    fn make_run(trash: &Trash, started: SystemTime, files: &[(&str, &[u8])]) -> String {
+        let name = run_name(started);
+        for (path, content) in files {
+            let path = trash.root().join(&name).join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        name
+    }
+
+    #[test]
+    fn test_run_names_sort_by_start() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(run_name(time), "2024-02-29T12-34-56");
+        assert_eq!(parse_run_name("2024-02-29T12-34-56"), Some((time, 1)));
+        assert_eq!(parse_run_name("2024-02-29T12-34-56-12"), Some((time, 12)));
+        assert_eq!(parse_run_name("2024-02-29T12-34-56.bak"), None);
+        assert_eq!(parse_run_name("notes"), None);
+
+        let dir = tempdir().unwrap();
+        let trash = Trash::of(dir.path());
+        let first = trash.new_run();
+        fs::create_dir_all(trash.root().join(&first)).unwrap();
+        let second = trash.new_run();
+        assert_ne!(first, second);
+        fs::create_dir_all(trash.root().join(&second)).unwrap();
+        fs::create_dir_all(trash.root().join("not a run")).unwrap();
+        let names: Vec<_> = trash.runs().unwrap().into_iter().map(|run| run.name).collect();
+        assert_eq!(names, [first, second]);
+    }
+
+    #[test]
+    fn test_restore_puts_files_back() {
+        let dir = tempdir().unwrap();
+        let trash = Trash::of(dir.path());
+        let name = make_run(&trash, SystemTime::now(), &[("a.txt", b"a"), ("sub/b.txt", b"b")]);
+        fs::write(dir.path().join("a.txt"), b"newer").unwrap();
+
+        let run = trash.run(&name).unwrap();
+        assert_eq!((run.files, run.size), (2, 2));
+        assert_eq!(trash.files(&name).unwrap(), [Path::new("a.txt"), Path::new("sub/b.txt")]);
+
+        let restored = trash.restore(&name, dir.path(), &[PathBuf::from("sub")]).unwrap();
+        assert_eq!(restored, [Path::new("sub/b.txt")]);
+        assert_eq!(fs::read(dir.path().join("sub/b.txt")).unwrap(), b"b");
+        assert_eq!(fs::read(dir.path().join("a.txt")).unwrap(), b"newer");
+        assert!(trash.restore(&name, dir.path(), &[PathBuf::from("sub")]).is_err());
+
+        trash.restore(&name, dir.path(), &[]).unwrap();
+        assert_eq!(fs::read(dir.path().join("a.txt")).unwrap(), b"a");
+        assert!(trash.runs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_purge_drops_old_runs_then_oldest_over_size() {
+        let dir = tempdir().unwrap();
+        let trash = Trash::of(dir.path());
+        let now = SystemTime::now();
+        let ancient = make_run(&trash, now - 30 * DAY, &[("a", b"1")]);
+        let old = make_run(&trash, now - 3 * DAY, &[("b", b"22")]);
+        let recent = make_run(&trash, now - DAY, &[("c", b"333")]);
+        let today = make_run(&trash, now, &[("d", b"4444")]);
+
+        let keep_week = Retention { max_age: Some(7 * DAY), max_size: None };
+        let purged = trash.purge(&keep_week).unwrap();
+        assert_eq!(purged.iter().map(|run| &run.name).collect::<Vec<_>>(), [&ancient]);
+
+        let fit = Retention { max_age: None, max_size: Some(7) };
+        let purged = trash.purge(&fit).unwrap();
+        assert_eq!(purged.iter().map(|run| &run.name).collect::<Vec<_>>(), [&old]);
+        let left: Vec<_> = trash.runs().unwrap().into_iter().map(|run| run.name).collect();
+        assert_eq!(left, [recent, today]);
+
+        assert_eq!(trash.purge_all().unwrap().len(), 2);
+        assert!(trash.runs().unwrap().is_empty());
+    }
+}
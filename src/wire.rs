@@ -0,0 +1,1119 @@
+//! Versioned binary encoding of scans, diffs and file data for remote peers
+//!
+//! Messages travel as frames: a little-endian `u32` length, then the payload.
+//! Messages that may grow without bound, like scans and diffs, are split
+//! over several frames by [`write_chunked`]. Inside a payload integers are
+//! little-endian, byte strings and lists carry a `u32` length and options a
+//! one-byte tag. Paths go as raw bytes on Unix, so names that aren't UTF-8
+//! survive the trip.
+//!
+//! Every connection opens with a [`Hello`] from each side. Its layout never
+//! changes between versions: it names the protocol versions a peer speaks,
+//! the hash algorithm it was built with and its optional capabilities, so two
+//! builds that can't understand each other fail up front with a
+//! [`WireError`] instead of comparing incompatible hashes.
+
+use crate::baseline::Baseline;
+use crate::core::{
+    DiffResult, DirMeta, DirRename, FileConflict, FileMeta, LocalCopyMode, Resolution, ScanOptions,
+    ScanResult, SymlinkMode, SyncOptions,
+};
+use crate::delta::{BlockSignature, DeltaOp, Signature};
+use crate::filter::Filter;
+use crate::hash::ContentHash;
+use crate::plan::{Step, SyncPlan};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// Largest frame accepted; guards against reading a garbage length
+pub const MAX_FRAME_SIZE: usize = 256 * 1024 * 1024;
+
+/// Most payload [`write_chunked`] puts in one frame
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Newest protocol version this build speaks
+pub const PROTOCOL_VERSION: u32 = 7;
+
+/// Oldest protocol version this build still speaks
+pub const MIN_PROTOCOL_VERSION: u32 = 7;
+
+/// Capability: the peer can rebuild files from delta ops against its copy
+pub const CAP_DELTA: &str = "delta";
+
+// First bytes of every Hello, so a peer that isn't jan is told apart
+const MAGIC: [u8; 4] = *b"JANW";
+
+/// Errors encoding, decoding or negotiating the wire protocol
+#[derive(Error, Debug)]
+pub enum WireError {
+    #[error("Message ends early")]
+    Truncated,
+
+    #[error("{0} unexpected trailing bytes in message")]
+    TrailingBytes(usize),
+
+    #[error("Unknown {what} tag {tag}")]
+    InvalidTag { what: &'static str, tag: u8 },
+
+    #[error("Invalid {what}: {reason}")]
+    InvalidValue { what: &'static str, reason: String },
+
+    #[error("Frame of {0} bytes is too large")]
+    FrameTooLarge(usize),
+
+    #[error("Hash algorithm {0} is not supported by this build")]
+    UnsupportedHash(String),
+
+    #[error("Peer is not speaking the jan protocol")]
+    BadMagic,
+
+    #[error(
+        "No common protocol version: this build speaks {ours_min}-{ours_max}, \
+         the peer {theirs_min}-{theirs_max}"
+    )]
+    VersionMismatch {
+        ours_min: u32,
+        ours_max: u32,
+        theirs_min: u32,
+        theirs_max: u32,
+    },
+
+    #[error("Hash algorithms differ: this build uses {ours}, the peer {theirs}")]
+    HashMismatch { ours: String, theirs: String },
+
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl WireError {
+    /// Whether the peer simply hung up
+    pub fn is_eof(&self) -> bool {
+        matches!(self, WireError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof)
+    }
+}
+
+/// Write one length-prefixed frame
+pub fn write_frame(writer: &mut impl Write, payload: &[u8]) -> Result<(), WireError> {
+    let len = u32::try_from(payload.len())
+        .ok()
+        .filter(|&len| len as usize <= MAX_FRAME_SIZE)
+        .ok_or(WireError::FrameTooLarge(payload.len()))?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(payload)?;
+    Ok(())
+}
+
+/// Read one length-prefixed frame
+pub fn read_frame(reader: &mut impl Read) -> Result<Vec<u8>, WireError> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(WireError::FrameTooLarge(len));
+    }
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+/// Write a payload of any size as frames of [`CHUNK_SIZE`] bytes, ended by
+/// a shorter one
+///
+/// A payload below [`CHUNK_SIZE`] goes out as the same single frame
+/// [`write_frame`] would write.
+pub fn write_chunked(writer: &mut impl Write, payload: &[u8]) -> Result<(), WireError> {
+    for chunk in payload.chunks(CHUNK_SIZE) {
+        write_frame(writer, chunk)?;
+    }
+    if payload.len() % CHUNK_SIZE == 0 {
+        write_frame(writer, &[])?;
+    }
+    Ok(())
+}
+
+/// Read a payload written by [`write_chunked`]
+pub fn read_chunked(reader: &mut impl Read) -> Result<Vec<u8>, WireError> {
+    let mut payload = Vec::new();
+    loop {
+        let frame = read_frame(reader)?;
+        if frame.len() > CHUNK_SIZE {
+            return Err(WireError::FrameTooLarge(frame.len()));
+        }
+        payload.extend_from_slice(&frame);
+        if frame.len() < CHUNK_SIZE {
+            return Ok(payload);
+        }
+    }
+}
+
+/// Encode `value` into a fresh payload
+pub fn to_bytes<T: Wire>(value: &T) -> Vec<u8> {
+    let mut enc = Encoder::new();
+    value.encode(&mut enc);
+    enc.into_bytes()
+}
+
+/// Decode a whole payload as `T`, rejecting trailing bytes
+pub fn from_bytes<T: Wire>(payload: &[u8]) -> Result<T, WireError> {
+    let mut dec = Decoder::new(payload);
+    let value = T::decode(&mut dec)?;
+    dec.finish()?;
+    Ok(value)
+}
+
+fn invalid(what: &'static str, reason: impl ToString) -> WireError {
+    WireError::InvalidValue { what, reason: reason.to_string() }
+}
+
+/// Growing payload buffer
+#[derive(Debug, Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn i64(&mut self, value: i64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.buf.extend_from_slice(value);
+    }
+
+    pub fn put<T: Wire>(&mut self, value: &T) {
+        value.encode(self);
+    }
+}
+
+/// Cursor over a received payload
+#[derive(Debug)]
+pub struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], WireError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.buf.len())
+            .ok_or(WireError::Truncated)?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], WireError> {
+        let mut out = [0u8; N];
+        out.copy_from_slice(self.take(N)?);
+        Ok(out)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, WireError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, WireError> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, WireError> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    pub fn i64(&mut self) -> Result<i64, WireError> {
+        self.array().map(i64::from_le_bytes)
+    }
+
+    pub fn bool(&mut self) -> Result<bool, WireError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(WireError::InvalidTag { what: "boolean", tag }),
+        }
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], WireError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    pub fn get<T: Wire>(&mut self) -> Result<T, WireError> {
+        T::decode(self)
+    }
+
+    /// Fail if anything is left over
+    pub fn finish(self) -> Result<(), WireError> {
+        if self.pos == self.buf.len() {
+            Ok(())
+        } else {
+            Err(WireError::TrailingBytes(self.buf.len() - self.pos))
+        }
+    }
+}
+
+/// Opening message of each side of a connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hello {
+    pub min_version: u32,
+    pub max_version: u32,
+    /// Algorithm every [`ContentHash`] from this peer uses
+    pub hash_algorithm: String,
+    /// Optional features, such as [`CAP_DELTA`]
+    pub capabilities: Vec<String>,
+}
+
+/// What two peers agreed to use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Negotiated {
+    pub version: u32,
+    /// Capabilities both sides have
+    pub capabilities: Vec<String>,
+}
+
+impl Negotiated {
+    pub fn has(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+impl Hello {
+    /// The Hello this build sends
+    pub fn local() -> Self {
+        Self {
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            hash_algorithm: crate::hash::default_algorithm().to_string(),
+            capabilities: vec![CAP_DELTA.to_string()],
+        }
+    }
+
+    /// Agree on the newest version both speak and the capabilities both have
+    ///
+    /// Fails if the version ranges don't overlap or the hash algorithms differ.
+    pub fn negotiate(&self, peer: &Hello) -> Result<Negotiated, WireError> {
+        let version = self.max_version.min(peer.max_version);
+        if version < self.min_version.max(peer.min_version) {
+            return Err(WireError::VersionMismatch {
+                ours_min: self.min_version,
+                ours_max: self.max_version,
+                theirs_min: peer.min_version,
+                theirs_max: peer.max_version,
+            });
+        }
+        if self.hash_algorithm != peer.hash_algorithm {
+            return Err(WireError::HashMismatch {
+                ours: self.hash_algorithm.clone(),
+                theirs: peer.hash_algorithm.clone(),
+            });
+        }
+        let capabilities = self
+            .capabilities
+            .iter()
+            .filter(|c| peer.capabilities.contains(c))
+            .cloned()
+            .collect();
+        Ok(Negotiated { version, capabilities })
+    }
+}
+
+/// A type with a wire encoding
+pub trait Wire: Sized {
+    fn encode(&self, enc: &mut Encoder);
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError>;
+}
+
+impl Wire for Hello {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.buf.extend_from_slice(&MAGIC);
+        enc.u32(self.min_version);
+        enc.u32(self.max_version);
+        enc.put(&self.hash_algorithm);
+        enc.put(&self.capabilities);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        if dec.array::<4>().ok() != Some(MAGIC) {
+            return Err(WireError::BadMagic);
+        }
+        Ok(Self {
+            min_version: dec.u32()?,
+            max_version: dec.u32()?,
+            hash_algorithm: dec.get()?,
+            capabilities: dec.get()?,
+        })
+    }
+}
+
+impl Wire for u32 {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.u32(*self);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        dec.u32()
+    }
+}
+
+impl Wire for u64 {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.u64(*self);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        dec.u64()
+    }
+}
+
+impl Wire for usize {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.u64(*self as u64);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        let value = dec.u64()?;
+        usize::try_from(value).map_err(|_| invalid("size", format!("{value} is out of range")))
+    }
+}
+
+impl Wire for bool {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.bool(*self);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        dec.bool()
+    }
+}
+
+impl Wire for String {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.bytes(self.as_bytes());
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        String::from_utf8(dec.bytes()?.to_vec()).map_err(|e| invalid("string", e))
+    }
+}
+
+impl Wire for PathBuf {
+    fn encode(&self, enc: &mut Encoder) {
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            enc.bytes(self.as_os_str().as_bytes());
+        }
+        #[cfg(not(unix))]
+        enc.bytes(self.to_string_lossy().as_bytes());
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        let bytes = dec.bytes()?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            Ok(PathBuf::from(std::ffi::OsStr::from_bytes(bytes)))
+        }
+        #[cfg(not(unix))]
+        {
+            String::from_utf8(bytes.to_vec())
+                .map(PathBuf::from)
+                .map_err(|e| invalid("path", e))
+        }
+    }
+}
+
+/// Seconds and nanoseconds either side of the Unix epoch
+impl Wire for SystemTime {
+    fn encode(&self, enc: &mut Encoder) {
+        let (secs, nanos) = match self.duration_since(UNIX_EPOCH) {
+            Ok(after) => (after.as_secs() as i64, after.subsec_nanos()),
+            Err(e) => {
+                let before = e.duration();
+                match before.subsec_nanos() {
+                    0 => (-(before.as_secs() as i64), 0),
+                    nanos => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+                }
+            },
+        };
+        enc.i64(secs);
+        enc.u32(nanos);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        let secs = dec.i64()?;
+        let nanos = dec.u32()?;
+        if nanos >= 1_000_000_000 {
+            return Err(invalid("timestamp", format!("{nanos} nanoseconds")));
+        }
+        let time = if secs >= 0 {
+            UNIX_EPOCH.checked_add(Duration::new(secs as u64, nanos))
+        } else {
+            UNIX_EPOCH
+                .checked_sub(Duration::from_secs(secs.unsigned_abs()))
+                .and_then(|t| t.checked_add(Duration::from_nanos(nanos as u64)))
+        };
+        time.ok_or_else(|| invalid("timestamp", format!("{secs}s is out of range")))
+    }
+}
+
+impl<T: Wire> Wire for Option<T> {
+    fn encode(&self, enc: &mut Encoder) {
+        match self {
+            None => enc.u8(0),
+            Some(value) => {
+                enc.u8(1);
+                value.encode(enc);
+            },
+        }
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        match dec.u8()? {
+            0 => Ok(None),
+            1 => T::decode(dec).map(Some),
+            tag => Err(WireError::InvalidTag { what: "option", tag }),
+        }
+    }
+}
+
+impl<T: Wire> Wire for Vec<T> {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.u32(self.len() as u32);
+        for item in self {
+            item.encode(enc);
+        }
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        let len = dec.u32()? as usize;
+        // Every item takes at least a byte, so a bogus length can't over-allocate
+        let mut items = Vec::with_capacity(len.min(dec.buf.len() - dec.pos));
+        for _ in 0..len {
+            items.push(T::decode(dec)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<A: Wire, B: Wire> Wire for (A, B) {
+    fn encode(&self, enc: &mut Encoder) {
+        self.0.encode(enc);
+        self.1.encode(enc);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        Ok((A::decode(dec)?, B::decode(dec)?))
+    }
+}
+
+/// Algorithm name, then the 32 hash bytes
+impl Wire for ContentHash {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.bytes(self.algorithm().as_bytes());
+        enc.buf.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        let algorithm = String::from_utf8_lossy(dec.bytes()?).into_owned();
+        let bytes = dec.array::<32>()?;
+        ContentHash::from_bytes(&algorithm, bytes).ok_or(WireError::UnsupportedHash(algorithm))
+    }
+}
+
+impl Wire for FileMeta {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.put(&self.path);
+        enc.u64(self.size);
+        enc.put(&self.mtime);
+        enc.put(&self.hash);
+        enc.put(&self.permissions);
+        enc.put(&self.symlink_target);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        Ok(Self {
+            path: dec.get()?,
+            size: dec.u64()?,
+            mtime: dec.get()?,
+            hash: dec.get()?,
+            permissions: dec.get()?,
+            symlink_target: dec.get()?,
+        })
+    }
+}
+
+impl Wire for DirMeta {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.put(&self.path);
+        enc.put(&self.mtime);
+        enc.put(&self.permissions);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        Ok(Self {
+            path: dec.get()?,
+            mtime: dec.get()?,
+            permissions: dec.get()?,
+        })
+    }
+}
+
+impl Wire for ScanResult {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.put(&self.root);
+        enc.put(&self.files);
+        enc.put(&self.dirs);
+        enc.put(&self.excluded);
+        enc.put(&self.scan_time);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        Ok(Self {
+            root: dec.get()?,
+            files: dec.get()?,
+            dirs: dec.get()?,
+            excluded: dec.get()?,
+            scan_time: dec.get()?,
+        })
+    }
+}
+
+impl Wire for DirRename {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.put(&self.old);
+        enc.put(&self.new);
+        enc.put(&self.files);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        Ok(Self {
+            old: dec.get()?,
+            new: dec.get()?,
+            files: dec.get()?,
+        })
+    }
+}
+
+impl Wire for DiffResult {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.put(&self.added);
+        enc.put(&self.removed);
+        enc.put(&self.modified);
+        enc.put(&self.renamed);
+        enc.put(&self.copied);
+        enc.put(&self.renamed_dirs);
+        enc.put(&self.added_dirs);
+        enc.put(&self.removed_dirs);
+        enc.put(&self.modified_dirs);
+        enc.put(&self.protected);
+        enc.put(&self.conflicts);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        Ok(Self {
+            added: dec.get()?,
+            removed: dec.get()?,
+            modified: dec.get()?,
+            renamed: dec.get()?,
+            copied: dec.get()?,
+            renamed_dirs: dec.get()?,
+            added_dirs: dec.get()?,
+            removed_dirs: dec.get()?,
+            modified_dirs: dec.get()?,
+            protected: dec.get()?,
+            conflicts: dec.get()?,
+        })
+    }
+}
+
+impl Wire for FileConflict {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.put(&self.source);
+        enc.put(&self.dest);
+        enc.put(&self.resolution);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        Ok(Self {
+            source: dec.get()?,
+            dest: dec.get()?,
+            resolution: dec.get()?,
+        })
+    }
+}
+
+impl Wire for Resolution {
+    fn encode(&self, enc: &mut Encoder) {
+        match self {
+            Resolution::Source => enc.u8(0),
+            Resolution::Dest => enc.u8(1),
+            Resolution::KeepBoth(aside) => {
+                enc.u8(2);
+                enc.put(aside);
+            },
+            Resolution::Unresolved => enc.u8(3),
+        }
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        match dec.u8()? {
+            0 => Ok(Resolution::Source),
+            1 => Ok(Resolution::Dest),
+            2 => Ok(Resolution::KeepBoth(dec.get()?)),
+            3 => Ok(Resolution::Unresolved),
+            tag => Err(WireError::InvalidTag { what: "conflict resolution", tag }),
+        }
+    }
+}
+
+/// Rules as filter-file lines with their sources
+impl Wire for Filter {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.put(&self.to_lines());
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        let mut filter = Filter::new();
+        for (line, source) in dec.get::<Vec<(String, String)>>()? {
+            filter.add_line(&line, &source).map_err(|e| invalid("filter rule", e))?;
+        }
+        Ok(filter)
+    }
+}
+
+impl Wire for SymlinkMode {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.u8(match self {
+            SymlinkMode::Preserve => 0,
+            SymlinkMode::Follow => 1,
+            SymlinkMode::Safe => 2,
+        });
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        match dec.u8()? {
+            0 => Ok(SymlinkMode::Preserve),
+            1 => Ok(SymlinkMode::Follow),
+            2 => Ok(SymlinkMode::Safe),
+            tag => Err(WireError::InvalidTag { what: "symlink mode", tag }),
+        }
+    }
+}
+
+impl Wire for LocalCopyMode {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.u8(match self {
+            LocalCopyMode::Copy => 0,
+            LocalCopyMode::Hardlink => 1,
+            LocalCopyMode::Reflink => 2,
+        });
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        match dec.u8()? {
+            0 => Ok(LocalCopyMode::Copy),
+            1 => Ok(LocalCopyMode::Hardlink),
+            2 => Ok(LocalCopyMode::Reflink),
+            tag => Err(WireError::InvalidTag { what: "local copy mode", tag }),
+        }
+    }
+}
+
+impl Wire for ScanOptions {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.put(&self.filter);
+        enc.put(&self.exclude_patterns);
+        enc.bool(self.respect_gitignore);
+        enc.put(&self.ignore_files);
+        enc.bool(self.use_index);
+        enc.bool(self.save_index);
+        enc.bool(self.rehash);
+        enc.put(&self.symlinks);
+        enc.bool(self.quick);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        Ok(Self {
+            filter: dec.get()?,
+            exclude_patterns: dec.get()?,
+            respect_gitignore: dec.bool()?,
+            ignore_files: dec.get()?,
+            use_index: dec.bool()?,
+            save_index: dec.bool()?,
+            rehash: dec.bool()?,
+            symlinks: dec.get()?,
+            quick: dec.bool()?,
+        })
+    }
+}
+
+impl Wire for SyncOptions {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.bool(self.delete_removed);
+        enc.bool(self.preserve_timestamps);
+        enc.bool(self.verify_after_copy);
+        enc.put(&self.local_copy);
+        enc.put(&self.filter);
+        enc.bool(self.delta);
+        enc.bool(self.chunks);
+        enc.bool(self.transactional);
+        enc.put(&self.backup_dir);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        Ok(Self {
+            delete_removed: dec.bool()?,
+            preserve_timestamps: dec.bool()?,
+            verify_after_copy: dec.bool()?,
+            local_copy: dec.get()?,
+            filter: dec.get()?,
+            delta: dec.bool()?,
+            chunks: dec.bool()?,
+            transactional: dec.bool()?,
+            backup_dir: dec.get()?,
+        })
+    }
+}
+
+impl Wire for Step {
+    fn encode(&self, enc: &mut Encoder) {
+        let (tag, index) = match self {
+            Step::DirMove(i) => (0, i),
+            Step::LocalCopy(i) => (1, i),
+            Step::Copy(i) => (2, i),
+            Step::Rename(i) => (3, i),
+        };
+        enc.u8(tag);
+        enc.put(index);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        match dec.u8()? {
+            0 => Ok(Step::DirMove(dec.get()?)),
+            1 => Ok(Step::LocalCopy(dec.get()?)),
+            2 => Ok(Step::Copy(dec.get()?)),
+            3 => Ok(Step::Rename(dec.get()?)),
+            tag => Err(WireError::InvalidTag { what: "step", tag }),
+        }
+    }
+}
+
+impl Wire for SyncPlan {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.put(&self.source_root);
+        enc.put(&self.diff);
+        enc.put(&self.options);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        Ok(Self {
+            source_root: dec.get()?,
+            diff: dec.get()?,
+            options: dec.get()?,
+        })
+    }
+}
+
+impl Wire for Baseline {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.put(&self.roots);
+        enc.put(&self.files);
+        enc.put(&self.dirs);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        Ok(Self {
+            roots: dec.get()?,
+            files: dec.get()?,
+            dirs: dec.get()?,
+        })
+    }
+}
+
+impl Wire for Signature {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.put(&self.block_size);
+        enc.u64(self.file_len);
+        enc.put(&self.blocks);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        Ok(Self {
+            block_size: dec.get()?,
+            file_len: dec.u64()?,
+            blocks: dec.get()?,
+        })
+    }
+}
+
+impl Wire for BlockSignature {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.u32(self.weak);
+        enc.put(&self.strong);
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        Ok(Self { weak: dec.u32()?, strong: dec.get()? })
+    }
+}
+
+impl Wire for DeltaOp {
+    fn encode(&self, enc: &mut Encoder) {
+        match self {
+            DeltaOp::Block(index) => {
+                enc.u8(0);
+                enc.u32(*index);
+            },
+            DeltaOp::Literal(bytes) => {
+                enc.u8(1);
+                enc.bytes(bytes);
+            },
+        }
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Self, WireError> {
+        match dec.u8()? {
+            0 => Ok(DeltaOp::Block(dec.u32()?)),
+            1 => Ok(DeltaOp::Literal(dec.bytes()?.to_vec())),
+            tag => Err(WireError::InvalidTag { what: "delta op", tag }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::hash_bytes;
+
+    fn file(path: &str, content: &[u8]) -> FileMeta {
+        FileMeta {
+            path: PathBuf::from(path),
+            size: content.len() as u64,
+            mtime: UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789),
+            hash: Some(hash_bytes(content)),
+            permissions: Some(0o644),
+            symlink_target: None,
+        }
+    }
+
+    fn round_trip<T: Wire>(value: &T) -> T {
+        from_bytes(&to_bytes(value)).unwrap()
+    }
+
+    #[test]
+    fn test_file_meta_round_trip() {
+        let mut link = file("dir/link", b"");
+        link.hash = None;
+        link.permissions = None;
+        link.symlink_target = Some(PathBuf::from("../target"));
+
+        for meta in [file("dir/a.txt", b"alpha"), link] {
+            assert_eq!(round_trip(&meta), meta);
+        }
+    }
+
+    #[test]
+    fn test_scan_and_diff_round_trip() {
+        let dir = DirMeta {
+            path: PathBuf::from("dir"),
+            mtime: UNIX_EPOCH + Duration::from_secs(5),
+            permissions: Some(0o755),
+        };
+        let scan = ScanResult {
+            root: PathBuf::from("/srv/data"),
+            files: vec![file("dir/a.txt", b"alpha"), file("b.txt", b"beta")],
+            dirs: vec![dir.clone()],
+            excluded: vec![(".gitignore".to_string(), 3)],
+            scan_time: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+        };
+        let decoded = round_trip(&scan);
+        assert_eq!(decoded.root, scan.root);
+        assert_eq!(decoded.files, scan.files);
+        assert_eq!(decoded.dirs, scan.dirs);
+        assert_eq!(decoded.excluded, scan.excluded);
+        assert_eq!(decoded.scan_time, scan.scan_time);
+
+        let moved = (file("old/c.txt", b"gamma"), file("new/c.txt", b"gamma"));
+        let diff = DiffResult {
+            added: vec![file("added.txt", b"new")],
+            removed: vec![file("removed.txt", b"old")],
+            modified: vec![file("b.txt", b"beta 2")],
+            renamed: vec![(file("x", b"same"), file("y", b"same"))],
+            copied: vec![(file("p", b"dup"), file("q", b"dup"))],
+            renamed_dirs: vec![DirRename {
+                old: PathBuf::from("old"),
+                new: PathBuf::from("new"),
+                files: vec![moved.clone()],
+            }],
+            added_dirs: vec![dir.clone()],
+            removed_dirs: vec![],
+            modified_dirs: vec![dir],
+            protected: vec![file("keep.txt", b"kept")],
+            conflicts: vec![FileConflict {
+                source: file("both.txt", b"source"),
+                dest: file("both.txt", b"dest"),
+                resolution: Resolution::KeepBoth(PathBuf::from(
+                    "both.txt.conflict-host-2026-01-02",
+                )),
+            }],
+        };
+        let decoded = round_trip(&diff);
+        assert_eq!(decoded.added, diff.added);
+        assert_eq!(decoded.removed, diff.removed);
+        assert_eq!(decoded.modified, diff.modified);
+        assert_eq!(decoded.renamed, diff.renamed);
+        assert_eq!(decoded.copied, diff.copied);
+        assert_eq!(decoded.renamed_dirs.len(), 1);
+        assert_eq!(decoded.renamed_dirs[0].old, PathBuf::from("old"));
+        assert_eq!(decoded.renamed_dirs[0].files, vec![moved]);
+        assert_eq!(decoded.added_dirs, diff.added_dirs);
+        assert!(decoded.removed_dirs.is_empty());
+        assert_eq!(decoded.modified_dirs, diff.modified_dirs);
+        assert_eq!(decoded.protected, diff.protected);
+        assert_eq!(decoded.conflicts, diff.conflicts);
+    }
+
+    #[test]
+    fn test_time_before_epoch_round_trip() {
+        for time in [
+            UNIX_EPOCH - Duration::new(86_400, 250_000_000),
+            UNIX_EPOCH - Duration::from_secs(1),
+            UNIX_EPOCH,
+        ] {
+            assert_eq!(round_trip(&time), time);
+        }
+    }
+
+    #[test]
+    fn test_malformed_payloads_are_rejected() {
+        let bytes = to_bytes(&file("a.txt", b"alpha"));
+        let err = from_bytes::<FileMeta>(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert!(matches!(err, WireError::Truncated), "{err}");
+
+        let mut longer = bytes.clone();
+        longer.extend_from_slice(&[0, 0]);
+        let err = from_bytes::<FileMeta>(&longer).unwrap_err();
+        assert!(matches!(err, WireError::TrailingBytes(2)), "{err}");
+
+        let err = from_bytes::<Option<u32>>(&[7]).unwrap_err();
+        assert!(matches!(err, WireError::InvalidTag { what: "option", tag: 7 }), "{err}");
+
+        let mut enc = Encoder::new();
+        enc.bytes(b"md5");
+        enc.buf.extend_from_slice(&[0; 32]);
+        let err = from_bytes::<ContentHash>(&enc.into_bytes()).unwrap_err();
+        assert!(matches!(err, WireError::UnsupportedHash(ref name) if name == "md5"), "{err}");
+    }
+
+    #[test]
+    fn test_frames_round_trip_and_reject_oversize() {
+        let mut stream = Vec::new();
+        write_frame(&mut stream, b"one").unwrap();
+        write_frame(&mut stream, b"").unwrap();
+        let mut reader = stream.as_slice();
+        assert_eq!(read_frame(&mut reader).unwrap(), b"one");
+        assert_eq!(read_frame(&mut reader).unwrap(), b"");
+        assert!(read_frame(&mut reader).unwrap_err().is_eof());
+
+        let huge = (MAX_FRAME_SIZE as u32 + 1).to_le_bytes();
+        let err = read_frame(&mut huge.as_slice()).unwrap_err();
+        assert!(matches!(err, WireError::FrameTooLarge(_)), "{err}");
+    }
+
+    #[test]
+    fn test_chunked_payloads_round_trip() {
+        let mut stream = Vec::new();
+        for len in [0, 5, CHUNK_SIZE, 2 * CHUNK_SIZE + 7] {
+            let payload: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            write_chunked(&mut stream, &payload).unwrap();
+        }
+        let mut reader = stream.as_slice();
+        for len in [0, 5, CHUNK_SIZE, 2 * CHUNK_SIZE + 7] {
+            let payload = read_chunked(&mut reader).unwrap();
+            assert_eq!(payload.len(), len);
+            assert!(payload.iter().enumerate().all(|(i, &b)| b == i as u8));
+        }
+        assert!(reader.is_empty());
+
+        // Short payloads are plain frames
+        let mut frame = Vec::new();
+        write_frame(&mut frame, b"one").unwrap();
+        assert_eq!(read_chunked(&mut frame.as_slice()).unwrap(), b"one");
+    }
+
+    #[test]
+    fn test_negotiate_picks_common_version_and_capabilities() {
+        let ours = Hello::local();
+        let peer = Hello {
+            min_version: 0,
+            max_version: PROTOCOL_VERSION + 3,
+            capabilities: vec!["future".to_string(), CAP_DELTA.to_string()],
+            ..Hello::local()
+        };
+        let agreed = ours.negotiate(&peer).unwrap();
+        assert_eq!(agreed.version, PROTOCOL_VERSION);
+        assert!(agreed.has(CAP_DELTA));
+        assert!(!agreed.has("future"));
+
+        let plain = Hello { capabilities: vec![], ..Hello::local() };
+        assert!(!ours.negotiate(&plain).unwrap().has(CAP_DELTA));
+        assert_eq!(round_trip(&peer), peer);
+    }
+
+    #[test]
+    fn test_negotiate_rejects_incompatible_peers() {
+        let ours = Hello::local();
+
+        let newer = Hello {
+            min_version: PROTOCOL_VERSION + 1,
+            max_version: PROTOCOL_VERSION + 2,
+            ..Hello::local()
+        };
+        let err = ours.negotiate(&newer).unwrap_err();
+        assert!(matches!(err, WireError::VersionMismatch { .. }), "{err}");
+        assert!(err.to_string().contains("No common protocol version"));
+
+        let other_hash = Hello {
+            hash_algorithm: "md5".to_string(),
+            ..Hello::local()
+        };
+        let err = ours.negotiate(&other_hash).unwrap_err();
+        assert!(matches!(err, WireError::HashMismatch { .. }), "{err}");
+
+        let err = from_bytes::<Hello>(b"SSH-2.0-OpenSSH").unwrap_err();
+        assert!(matches!(err, WireError::BadMagic), "{err}");
+    }
+}
@@ -0,0 +1,112 @@
+//! Unit tests for syncing through a `jan --server` peer
+
+use janice::core::{diff_scans, scan_directory, ScanOptions, SyncOptions};
+use janice::io::JAN_INCOMING_DIR;
+use janice::RemoteSession;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempfile::tempdir;
+
+/// Talk to a local server instead of one reached over ssh
+fn local_server(root: &Path) -> RemoteSession {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_jan"));
+    command.arg("--server").arg(root);
+    RemoteSession::spawn(command).unwrap()
+}
+
+fn sync_remote(src: &Path, dst: &Path, options: &SyncOptions) {
+    let mut session = local_server(dst);
+    let source_scan = scan_directory(src).unwrap();
+    let dest_scan = session.scan(&ScanOptions::default()).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    session.sync(src, &source_scan, &diff, options).unwrap();
+    session.close().unwrap();
+}
+
+#[test]
+fn test_remote_scan_matches_local_scan() {
+    let dst = tempdir().unwrap();
+    fs::create_dir(dst.path().join("sub")).unwrap();
+    fs::write(dst.path().join("sub/a.txt"), b"alpha").unwrap();
+
+    let mut session = local_server(dst.path());
+    let remote = session.scan(&ScanOptions::default()).unwrap();
+    session.close().unwrap();
+
+    let local = scan_directory(dst.path()).unwrap();
+    assert_eq!(remote.files, local.files);
+    assert_eq!(remote.dirs, local.dirs);
+}
+
+#[test]
+fn test_remote_sync_copies_renames_and_deletes() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::create_dir(src.path().join("docs")).unwrap();
+    fs::write(src.path().join("docs/new.txt"), b"brand new").unwrap();
+    fs::write(src.path().join("moved.txt"), b"same bytes").unwrap();
+    fs::write(src.path().join("changed.txt"), b"after").unwrap();
+    fs::write(dst.path().join("original.txt"), b"same bytes").unwrap();
+    fs::write(dst.path().join("changed.txt"), b"before").unwrap();
+    fs::write(dst.path().join("stale.txt"), b"gone soon").unwrap();
+
+    let options = SyncOptions {
+        delete_removed: true,
+        verify_after_copy: true,
+        ..SyncOptions::default()
+    };
+    sync_remote(src.path(), dst.path(), &options);
+
+    assert_eq!(fs::read(dst.path().join("docs/new.txt")).unwrap(), b"brand new");
+    assert_eq!(fs::read(dst.path().join("moved.txt")).unwrap(), b"same bytes");
+    assert_eq!(fs::read(dst.path().join("changed.txt")).unwrap(), b"after");
+    assert!(!dst.path().join("original.txt").exists());
+    assert!(!dst.path().join("stale.txt").exists());
+    assert!(!dst.path().join(JAN_INCOMING_DIR).exists());
+    assert_eq!(
+        fs::metadata(src.path().join("changed.txt")).unwrap().modified().unwrap(),
+        fs::metadata(dst.path().join("changed.txt")).unwrap().modified().unwrap(),
+    );
+}
+
+#[test]
+fn test_remote_delta_sends_modified_file() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    let old: Vec<u8> = (0..100_000u32).map(|i| (i * 31 % 253) as u8).collect();
+    let mut new = old.clone();
+    new.extend_from_slice(b"tail");
+    fs::write(dst.path().join("big.bin"), &old).unwrap();
+    fs::write(src.path().join("big.bin"), &new).unwrap();
+
+    let options = SyncOptions { delta: true, ..SyncOptions::default() };
+    sync_remote(src.path(), dst.path(), &options);
+
+    assert_eq!(fs::read(dst.path().join("big.bin")).unwrap(), new);
+}
+
+#[test]
+fn test_remote_errors_are_reported() {
+    let dst = tempdir().unwrap();
+    let missing = dst.path().join("nowhere");
+
+    let mut session = local_server(&missing);
+    let err = session.scan(&ScanOptions::default()).unwrap_err();
+    assert!(err.to_string().starts_with("Remote:"), "{err:#}");
+}
+
+#[test]
+fn test_remote_lock_keeps_second_session_out() {
+    let dst = tempdir().unwrap();
+    let mut first = local_server(dst.path());
+    first.lock(false).unwrap();
+
+    let mut second = local_server(dst.path());
+    let err = second.lock(false).unwrap_err();
+    assert!(format!("{err:#}").contains("is locked by process"), "{err:#}");
+
+    first.close().unwrap();
+    second.lock(false).unwrap();
+    second.close().unwrap();
+}
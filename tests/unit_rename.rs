@@ -1,6 +1,6 @@
 //! Unit tests for rename detection heuristics
 
-use janice::core::{diff_scans, FileMeta, ScanResult};
+use janice::core::{diff_scans, DirMeta, FileMeta, ScanResult};
 use janice::hash::hash_bytes;
 use std::path::PathBuf;
 use std::time::SystemTime;
@@ -11,8 +11,9 @@ fn make_file_meta(path: &str, content: &[u8]) -> FileMeta {
         path: PathBuf::from(path),
         size: content.len() as u64,
         mtime: SystemTime::now(),
-        hash: hash_bytes(content),
+        hash: Some(hash_bytes(content)),
         permissions: None,
+        symlink_target: None,
     }
 }
 
@@ -21,6 +22,8 @@ fn make_scan(files: Vec<FileMeta>) -> ScanResult {
     ScanResult {
         root: PathBuf::from("/test"),
         files,
+        dirs: Vec::new(),
+        excluded: Vec::new(),
         scan_time: SystemTime::now(),
     }
 }
@@ -356,3 +359,128 @@ fn test_dest_empty() {
     assert_eq!(diff.added.len(), 2);
     assert_eq!(diff.renamed.len(), 0);
 }
+
+#[test]
+fn test_duplicate_of_existing_content_is_local_copy() {
+    // Source duplicated a file that is unchanged in dest
+    let source_files = vec![
+        make_file_meta("photos/a.jpg", b"jpeg bytes"),
+        make_file_meta("copy/a.jpg", b"jpeg bytes"),
+    ];
+    let dest_files = vec![make_file_meta("photos/a.jpg", b"jpeg bytes")];
+
+    let diff = diff_scans(&make_scan(source_files), &make_scan(dest_files)).unwrap();
+
+    assert_eq!(diff.added.len(), 0, "Content exists in dest, nothing to stream");
+    assert_eq!(diff.renamed.len(), 0);
+    assert_eq!(diff.copied.len(), 1);
+
+    let (existing, new) = &diff.copied[0];
+    assert_eq!(existing.path, PathBuf::from("photos/a.jpg"));
+    assert_eq!(new.path, PathBuf::from("copy/a.jpg"));
+}
+
+#[test]
+fn test_rename_never_steals_file_kept_in_source() {
+    // The copy is visited first; it must not move the original away
+    let source_files =
+        vec![make_file_meta("backup/a.txt", b"same"), make_file_meta("a.txt", b"same")];
+    let dest_files = vec![make_file_meta("a.txt", b"same")];
+
+    let diff = diff_scans(&make_scan(source_files), &make_scan(dest_files)).unwrap();
+
+    assert_eq!(diff.renamed.len(), 0);
+    assert_eq!(diff.copied.len(), 1);
+    assert_eq!(diff.removed.len(), 0);
+}
+
+#[test]
+fn test_directory_move_detected() {
+    let source_files = vec![
+        make_file_meta("archive/photos-2023/jan/a.jpg", b"a"),
+        make_file_meta("archive/photos-2023/jan/b.jpg", b"b"),
+        make_file_meta("archive/photos-2023/c.jpg", b"c"),
+        make_file_meta("notes.txt", b"notes"),
+    ];
+    let dest_files = vec![
+        make_file_meta("photos/2023/jan/a.jpg", b"a"),
+        make_file_meta("photos/2023/jan/b.jpg", b"b"),
+        make_file_meta("photos/2023/c.jpg", b"c"),
+        make_file_meta("notes.txt", b"notes"),
+    ];
+
+    let diff = diff_scans(&make_scan(source_files), &make_scan(dest_files)).unwrap();
+
+    assert_eq!(diff.renamed_dirs.len(), 1, "Should collapse into one directory move");
+    assert_eq!(diff.renamed.len(), 0);
+    let dir = &diff.renamed_dirs[0];
+    assert_eq!(dir.old, PathBuf::from("photos/2023"));
+    assert_eq!(dir.new, PathBuf::from("archive/photos-2023"));
+    assert_eq!(dir.files.len(), 3);
+}
+
+#[test]
+fn test_directory_move_with_edit_becomes_modification() {
+    let source_files = vec![
+        make_file_meta("new/a.txt", b"a"),
+        make_file_meta("new/b.txt", b"b"),
+        make_file_meta("new/c.txt", b"c edited"),
+    ];
+    let dest_files = vec![
+        make_file_meta("old/a.txt", b"a"),
+        make_file_meta("old/b.txt", b"b"),
+        make_file_meta("old/c.txt", b"c"),
+    ];
+
+    let diff = diff_scans(&make_scan(source_files), &make_scan(dest_files)).unwrap();
+
+    assert_eq!(diff.renamed_dirs.len(), 1);
+    assert_eq!(diff.added.len(), 0);
+    assert_eq!(diff.removed.len(), 0);
+    assert_eq!(diff.modified.len(), 1, "Edited file is modified in place after the move");
+    assert_eq!(diff.modified[0].path, PathBuf::from("new/c.txt"));
+}
+
+#[test]
+fn test_no_directory_move_when_source_keeps_dir() {
+    // "old" still exists in source, so it can't be moved wholesale
+    let source_files = vec![
+        make_file_meta("new/a.txt", b"a"),
+        make_file_meta("new/b.txt", b"b"),
+        make_file_meta("old/keep.txt", b"keep"),
+    ];
+    let dest_files = vec![
+        make_file_meta("old/a.txt", b"a"),
+        make_file_meta("old/b.txt", b"b"),
+        make_file_meta("old/keep.txt", b"keep"),
+    ];
+
+    let diff = diff_scans(&make_scan(source_files), &make_scan(dest_files)).unwrap();
+
+    assert_eq!(diff.renamed_dirs.len(), 0);
+    assert_eq!(diff.renamed.len(), 2);
+}
+
+#[test]
+fn test_directory_move_carries_subdirectories() {
+    let mtime = SystemTime::UNIX_EPOCH;
+    let dir = |path: &str| DirMeta {
+        path: PathBuf::from(path),
+        mtime,
+        permissions: None,
+    };
+
+    let mut source =
+        make_scan(vec![make_file_meta("new/a.txt", b"a"), make_file_meta("new/b.txt", b"b")]);
+    source.dirs = vec![dir("new"), dir("new/empty")];
+    let mut dest =
+        make_scan(vec![make_file_meta("old/a.txt", b"a"), make_file_meta("old/b.txt", b"b")]);
+    dest.dirs = vec![dir("old"), dir("old/empty")];
+
+    let diff = diff_scans(&source, &dest).unwrap();
+
+    assert_eq!(diff.renamed_dirs.len(), 1);
+    assert!(diff.added_dirs.is_empty(), "Subdirectories travel with the move");
+    assert!(diff.removed_dirs.is_empty());
+    assert!(diff.modified_dirs.is_empty());
+}
@@ -0,0 +1,212 @@
+//! Unit tests for directory scanning
+
+use janice::core::{scan_directory, scan_directory_with_options, ScanOptions};
+use janice::filter::Filter;
+use janice::hash::hash_bytes;
+use janice::index::{HashIndex, IndexKey};
+use janice::io::{JAN_INDEX_FILE, JAN_TEMP_DIR};
+use std::fs::{self, File};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tempfile::tempdir;
+
+/// Write a file and backdate its mtime so the hash index will accept it
+fn write_old_file(path: &Path, content: &[u8]) {
+    fs::write(path, content).unwrap();
+    let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+    File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(an_hour_ago)
+        .unwrap();
+}
+
+fn indexed_scan(rehash: bool) -> ScanOptions {
+    ScanOptions {
+        use_index: true,
+        save_index: true,
+        rehash,
+        ..ScanOptions::default()
+    }
+}
+
+#[test]
+fn test_scan_writes_index() {
+    let dir = tempdir().unwrap();
+    write_old_file(&dir.path().join("a.txt"), b"alpha");
+
+    let scan = scan_directory_with_options(dir.path(), &indexed_scan(false)).unwrap();
+
+    assert_eq!(scan.files.len(), 1);
+    assert!(dir.path().join(JAN_INDEX_FILE).exists(), "Index should be written");
+    assert!(!dir.path().join(JAN_TEMP_DIR).exists(), "Temp dir should not be left behind");
+    assert_eq!(HashIndex::load(dir.path()).unwrap().len(), 1);
+}
+
+#[test]
+fn test_read_only_scan_leaves_tree_untouched() {
+    let dir = tempdir().unwrap();
+    write_old_file(&dir.path().join("a.txt"), b"alpha");
+
+    let options = ScanOptions { save_index: false, ..indexed_scan(false) };
+    scan_directory_with_options(dir.path(), &options).unwrap();
+
+    let names: Vec<_> = fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(names, ["a.txt"], "Nothing but the file itself should be there");
+}
+
+#[test]
+fn test_index_excluded_from_scan() {
+    let dir = tempdir().unwrap();
+    write_old_file(&dir.path().join("a.txt"), b"alpha");
+
+    scan_directory_with_options(dir.path(), &indexed_scan(false)).unwrap();
+    let scan = scan_directory(dir.path()).unwrap();
+
+    assert_eq!(scan.files.len(), 1, "The index file must not show up as content");
+}
+
+#[test]
+fn test_index_hash_reused_until_rehash() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("a.txt");
+    write_old_file(&path, b"alpha");
+
+    // Plant a bogus hash for the file's current fingerprint
+    let bogus = hash_bytes(b"not alpha");
+    let key = IndexKey::from_metadata(&fs::metadata(&path).unwrap());
+    let mut index = HashIndex::new();
+    index.insert("a.txt".into(), key, bogus.clone(), SystemTime::now());
+    index.save(dir.path()).unwrap();
+
+    let scan = scan_directory_with_options(dir.path(), &indexed_scan(false)).unwrap();
+    assert_eq!(scan.files[0].hash, Some(bogus), "Matching fingerprint should reuse stored hash");
+
+    let scan = scan_directory_with_options(dir.path(), &indexed_scan(true)).unwrap();
+    assert_eq!(scan.files[0].hash, Some(hash_bytes(b"alpha")), "--rehash should read the file");
+}
+
+#[test]
+fn test_index_invalidated_by_change() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("a.txt");
+    write_old_file(&path, b"alpha");
+    scan_directory_with_options(dir.path(), &indexed_scan(false)).unwrap();
+
+    write_old_file(&path, b"bravo!");
+    let scan = scan_directory_with_options(dir.path(), &indexed_scan(false)).unwrap();
+
+    assert_eq!(scan.files[0].hash, Some(hash_bytes(b"bravo!")));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_scan_records_symlinks() {
+    use janice::core::SymlinkMode;
+    use std::os::unix::fs::symlink;
+
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("real.txt"), b"data").unwrap();
+    symlink("real.txt", dir.path().join("inside")).unwrap();
+    symlink("/etc/hostname", dir.path().join("absolute")).unwrap();
+    symlink("../elsewhere", dir.path().join("escaping")).unwrap();
+
+    let options = |symlinks| ScanOptions { symlinks, ..ScanOptions::default() };
+
+    let scan = scan_directory(dir.path()).unwrap();
+    let link = scan.files.iter().find(|f| f.path == Path::new("inside")).unwrap();
+    assert_eq!(link.symlink_target.as_deref(), Some(Path::new("real.txt")));
+    assert_eq!(scan.files.len(), 4, "Dangling links are still recorded");
+
+    let safe = scan_directory_with_options(dir.path(), &options(SymlinkMode::Safe)).unwrap();
+    let mut paths: Vec<_> = safe.files.iter().map(|f| f.path.clone()).collect();
+    paths.sort();
+    assert_eq!(paths, vec![Path::new("inside"), Path::new("real.txt")]);
+
+    let followed = scan_directory_with_options(dir.path(), &options(SymlinkMode::Follow)).unwrap();
+    let inside = followed.files.iter().find(|f| f.path == Path::new("inside")).unwrap();
+    assert!(inside.symlink_target.is_none());
+    assert_eq!(inside.hash, Some(hash_bytes(b"data")));
+}
+
+#[test]
+fn test_scan_lists_directories() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("empty")).unwrap();
+    fs::create_dir_all(dir.path().join("full/nested")).unwrap();
+    fs::write(dir.path().join("full/nested/f.txt"), b"f").unwrap();
+
+    let scan = scan_directory(dir.path()).unwrap();
+    let mut dirs: Vec<_> = scan.dirs.iter().map(|d| d.path.clone()).collect();
+    dirs.sort();
+
+    assert_eq!(dirs, vec![Path::new("empty"), Path::new("full"), Path::new("full/nested")]);
+}
+
+#[test]
+fn test_gitignore_is_opt_in() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("target")).unwrap();
+    fs::write(dir.path().join(".gitignore"), "target/\n.env\n").unwrap();
+    fs::write(dir.path().join("target/out.bin"), b"build").unwrap();
+    fs::write(dir.path().join(".env"), b"SECRET=1").unwrap();
+    fs::write(dir.path().join(".janignore"), "*.bak\n").unwrap();
+    fs::write(dir.path().join("notes.bak"), b"old").unwrap();
+
+    let scan = scan_directory(dir.path()).unwrap();
+    assert_eq!(scan.files.len(), 5, "Ignore files are not honoured by default");
+    assert!(scan.excluded.is_empty());
+
+    let options = ScanOptions {
+        respect_gitignore: true,
+        ignore_files: vec![".janignore".to_string()],
+        ..ScanOptions::default()
+    };
+    let scan = scan_directory_with_options(dir.path(), &options).unwrap();
+    let mut paths: Vec<_> = scan.files.iter().map(|f| f.path.clone()).collect();
+    paths.sort();
+    assert_eq!(paths, vec![Path::new(".gitignore"), Path::new(".janignore")]);
+    assert!(scan.excluded.contains(&(".gitignore".to_string(), 2)));
+    assert!(scan.excluded.contains(&(".janignore".to_string(), 1)));
+}
+
+#[test]
+fn test_ordered_filter_rules() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("shoots/day1")).unwrap();
+    fs::create_dir_all(dir.path().join("other")).unwrap();
+    for file in ["shoots/day1/a.raw", "shoots/day1/a.xmp", "shoots/day1/a.jpg", "other/b.raw"] {
+        fs::write(dir.path().join(file), file).unwrap();
+    }
+
+    let mut filter = Filter::new();
+    for line in ["+ /shoots/", "+ /shoots/**/", "+ /shoots/**/*.raw", "+ /shoots/**/*.xmp", "- *"] {
+        filter.add_line(line, "rules").unwrap();
+    }
+    let options = ScanOptions { filter, ..ScanOptions::default() };
+
+    let scan = scan_directory_with_options(dir.path(), &options).unwrap();
+    let mut paths: Vec<_> = scan.files.iter().map(|f| f.path.clone()).collect();
+    paths.sort();
+    assert_eq!(paths, vec![Path::new("shoots/day1/a.raw"), Path::new("shoots/day1/a.xmp")]);
+    assert_eq!(scan.excluded, vec![("rules".to_string(), 2)], "a.jpg and other/");
+}
+
+#[test]
+fn test_filter_merge_files() {
+    let dir = tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("project")).unwrap();
+    fs::write(dir.path().join("project/.jan-filter"), "- *.o\n").unwrap();
+    fs::write(dir.path().join("project/main.o"), b"obj").unwrap();
+    fs::write(dir.path().join("top.o"), b"obj").unwrap();
+
+    let mut filter = Filter::new();
+    filter.dir_merge(".jan-filter");
+    let options = ScanOptions { filter, ..ScanOptions::default() };
+
+    let scan = scan_directory_with_options(dir.path(), &options).unwrap();
+    let mut paths: Vec<_> = scan.files.iter().map(|f| f.path.clone()).collect();
+    paths.sort();
+    assert_eq!(paths, vec![Path::new("project/.jan-filter"), Path::new("top.o")]);
+}
@@ -0,0 +1,146 @@
+//! Unit tests for snapshot backups linked to the previous snapshot
+
+use janice::core::ScanOptions;
+use janice::io::{JAN_INDEX_FILE, JAN_TEMP_DIR};
+use janice::snapshot::{
+    plan_snapshot, remove_snapshot, snapshots, space_freed, take_snapshot, Snapshot, SnapshotStats,
+};
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+fn backup(source: &Path, dest_root: &Path) -> (Snapshot, SnapshotStats) {
+    let plan = plan_snapshot(source, dest_root, &ScanOptions::default()).unwrap();
+    take_snapshot(&plan, dest_root, true).unwrap()
+}
+
+#[cfg(unix)]
+fn same_inode(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(a).unwrap().ino() == fs::metadata(b).unwrap().ino()
+}
+
+#[test]
+fn test_first_snapshot_copies_everything() {
+    let src = tempdir().unwrap();
+    let root = tempdir().unwrap();
+    fs::create_dir_all(src.path().join("docs/empty")).unwrap();
+    fs::write(src.path().join("docs/a.txt"), b"alpha").unwrap();
+    fs::write(src.path().join("b.txt"), b"beta").unwrap();
+
+    let (snapshot, stats) = backup(src.path(), root.path());
+
+    assert_eq!(
+        stats,
+        SnapshotStats {
+            linked: 0,
+            linked_bytes: 0,
+            copied: 2,
+            copied_bytes: 9
+        }
+    );
+    assert_eq!(fs::read(snapshot.path.join("docs/a.txt")).unwrap(), b"alpha");
+    assert!(snapshot.path.join("docs/empty").is_dir());
+    assert!(snapshot.path.join(JAN_INDEX_FILE).exists());
+    assert!(!root.path().join(JAN_TEMP_DIR).exists());
+    assert_eq!(snapshots(root.path()).unwrap(), [snapshot]);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_next_snapshot_links_unchanged_files() {
+    let src = tempdir().unwrap();
+    let root = tempdir().unwrap();
+    fs::create_dir(src.path().join("docs")).unwrap();
+    fs::write(src.path().join("docs/a.txt"), b"alpha").unwrap();
+    fs::write(src.path().join("b.txt"), b"beta").unwrap();
+    fs::write(src.path().join("c.txt"), b"gamma").unwrap();
+    std::os::unix::fs::symlink("b.txt", src.path().join("link")).unwrap();
+    let (first, _) = backup(src.path(), root.path());
+
+    fs::write(src.path().join("b.txt"), b"beta, edited").unwrap();
+    fs::rename(src.path().join("c.txt"), src.path().join("docs/c.txt")).unwrap();
+    fs::write(src.path().join("new.txt"), b"new").unwrap();
+
+    let plan = plan_snapshot(src.path(), root.path(), &ScanOptions::default()).unwrap();
+    assert_eq!(plan.previous.as_ref(), Some(&first));
+    assert_eq!((plan.diff.added.len(), plan.diff.modified.len()), (1, 1));
+    let (second, stats) = take_snapshot(&plan, root.path(), false).unwrap();
+
+    assert_ne!(first.name, second.name);
+    // Unchanged and moved files share the previous snapshot's bytes
+    assert_eq!((stats.linked, stats.linked_bytes), (2, 10));
+    assert!(same_inode(&first.path.join("docs/a.txt"), &second.path.join("docs/a.txt")));
+    assert!(same_inode(&first.path.join("c.txt"), &second.path.join("docs/c.txt")));
+    // Changed and new files, and the link, are copied
+    assert_eq!(stats.copied, 3);
+    assert!(!same_inode(&first.path.join("b.txt"), &second.path.join("b.txt")));
+    assert_eq!(fs::read(second.path.join("b.txt")).unwrap(), b"beta, edited");
+    assert_eq!(fs::read_link(second.path.join("link")).unwrap(), Path::new("b.txt"));
+    // The previous snapshot stays as it was
+    assert_eq!(fs::read(first.path.join("b.txt")).unwrap(), b"beta");
+    assert!(first.path.join("c.txt").exists());
+
+    let names: Vec<_> = snapshots(root.path()).unwrap().into_iter().map(|s| s.name).collect();
+    assert_eq!(names, [first.name, second.name]);
+}
+
+#[test]
+fn test_interrupted_snapshot_is_never_listed() {
+    let src = tempdir().unwrap();
+    let root = tempdir().unwrap();
+    fs::write(src.path().join("a.txt"), b"alpha").unwrap();
+
+    // What a killed backup leaves: a half-built snapshot among the temp files
+    let stale = root.path().join(JAN_TEMP_DIR).join("2024-02-29T1234");
+    fs::create_dir_all(&stale).unwrap();
+    fs::write(stale.join("half.txt"), b"ha").unwrap();
+    assert!(snapshots(root.path()).unwrap().is_empty());
+
+    let (snapshot, _) = backup(src.path(), root.path());
+    assert_eq!(snapshots(root.path()).unwrap(), [snapshot]);
+    assert!(!root.path().join(JAN_TEMP_DIR).exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_pruning_frees_only_what_no_other_snapshot_links() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let src = tempdir().unwrap();
+    let root = tempdir().unwrap();
+    fs::create_dir(src.path().join("locked")).unwrap();
+    fs::write(src.path().join("locked/kept.txt"), b"shared by all").unwrap();
+    fs::write(src.path().join("once.txt"), b"first only").unwrap();
+    fs::set_permissions(src.path().join("locked"), fs::Permissions::from_mode(0o555)).unwrap();
+    let (first, _) = backup(src.path(), root.path());
+
+    fs::write(src.path().join("once.txt"), b"second").unwrap();
+    let (second, _) = backup(src.path(), root.path());
+    fs::write(src.path().join("once.txt"), b"third").unwrap();
+    let (third, _) = backup(src.path(), root.path());
+
+    // Each holds its once.txt and its index alone; kept.txt is everyone's
+    let index =
+        |snapshot: &Snapshot| fs::metadata(snapshot.path.join(JAN_INDEX_FILE)).unwrap().len();
+    let freed = space_freed(&[&first]).unwrap();
+    assert_eq!(freed.per_snapshot, [10 + index(&first)]);
+    let freed = space_freed(&[&first, &second]).unwrap();
+    assert_eq!(freed.per_snapshot, [10 + index(&first), 6 + index(&second)]);
+    assert_eq!(freed.total, 16 + index(&first) + index(&second));
+    // Once only one snapshot is left, what they shared is its alone
+    let freed = space_freed(&[&first, &second, &third]).unwrap();
+    assert_eq!(freed.total - freed.per_snapshot.iter().sum::<u64>(), 13);
+
+    remove_snapshot(root.path(), &first).unwrap();
+    remove_snapshot(root.path(), &second).unwrap();
+    assert_eq!(snapshots(root.path()).unwrap(), std::slice::from_ref(&third));
+    assert!(!root.path().join(JAN_TEMP_DIR).exists());
+    // Shared files lose a link, never their content or mode
+    let kept = third.path.join("locked/kept.txt");
+    assert_eq!(fs::read(&kept).unwrap(), b"shared by all");
+    assert_eq!(
+        fs::metadata(third.path.join("locked")).unwrap().permissions().mode() & 0o777,
+        0o555
+    );
+}
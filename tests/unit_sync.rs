@@ -0,0 +1,839 @@
+//! Unit tests for applying diffs with sync_changes
+
+use janice::baseline::{find_conflicts, Baseline};
+use janice::core::{
+    diff_scans, diff_scans_with_options, resume_sync, scan_directory, scan_directory_with_options,
+    sync_changes, ConflictPolicy, DiffOptions, LocalCopyMode, Resolution, ScanOptions, SyncOptions,
+};
+use janice::filter::Filter;
+use janice::hash::{hash_bytes, hash_file};
+use janice::io::{
+    ResumePoint, SyncJournal, JAN_BASELINE_FILE, JAN_JOURNAL_FILE, JAN_PLAN_FILE, JAN_ROLLBACK_DIR,
+    JAN_TEMP_DIR, JAN_TRASH_DIR,
+};
+use janice::plan::{SavedPlan, Step};
+use janice::rollback::{rollback, undo_state, UndoState};
+use janice::trash::Trash;
+use std::fs::{self, File};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tempfile::tempdir;
+
+fn sync(src: &Path, dst: &Path, options: &SyncOptions) {
+    let source_scan = scan_directory(src).unwrap();
+    let dest_scan = scan_directory(dst).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    sync_changes(src, dst, &diff, options).unwrap();
+}
+
+#[test]
+fn test_sync_copies_new_and_modified() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::write(src.path().join("new.txt"), b"new").unwrap();
+    fs::write(src.path().join("changed.txt"), b"after").unwrap();
+    fs::write(dst.path().join("changed.txt"), b"before").unwrap();
+
+    sync(src.path(), dst.path(), &SyncOptions::default());
+
+    assert_eq!(fs::read(dst.path().join("new.txt")).unwrap(), b"new");
+    assert_eq!(fs::read(dst.path().join("changed.txt")).unwrap(), b"after");
+    assert!(!dst.path().join(JAN_TEMP_DIR).exists());
+    assert!(!dst.path().join(JAN_JOURNAL_FILE).exists());
+}
+
+#[test]
+fn test_rename_moves_destination_file() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::create_dir_all(src.path().join("moved")).unwrap();
+    fs::write(src.path().join("moved/report.pdf"), b"report body").unwrap();
+    fs::write(dst.path().join("report.pdf"), b"report body").unwrap();
+
+    let source_scan = scan_directory(src.path()).unwrap();
+    let dest_scan = scan_directory(dst.path()).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    assert_eq!(diff.renamed.len(), 1);
+
+    #[cfg(unix)]
+    let inode_before = {
+        use std::os::unix::fs::MetadataExt;
+        fs::metadata(dst.path().join("report.pdf")).unwrap().ino()
+    };
+
+    // The source copy is gone: a real rename must not need it
+    fs::remove_file(src.path().join("moved/report.pdf")).unwrap();
+    sync_changes(src.path(), dst.path(), &diff, &SyncOptions::default()).unwrap();
+
+    assert!(!dst.path().join("report.pdf").exists());
+    let moved = dst.path().join("moved/report.pdf");
+    assert_eq!(fs::read(&moved).unwrap(), b"report body");
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        assert_eq!(fs::metadata(&moved).unwrap().ino(), inode_before, "Should be the same file");
+    }
+}
+
+#[test]
+fn test_delete_removed() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::write(src.path().join("keep.txt"), b"keep").unwrap();
+    fs::write(dst.path().join("keep.txt"), b"keep").unwrap();
+    fs::write(dst.path().join("stale.txt"), b"stale").unwrap();
+
+    sync(src.path(), dst.path(), &SyncOptions::default());
+    assert!(dst.path().join("stale.txt").exists(), "Nothing deleted without delete_removed");
+
+    let options = SyncOptions {
+        delete_removed: true,
+        ..SyncOptions::default()
+    };
+    sync(src.path(), dst.path(), &options);
+    assert!(!dst.path().join("stale.txt").exists());
+    assert!(dst.path().join("keep.txt").exists());
+}
+
+#[test]
+fn test_duplicate_copied_from_destination() {
+    for mode in [LocalCopyMode::Copy, LocalCopyMode::Hardlink, LocalCopyMode::Reflink] {
+        let src = tempdir().unwrap();
+        let dst = tempdir().unwrap();
+        fs::create_dir_all(src.path().join("copy")).unwrap();
+        fs::write(src.path().join("a.bin"), b"big payload").unwrap();
+        fs::write(src.path().join("copy/a.bin"), b"big payload").unwrap();
+        fs::write(dst.path().join("a.bin"), b"big payload").unwrap();
+
+        let source_scan = scan_directory(src.path()).unwrap();
+        let dest_scan = scan_directory(dst.path()).unwrap();
+        let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+        assert_eq!(diff.copied.len(), 1);
+
+        // Prove the bytes come from the destination, not the source
+        fs::remove_file(src.path().join("copy/a.bin")).unwrap();
+        let options = SyncOptions {
+            local_copy: mode,
+            verify_after_copy: true,
+            ..SyncOptions::default()
+        };
+        sync_changes(src.path(), dst.path(), &diff, &options).unwrap();
+
+        assert_eq!(fs::read(dst.path().join("copy/a.bin")).unwrap(), b"big payload");
+        assert_eq!(fs::read(dst.path().join("a.bin")).unwrap(), b"big payload");
+    }
+}
+
+#[test]
+fn test_directory_move_applied_as_one_rename() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    for (dir, root) in [("archive/photos-2023", src.path()), ("photos/2023", dst.path())] {
+        fs::create_dir_all(root.join(dir).join("jan")).unwrap();
+        fs::write(root.join(dir).join("jan/a.jpg"), b"a").unwrap();
+        fs::write(root.join(dir).join("b.jpg"), b"b").unwrap();
+        fs::write(root.join(dir).join("c.jpg"), b"c").unwrap();
+    }
+    fs::write(src.path().join("archive/photos-2023/c.jpg"), b"c edited").unwrap();
+
+    let source_scan = scan_directory(src.path()).unwrap();
+    let dest_scan = scan_directory(dst.path()).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    assert_eq!(diff.renamed_dirs.len(), 1);
+
+    sync_changes(src.path(), dst.path(), &diff, &SyncOptions::default()).unwrap();
+
+    let moved = dst.path().join("archive/photos-2023");
+    assert_eq!(fs::read(moved.join("jan/a.jpg")).unwrap(), b"a");
+    assert_eq!(fs::read(moved.join("b.jpg")).unwrap(), b"b");
+    assert_eq!(fs::read(moved.join("c.jpg")).unwrap(), b"c edited");
+    assert!(!dst.path().join("photos/2023").exists());
+
+    let rescan = diff_scans(&source_scan, &scan_directory(dst.path()).unwrap()).unwrap();
+    assert!(rescan.added.is_empty() && rescan.modified.is_empty() && rescan.removed.is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlinks_recreated_and_retargeted() {
+    use std::os::unix::fs::symlink;
+
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::write(src.path().join("target.txt"), b"target").unwrap();
+    symlink("target.txt", src.path().join("link")).unwrap();
+    symlink("missing", src.path().join("dangling")).unwrap();
+    symlink("old-target", dst.path().join("link")).unwrap();
+
+    let diff =
+        diff_scans(&scan_directory(src.path()).unwrap(), &scan_directory(dst.path()).unwrap())
+            .unwrap();
+    assert_eq!(diff.modified.len(), 1, "A changed link target is a modification");
+    assert!(diff.renamed.is_empty() && diff.copied.is_empty());
+
+    sync_changes(src.path(), dst.path(), &diff, &SyncOptions::default()).unwrap();
+
+    assert_eq!(fs::read_link(dst.path().join("link")).unwrap(), Path::new("target.txt"));
+    assert_eq!(fs::read_link(dst.path().join("dangling")).unwrap(), Path::new("missing"));
+    assert_eq!(fs::read(dst.path().join("link")).unwrap(), b"target");
+
+    let again =
+        diff_scans(&scan_directory(src.path()).unwrap(), &scan_directory(dst.path()).unwrap())
+            .unwrap();
+    assert!(again.added.is_empty() && again.modified.is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_followed_link_resynced_after_target_changes() {
+    use janice::core::SymlinkMode;
+    use std::os::unix::fs::symlink;
+
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    let outside = tempdir().unwrap();
+    let target = outside.path().join("target.txt");
+    let write_target = |body: &[u8], age: u64| {
+        fs::write(&target, body).unwrap();
+        let mtime = SystemTime::now() - Duration::from_secs(age);
+        File::options().write(true).open(&target).unwrap().set_modified(mtime).unwrap();
+    };
+    write_target(b"first", 3600);
+    symlink(&target, src.path().join("link")).unwrap();
+
+    let options = ScanOptions {
+        use_index: true,
+        save_index: true,
+        symlinks: SymlinkMode::Follow,
+        ..ScanOptions::default()
+    };
+    let sync_followed = || {
+        let source_scan = scan_directory_with_options(src.path(), &options).unwrap();
+        let dest_scan = scan_directory_with_options(dst.path(), &options).unwrap();
+        // The link is seen as the file it points to
+        assert_eq!(source_scan.files[0].size, fs::metadata(&target).unwrap().len());
+        let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+        sync_changes(src.path(), dst.path(), &diff, &SyncOptions::default()).unwrap();
+        diff
+    };
+
+    sync_followed();
+    assert_eq!(fs::read(dst.path().join("link")).unwrap(), b"first");
+
+    // Editing the target leaves the link itself as it was
+    write_target(b"second, longer", 1800);
+    let diff = sync_followed();
+    assert_eq!(diff.modified.len(), 1, "The index must not vouch for the old content");
+    assert_eq!(fs::read(dst.path().join("link")).unwrap(), b"second, longer");
+}
+
+#[test]
+fn test_empty_dirs_created_with_source_mtime() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::create_dir_all(src.path().join("empty/deeper")).unwrap();
+    fs::create_dir_all(src.path().join("data")).unwrap();
+    fs::write(src.path().join("data/file.txt"), b"x").unwrap();
+    let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+    for dir in ["empty", "data"] {
+        File::open(src.path().join(dir)).unwrap().set_modified(an_hour_ago).unwrap();
+    }
+
+    sync(src.path(), dst.path(), &SyncOptions::default());
+
+    assert!(dst.path().join("empty/deeper").is_dir());
+    let mtime = |p: &Path| fs::metadata(p).unwrap().modified().unwrap();
+    assert_eq!(mtime(&dst.path().join("empty")), an_hour_ago);
+    assert_eq!(mtime(&dst.path().join("data")), an_hour_ago);
+
+    let again =
+        diff_scans(&scan_directory(src.path()).unwrap(), &scan_directory(dst.path()).unwrap())
+            .unwrap();
+    assert!(again.added_dirs.is_empty() && again.modified_dirs.is_empty());
+}
+
+#[test]
+fn test_delete_prunes_emptied_dirs() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::create_dir_all(dst.path().join("gone/inner")).unwrap();
+    fs::write(dst.path().join("gone/inner/old.txt"), b"old").unwrap();
+    fs::create_dir_all(dst.path().join("stale")).unwrap();
+
+    // Without --delete, nothing is pruned
+    sync(src.path(), dst.path(), &SyncOptions::default());
+    assert!(dst.path().join("gone/inner/old.txt").exists());
+    assert!(dst.path().join("stale").is_dir());
+
+    let options = SyncOptions {
+        delete_removed: true,
+        ..SyncOptions::default()
+    };
+    sync(src.path(), dst.path(), &options);
+    assert!(!dst.path().join("gone").exists());
+    assert!(!dst.path().join("stale").exists());
+}
+
+#[test]
+fn test_rename_prunes_vacated_dir() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::create_dir_all(src.path().join("new")).unwrap();
+    fs::write(src.path().join("new/only.txt"), b"only file").unwrap();
+    fs::create_dir_all(dst.path().join("old")).unwrap();
+    fs::write(dst.path().join("old/only.txt"), b"only file").unwrap();
+    fs::create_dir_all(dst.path().join("untouched")).unwrap();
+
+    sync(src.path(), dst.path(), &SyncOptions::default());
+
+    assert!(dst.path().join("new/only.txt").exists());
+    assert!(!dst.path().join("old").exists(), "Dir emptied by a rename is pruned");
+    assert!(dst.path().join("untouched").is_dir(), "Other dirs need --delete");
+}
+
+#[test]
+fn test_delete_spares_filtered_paths() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::create_dir_all(dst.path().join("cache")).unwrap();
+    fs::write(dst.path().join("cache/blob"), b"local only").unwrap();
+    fs::write(dst.path().join("stale.txt"), b"stale").unwrap();
+
+    let mut filter = Filter::new();
+    filter.exclude("cache/", "test").unwrap();
+    let options = SyncOptions {
+        delete_removed: true,
+        filter,
+        ..SyncOptions::default()
+    };
+
+    // An unfiltered destination scan still lists cache/blob as removed
+    sync(src.path(), dst.path(), &options);
+
+    assert!(dst.path().join("cache/blob").exists(), "Filtered paths are never deleted");
+    assert!(!dst.path().join("stale.txt").exists());
+}
+
+#[test]
+fn test_protected_files_survive_delete() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::create_dir_all(dst.path().join("logs")).unwrap();
+    fs::write(dst.path().join("logs/today.log"), b"keep me").unwrap();
+    fs::write(dst.path().join("old.txt"), b"bye").unwrap();
+
+    let mut protect = Filter::new();
+    protect.include("/logs/", "--protect").unwrap();
+    let diff = diff_scans_with_options(
+        &scan_directory(src.path()).unwrap(),
+        &scan_directory(dst.path()).unwrap(),
+        &DiffOptions { protect },
+    )
+    .unwrap();
+
+    assert_eq!(diff.protected.len(), 1);
+    assert_eq!(diff.protected[0].path, Path::new("logs/today.log"));
+    assert_eq!(diff.removed.len(), 1);
+    assert!(diff.removed_dirs.is_empty(), "Protected dirs are not pruned");
+
+    let options = SyncOptions {
+        delete_removed: true,
+        ..SyncOptions::default()
+    };
+    sync_changes(src.path(), dst.path(), &diff, &options).unwrap();
+
+    assert!(dst.path().join("logs/today.log").exists());
+    assert!(!dst.path().join("old.txt").exists());
+}
+
+#[test]
+fn test_protected_files_are_never_moved() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::create_dir_all(dst.path().join("logs")).unwrap();
+    fs::write(dst.path().join("logs/today.log"), b"same bytes").unwrap();
+    fs::write(dst.path().join("logs/older.log"), b"more bytes").unwrap();
+    // The whole dir reappears elsewhere, which would otherwise be a dir move
+    fs::create_dir_all(src.path().join("new")).unwrap();
+    fs::write(src.path().join("new/today.log"), b"same bytes").unwrap();
+    fs::write(src.path().join("new/older.log"), b"more bytes").unwrap();
+
+    let mut protect = Filter::new();
+    protect.include("/logs/", "--protect").unwrap();
+    let diff = diff_scans_with_options(
+        &scan_directory(src.path()).unwrap(),
+        &scan_directory(dst.path()).unwrap(),
+        &DiffOptions { protect },
+    )
+    .unwrap();
+
+    assert!(diff.renamed.is_empty() && diff.renamed_dirs.is_empty() && diff.copied.is_empty());
+    assert_eq!(diff.added.len(), 2);
+    assert_eq!(diff.protected.len(), 2);
+
+    let options = SyncOptions {
+        delete_removed: true,
+        ..SyncOptions::default()
+    };
+    sync_changes(src.path(), dst.path(), &diff, &options).unwrap();
+
+    assert_eq!(fs::read(dst.path().join("logs/today.log")).unwrap(), b"same bytes");
+    assert_eq!(fs::read(dst.path().join("new/today.log")).unwrap(), b"same bytes");
+}
+
+#[test]
+fn test_delete_excluded_removes_filtered_destination_files() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::write(src.path().join("keep.txt"), b"keep").unwrap();
+    fs::write(src.path().join("build.o"), b"obj").unwrap();
+    fs::write(dst.path().join("build.o"), b"obj").unwrap();
+
+    let mut filter = Filter::new();
+    filter.exclude("*.o", "test").unwrap();
+    let source_scan =
+        scan_directory_with_options(src.path(), &ScanOptions { filter, ..ScanOptions::default() })
+            .unwrap();
+
+    // The destination is scanned unfiltered and synced without the filter
+    let diff = diff_scans(&source_scan, &scan_directory(dst.path()).unwrap()).unwrap();
+    let options = SyncOptions {
+        delete_removed: true,
+        ..SyncOptions::default()
+    };
+    sync_changes(src.path(), dst.path(), &diff, &options).unwrap();
+
+    assert!(dst.path().join("keep.txt").exists());
+    assert!(!dst.path().join("build.o").exists());
+}
+
+#[test]
+fn test_quick_check_hashes_only_what_it_must() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    let stamp = SystemTime::now() - Duration::from_secs(3600);
+    let write = |root: &Path, name: &str, body: &[u8], mtime: SystemTime| {
+        fs::write(root.join(name), body).unwrap();
+        File::options()
+            .write(true)
+            .open(root.join(name))
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+    };
+    // Same size and mtime: trusted as unchanged without reading either side
+    write(src.path(), "same.txt", b"aaaa", stamp);
+    write(dst.path(), "same.txt", b"bbbb", stamp);
+    // Same size, newer mtime, same content: hashed and found equal
+    write(src.path(), "touched.txt", b"cccc", SystemTime::now());
+    write(dst.path(), "touched.txt", b"cccc", stamp);
+    // Different size: modified without hashing
+    write(src.path(), "grown.txt", b"dddddd", stamp);
+    write(dst.path(), "grown.txt", b"dd", stamp);
+    // New source file matching a destination file by size and content
+    write(src.path(), "moved.txt", b"payload", stamp);
+    write(dst.path(), "old.txt", b"payload", stamp);
+
+    let options = ScanOptions { quick: true, ..ScanOptions::default() };
+    let source_scan = scan_directory_with_options(src.path(), &options).unwrap();
+    let dest_scan = scan_directory_with_options(dst.path(), &options).unwrap();
+    assert!(source_scan.files.iter().all(|f| f.hash.is_none()));
+
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    let modified: Vec<_> = diff.modified.iter().map(|f| f.path.clone()).collect();
+    assert_eq!(modified, vec![Path::new("grown.txt")]);
+    assert_eq!(diff.renamed.len(), 1);
+    assert_eq!(diff.renamed[0].1.path, Path::new("moved.txt"));
+
+    let options = SyncOptions {
+        verify_after_copy: true,
+        ..SyncOptions::default()
+    };
+    sync_changes(src.path(), dst.path(), &diff, &options).unwrap();
+    assert_eq!(fs::read(dst.path().join("grown.txt")).unwrap(), b"dddddd");
+    assert_eq!(fs::read(dst.path().join("moved.txt")).unwrap(), b"payload");
+    assert_eq!(fs::read(dst.path().join("same.txt")).unwrap(), b"bbbb");
+}
+
+#[test]
+fn test_delta_rebuilds_modified_file() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    let old: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let mut new = old.clone();
+    new.splice(50_000..50_000, b"edited".iter().copied());
+    new.extend_from_slice(b"appended");
+    fs::write(dst.path().join("image.bin"), &old).unwrap();
+    fs::write(src.path().join("image.bin"), &new).unwrap();
+
+    let options = SyncOptions { delta: true, ..SyncOptions::default() };
+    sync(src.path(), dst.path(), &options);
+
+    assert_eq!(fs::read(dst.path().join("image.bin")).unwrap(), new);
+    assert!(!dst.path().join(JAN_JOURNAL_FILE).exists());
+}
+
+#[test]
+fn test_chunks_build_near_duplicate_from_other_file() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    let body: Vec<u8> =
+        (0..300_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+    let mut variant = b"new header\n".to_vec();
+    variant.extend_from_slice(&body[1_000..]);
+    fs::write(src.path().join("base.bin"), &body).unwrap();
+    fs::write(dst.path().join("base.bin"), &body).unwrap();
+    fs::write(src.path().join("variant.bin"), &variant).unwrap();
+
+    let options = SyncOptions { chunks: true, ..SyncOptions::default() };
+    sync(src.path(), dst.path(), &options);
+
+    assert_eq!(fs::read(dst.path().join("variant.bin")).unwrap(), variant);
+    assert_eq!(fs::read(dst.path().join("base.bin")).unwrap(), body);
+    assert!(!dst.path().join(JAN_TEMP_DIR).exists());
+}
+
+#[test]
+fn test_interrupted_copy_resumes_from_partial_file() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    let content: Vec<u8> = (0..400_000u32).map(|i| (i * 13 % 241) as u8).collect();
+    let source_path = src.path().join("big.bin");
+    fs::write(&source_path, &content).unwrap();
+
+    // What a sync killed partway through a copy leaves behind
+    let temp_dir = dst.path().join(JAN_TEMP_DIR);
+    fs::create_dir(&temp_dir).unwrap();
+    let partial = temp_dir.join("1-0.tmp");
+    fs::write(&partial, &content[..150_000]).unwrap();
+    let final_path = dst.path().join("big.bin");
+    let journal = SyncJournal::create(dst.path().join(JAN_JOURNAL_FILE)).unwrap();
+    journal.record_pending("COPY", &partial, &final_path).unwrap();
+    let point = ResumePoint {
+        source_hash: Some(hash_file(&source_path).unwrap()),
+        source_size: content.len() as u64,
+        source_mtime: fs::metadata(&source_path).unwrap().modified().unwrap(),
+        written: 150_000,
+        prefix_hash: hash_bytes(&content[..150_000]),
+    };
+    journal.record_progress(&partial, &final_path, &point).unwrap();
+    journal.keep().unwrap();
+
+    let options = SyncOptions {
+        verify_after_copy: true,
+        ..SyncOptions::default()
+    };
+    sync(src.path(), dst.path(), &options);
+
+    assert_eq!(fs::read(&final_path).unwrap(), content);
+    assert!(!dst.path().join(JAN_TEMP_DIR).exists());
+    assert!(!dst.path().join(JAN_JOURNAL_FILE).exists());
+}
+
+#[test]
+fn test_failed_sync_resumes_from_saved_plan() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        fs::write(src.path().join(name), name.as_bytes()).unwrap();
+    }
+    let source_scan = scan_directory(src.path()).unwrap();
+    let dest_scan = scan_directory(dst.path()).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+
+    // One source file vanishes between scan and copy
+    let vanished = src.path().join("b.txt");
+    let mtime = fs::metadata(&vanished).unwrap().modified().unwrap();
+    fs::remove_file(&vanished).unwrap();
+    assert!(sync_changes(src.path(), dst.path(), &diff, &SyncOptions::default()).is_err());
+
+    let saved = SavedPlan::load(dst.path()).unwrap().expect("plan kept after failure");
+    assert_eq!(saved.plan.step_count(), 3);
+    let b = diff.added.iter().position(|f| f.path == Path::new("b.txt")).unwrap();
+    assert!(!saved.done.contains(&Step::Copy(b)));
+
+    // Still missing: the plan no longer matches the source
+    assert!(resume_sync(dst.path(), &saved).is_err());
+
+    fs::write(&vanished, b"b.txt").unwrap();
+    File::options()
+        .write(true)
+        .open(&vanished)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+    let saved = SavedPlan::load(dst.path()).unwrap().unwrap();
+    resume_sync(dst.path(), &saved).unwrap();
+
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        assert_eq!(fs::read(dst.path().join(name)).unwrap(), name.as_bytes());
+    }
+    assert!(!dst.path().join(JAN_PLAN_FILE).exists());
+    assert!(!dst.path().join(JAN_JOURNAL_FILE).exists());
+
+    // What the resumed sync wrote is the baseline the next sync compares with
+    let written = scan_directory(dst.path()).unwrap();
+    let baseline = Baseline::after_resume(src.path(), &written, &saved.plan.diff, None).unwrap();
+    assert_eq!(baseline.files.len(), 3);
+}
+
+#[test]
+fn test_resume_skips_steps_already_done() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::write(src.path().join("done.txt"), b"source").unwrap();
+    fs::write(src.path().join("todo.txt"), b"source").unwrap();
+    fs::write(src.path().join("extra.txt"), b"x").unwrap();
+    let source_scan = scan_directory(src.path()).unwrap();
+    let dest_scan = scan_directory(dst.path()).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+
+    // Fail after planning, so a plan is left to resume
+    fs::remove_file(src.path().join("extra.txt")).unwrap();
+    assert!(sync_changes(src.path(), dst.path(), &diff, &SyncOptions::default()).is_err());
+    let mut saved = SavedPlan::load(dst.path()).unwrap().unwrap();
+
+    // Pretend "done.txt" had been copied, with content a rerun would overwrite
+    let done = diff.added.iter().position(|f| f.path == Path::new("done.txt")).unwrap();
+    let extra = diff.added.iter().position(|f| f.path == Path::new("extra.txt")).unwrap();
+    fs::write(dst.path().join("done.txt"), b"kept").unwrap();
+    saved.done.insert(Step::Copy(done));
+    saved.done.insert(Step::Copy(extra));
+    resume_sync(dst.path(), &saved).unwrap();
+
+    assert_eq!(fs::read(dst.path().join("done.txt")).unwrap(), b"kept");
+    assert_eq!(fs::read(dst.path().join("todo.txt")).unwrap(), b"source");
+    assert!(!dst.path().join(JAN_PLAN_FILE).exists());
+}
+
+#[test]
+fn test_failed_transactional_sync_is_rolled_back() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::create_dir(src.path().join("sub")).unwrap();
+    fs::write(src.path().join("sub/new.txt"), b"new").unwrap();
+    fs::write(src.path().join("changed.txt"), b"after").unwrap();
+    fs::write(src.path().join("vanishing.txt"), b"soon gone").unwrap();
+    fs::write(dst.path().join("changed.txt"), b"before").unwrap();
+    let dest_mtime = fs::metadata(dst.path()).unwrap().modified().unwrap();
+    let source_scan = scan_directory(src.path()).unwrap();
+    let dest_scan = scan_directory(dst.path()).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+
+    fs::remove_file(src.path().join("vanishing.txt")).unwrap();
+    let options = SyncOptions {
+        transactional: true,
+        ..SyncOptions::default()
+    };
+    let err = sync_changes(src.path(), dst.path(), &diff, &options).unwrap_err();
+    assert!(format!("{err:#}").contains("rolled back"), "{err:#}");
+
+    let mut names: Vec<_> = fs::read_dir(dst.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["changed.txt"]);
+    assert_eq!(fs::read(dst.path().join("changed.txt")).unwrap(), b"before");
+    assert_eq!(fs::metadata(dst.path()).unwrap().modified().unwrap(), dest_mtime);
+}
+
+#[test]
+fn test_rollback_undoes_finished_transactional_sync() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::write(src.path().join("renamed.txt"), b"same bytes").unwrap();
+    fs::write(src.path().join("changed.txt"), b"after").unwrap();
+    fs::write(dst.path().join("original.txt"), b"same bytes").unwrap();
+    fs::write(dst.path().join("changed.txt"), b"before").unwrap();
+    fs::create_dir(dst.path().join("stale")).unwrap();
+    fs::write(dst.path().join("stale/old.txt"), b"deleted").unwrap();
+
+    let options = SyncOptions {
+        delete_removed: true,
+        transactional: true,
+        ..SyncOptions::default()
+    };
+    sync(src.path(), dst.path(), &options);
+    assert!(!dst.path().join("stale").exists());
+    assert_eq!(undo_state(dst.path()), UndoState::Complete);
+
+    rollback(dst.path()).unwrap();
+
+    assert_eq!(fs::read(dst.path().join("original.txt")).unwrap(), b"same bytes");
+    assert_eq!(fs::read(dst.path().join("changed.txt")).unwrap(), b"before");
+    assert_eq!(fs::read(dst.path().join("stale/old.txt")).unwrap(), b"deleted");
+    assert!(!dst.path().join("renamed.txt").exists());
+    assert!(!dst.path().join(JAN_ROLLBACK_DIR).exists());
+    assert_eq!(undo_state(dst.path()), UndoState::None);
+}
+
+#[test]
+fn test_backup_keeps_displaced_files() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::create_dir(dst.path().join("stale")).unwrap();
+    fs::write(dst.path().join("stale/old.txt"), b"deleted").unwrap();
+    fs::write(dst.path().join("changed.txt"), b"before").unwrap();
+    fs::write(src.path().join("changed.txt"), b"after").unwrap();
+    fs::write(src.path().join("new.txt"), b"new").unwrap();
+
+    let trash = Trash::of(dst.path());
+    let run = trash.new_run();
+    let options = SyncOptions {
+        delete_removed: true,
+        backup_dir: Some(Path::new(JAN_TRASH_DIR).join(&run)),
+        ..SyncOptions::default()
+    };
+    sync(src.path(), dst.path(), &options);
+
+    assert!(!dst.path().join("stale").exists());
+    assert_eq!(fs::read(dst.path().join("changed.txt")).unwrap(), b"after");
+    assert_eq!(
+        trash.files(&run).unwrap(),
+        [Path::new("changed.txt"), Path::new("stale/old.txt")]
+    );
+    assert_eq!(fs::read(trash.root().join(&run).join("changed.txt")).unwrap(), b"before");
+    // The trash is no part of the tree
+    assert_eq!(scan_directory(dst.path()).unwrap().files.len(), 2);
+
+    trash.restore(&run, dst.path(), &[]).unwrap();
+    assert_eq!(fs::read(dst.path().join("changed.txt")).unwrap(), b"before");
+    assert_eq!(fs::read(dst.path().join("stale/old.txt")).unwrap(), b"deleted");
+
+    // Rolling back a transactional sync takes its trash with it
+    let run = trash.new_run();
+    let options = SyncOptions {
+        transactional: true,
+        backup_dir: Some(Path::new(JAN_TRASH_DIR).join(&run)),
+        ..options
+    };
+    sync(src.path(), dst.path(), &options);
+    assert_eq!(
+        trash.files(&run).unwrap(),
+        [Path::new("changed.txt"), Path::new("stale/old.txt")]
+    );
+    rollback(dst.path()).unwrap();
+    assert_eq!(fs::read(dst.path().join("changed.txt")).unwrap(), b"before");
+    assert_eq!(fs::read(dst.path().join("stale/old.txt")).unwrap(), b"deleted");
+    assert!(trash.runs().unwrap().is_empty());
+}
+
+#[test]
+fn test_rollback_restores_baseline() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::write(src.path().join("a.txt"), b"v1").unwrap();
+    let sync_with_baseline = |transactional| {
+        let previous = Baseline::load(src.path(), dst.path()).unwrap();
+        let source_scan = scan_directory(src.path()).unwrap();
+        let dest_scan = scan_directory(dst.path()).unwrap();
+        let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+        let options = SyncOptions { transactional, ..SyncOptions::default() };
+        sync_changes(src.path(), dst.path(), &diff, &options).unwrap();
+        Baseline::after_sync(&source_scan, &dest_scan, &diff, false, previous.as_ref())
+            .unwrap()
+            .save_in(dst.path())
+            .unwrap();
+    };
+
+    // Undoing the first sync leaves no baseline behind
+    sync_with_baseline(true);
+    rollback(dst.path()).unwrap();
+    assert!(!dst.path().join(JAN_BASELINE_FILE).exists());
+
+    sync_with_baseline(false);
+    fs::write(src.path().join("a.txt"), b"v2").unwrap();
+    sync_with_baseline(true);
+    rollback(dst.path()).unwrap();
+    assert_eq!(fs::read(dst.path().join("a.txt")).unwrap(), b"v1");
+
+    // The restored file is what the restored baseline expects, so no conflict
+    let baseline = Baseline::load(src.path(), dst.path()).unwrap().unwrap();
+    let dest_scan = scan_directory(dst.path()).unwrap();
+    let mut diff = diff_scans(&scan_directory(src.path()).unwrap(), &dest_scan).unwrap();
+    find_conflicts(&mut diff, &dest_scan, &baseline, ConflictPolicy::Abort);
+    assert!(diff.conflicts.is_empty());
+    assert_eq!(diff.modified.len(), 1);
+}
+
+#[test]
+fn test_destination_edits_since_last_sync_are_conflicts() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    for name in ["edited.txt", "untouched.txt", "newer.txt"] {
+        fs::write(src.path().join(name), b"v1").unwrap();
+    }
+    let sync_with_baseline = |policy: ConflictPolicy| {
+        let previous = Baseline::load(src.path(), dst.path()).unwrap();
+        let source_scan = scan_directory(src.path()).unwrap();
+        let dest_scan = scan_directory(dst.path()).unwrap();
+        let mut diff = diff_scans(&source_scan, &dest_scan).unwrap();
+        if let Some(baseline) = &previous {
+            find_conflicts(&mut diff, &dest_scan, baseline, policy);
+        }
+        sync_changes(src.path(), dst.path(), &diff, &SyncOptions::default()).unwrap();
+        Baseline::after_sync(&source_scan, &dest_scan, &diff, false, previous.as_ref())
+            .unwrap()
+            .save_in(dst.path())
+            .unwrap();
+        diff
+    };
+    sync_with_baseline(ConflictPolicy::SourceWins);
+    assert!(!src.path().join(JAN_BASELINE_FILE).exists());
+
+    let earlier = SystemTime::now() - Duration::from_secs(60);
+    for name in ["edited.txt", "untouched.txt", "newer.txt"] {
+        fs::write(src.path().join(name), b"v2").unwrap();
+    }
+    fs::write(dst.path().join("edited.txt"), b"edited in dest").unwrap();
+    fs::write(dst.path().join("newer.txt"), b"edited in dest").unwrap();
+    for older in [src.path().join("edited.txt"), dst.path().join("newer.txt")] {
+        File::options().write(true).open(older).unwrap().set_modified(earlier).unwrap();
+    }
+
+    let diff = sync_with_baseline(ConflictPolicy::Newer);
+    assert_eq!(diff.modified.len(), 1, "Untouched files are no conflict");
+    assert_eq!(diff.conflicts.len(), 2);
+    assert_eq!(fs::read(dst.path().join("untouched.txt")).unwrap(), b"v2");
+    // Each side's edit is the newer one once
+    assert_eq!(fs::read(dst.path().join("newer.txt")).unwrap(), b"v2");
+    assert_eq!(fs::read(dst.path().join("edited.txt")).unwrap(), b"edited in dest");
+
+    // Kept in the destination, so still a conflict
+    let diff = sync_with_baseline(ConflictPolicy::KeepBoth);
+    assert_eq!(diff.conflicts.len(), 1);
+    let Resolution::KeepBoth(aside) = &diff.conflicts[0].resolution else {
+        panic!("{:?}", diff.conflicts[0].resolution);
+    };
+    assert_eq!(fs::read(dst.path().join("edited.txt")).unwrap(), b"v2");
+    assert_eq!(fs::read(dst.path().join(aside)).unwrap(), b"edited in dest");
+
+    let diff = sync_with_baseline(ConflictPolicy::Abort);
+    assert!(diff.conflicts.is_empty() && diff.modified.is_empty());
+}
+
+#[test]
+fn test_destination_only_edit_is_no_conflict() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::write(src.path().join("a.txt"), b"v1").unwrap();
+    let source_scan = scan_directory(src.path()).unwrap();
+    let dest_scan = scan_directory(dst.path()).unwrap();
+    let diff = diff_scans(&source_scan, &dest_scan).unwrap();
+    sync_changes(src.path(), dst.path(), &diff, &SyncOptions::default()).unwrap();
+    Baseline::after_sync(&source_scan, &dest_scan, &diff, false, None)
+        .unwrap()
+        .save_in(dst.path())
+        .unwrap();
+
+    // Only the destination changed since, so the source's copy just wins
+    fs::write(dst.path().join("a.txt"), b"edited in dest").unwrap();
+    let baseline = Baseline::load(src.path(), dst.path()).unwrap().unwrap();
+    let dest_scan = scan_directory(dst.path()).unwrap();
+    let mut diff = diff_scans(&scan_directory(src.path()).unwrap(), &dest_scan).unwrap();
+    find_conflicts(&mut diff, &dest_scan, &baseline, ConflictPolicy::Abort);
+    assert!(diff.conflicts.is_empty());
+    assert_eq!(diff.modified.len(), 1);
+}
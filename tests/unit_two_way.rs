@@ -0,0 +1,153 @@
+//! Unit tests for two-way syncs against a stored baseline
+
+use janice::baseline::{plan_two_way, sync_two_way, Baseline, TwoWayPlan};
+use janice::core::{scan_directory, ConflictPolicy, DiffOptions, Resolution, SyncOptions};
+use janice::io::JAN_BASELINE_FILE;
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+fn plan_with(a: &Path, b: &Path, policy: Option<ConflictPolicy>) -> TwoWayPlan {
+    let baseline = Baseline::load(a, b).unwrap();
+    let (a_scan, b_scan) = (scan_directory(a).unwrap(), scan_directory(b).unwrap());
+    plan_two_way(&a_scan, &b_scan, baseline.as_ref(), &DiffOptions::default(), policy).unwrap()
+}
+
+fn plan_only(a: &Path, b: &Path) -> TwoWayPlan {
+    plan_with(a, b, None)
+}
+
+fn two_way(a: &Path, b: &Path) -> TwoWayPlan {
+    let plan = plan_only(a, b);
+    sync_two_way(&plan, &SyncOptions::default()).unwrap();
+    plan
+}
+
+#[test]
+fn test_first_sync_merges_without_deleting() {
+    let a = tempdir().unwrap();
+    let b = tempdir().unwrap();
+    fs::write(a.path().join("from_a.txt"), b"a").unwrap();
+    fs::create_dir(b.path().join("docs")).unwrap();
+    fs::write(b.path().join("docs/from_b.txt"), b"b").unwrap();
+    fs::write(a.path().join("shared.txt"), b"same").unwrap();
+    fs::write(b.path().join("shared.txt"), b"same").unwrap();
+    fs::write(a.path().join("differs.txt"), b"a's version").unwrap();
+    fs::write(b.path().join("differs.txt"), b"b's version").unwrap();
+
+    let plan = two_way(a.path(), b.path());
+
+    assert_eq!(plan.conflicts.len(), 1);
+    assert_eq!(plan.conflicts[0].path, Path::new("differs.txt"));
+    for root in [a.path(), b.path()] {
+        assert_eq!(fs::read(root.join("from_a.txt")).unwrap(), b"a");
+        assert_eq!(fs::read(root.join("docs/from_b.txt")).unwrap(), b"b");
+        assert!(root.join(JAN_BASELINE_FILE).exists());
+    }
+    // Conflicts are left as they are
+    assert_eq!(fs::read(a.path().join("differs.txt")).unwrap(), b"a's version");
+    assert_eq!(fs::read(b.path().join("differs.txt")).unwrap(), b"b's version");
+
+    // Still a conflict next time, and nothing else to do
+    let again = plan_only(a.path(), b.path());
+    assert!(again.is_empty());
+    assert_eq!(again.conflicts.len(), 1);
+}
+
+#[test]
+fn test_changes_on_either_side_carry_over() {
+    let a = tempdir().unwrap();
+    let b = tempdir().unwrap();
+    for name in ["edit_in_a.txt", "delete_in_b.txt", "rename_in_a.txt", "keep.txt"] {
+        fs::write(a.path().join(name), format!("{name} original")).unwrap();
+    }
+    two_way(a.path(), b.path());
+    assert_eq!(fs::read(b.path().join("keep.txt")).unwrap(), b"keep.txt original");
+
+    fs::write(a.path().join("edit_in_a.txt"), b"edited").unwrap();
+    fs::rename(a.path().join("rename_in_a.txt"), a.path().join("renamed.txt")).unwrap();
+    fs::remove_file(b.path().join("delete_in_b.txt")).unwrap();
+    fs::write(b.path().join("new_in_b.txt"), b"new").unwrap();
+
+    let plan = two_way(a.path(), b.path());
+
+    assert!(plan.conflicts.is_empty());
+    assert_eq!(plan.a_to_b.modified.len(), 1);
+    assert_eq!(plan.a_to_b.renamed.len(), 1, "Should move, not copy and delete");
+    assert_eq!(plan.b_to_a.added.len(), 1);
+    assert_eq!(plan.b_to_a.removed.len(), 1);
+    for root in [a.path(), b.path()] {
+        assert_eq!(fs::read(root.join("edit_in_a.txt")).unwrap(), b"edited");
+        assert_eq!(fs::read(root.join("renamed.txt")).unwrap(), b"rename_in_a.txt original");
+        assert!(!root.join("rename_in_a.txt").exists());
+        assert!(!root.join("delete_in_b.txt").exists());
+        assert_eq!(fs::read(root.join("new_in_b.txt")).unwrap(), b"new");
+    }
+    assert!(plan_only(a.path(), b.path()).is_empty());
+}
+
+#[test]
+fn test_edit_against_delete_is_a_conflict() {
+    let a = tempdir().unwrap();
+    let b = tempdir().unwrap();
+    fs::create_dir(a.path().join("dir")).unwrap();
+    fs::write(a.path().join("dir/file.txt"), b"original").unwrap();
+    two_way(a.path(), b.path());
+
+    fs::remove_dir_all(a.path().join("dir")).unwrap();
+    fs::write(b.path().join("dir/file.txt"), b"edited in b").unwrap();
+    fs::write(b.path().join("dir/added_in_b.txt"), b"added").unwrap();
+
+    let plan = two_way(a.path(), b.path());
+
+    assert_eq!(plan.conflicts.len(), 1);
+    assert!(plan.conflicts[0].a.is_none());
+    assert_eq!(fs::read(b.path().join("dir/file.txt")).unwrap(), b"edited in b");
+    // The directory A deleted comes back for the file B added in it
+    assert_eq!(fs::read(a.path().join("dir/added_in_b.txt")).unwrap(), b"added");
+    assert!(!a.path().join("dir/file.txt").exists());
+}
+
+#[test]
+fn test_conflict_policies_settle_both_sides() {
+    let a = tempdir().unwrap();
+    let b = tempdir().unwrap();
+    for name in ["a_wins.txt", "b_wins.txt", "both.txt"] {
+        fs::write(a.path().join(name), b"original").unwrap();
+    }
+    two_way(a.path(), b.path());
+    for root in [a.path(), b.path()] {
+        for name in ["a_wins.txt", "b_wins.txt", "both.txt"] {
+            fs::write(root.join(name), root.to_string_lossy().as_bytes()).unwrap();
+        }
+    }
+    let a_version = a.path().to_string_lossy().into_owned().into_bytes();
+    let b_version = b.path().to_string_lossy().into_owned().into_bytes();
+
+    let plan = plan_with(a.path(), b.path(), Some(ConflictPolicy::KeepBoth));
+    assert_eq!(plan.conflicts.len(), 3);
+    let Resolution::KeepBoth(aside) = &plan.conflicts[0].resolution else {
+        panic!("{:?}", plan.conflicts[0].resolution);
+    };
+    assert!(
+        aside.to_string_lossy().starts_with("a_wins.txt.conflict-"),
+        "{}",
+        aside.display()
+    );
+    sync_two_way(&plan, &SyncOptions::default()).unwrap();
+
+    for root in [a.path(), b.path()] {
+        assert_eq!(fs::read(root.join("a_wins.txt")).unwrap(), a_version);
+        assert_eq!(fs::read(root.join(aside)).unwrap(), b_version);
+    }
+    assert!(plan_only(a.path(), b.path()).conflicts.is_empty());
+
+    // Only the files changed again conflict again
+    fs::write(b.path().join("b_wins.txt"), b"b again").unwrap();
+    fs::write(a.path().join("b_wins.txt"), b"a again").unwrap();
+    let plan = plan_with(a.path(), b.path(), Some(ConflictPolicy::DestWins));
+    assert_eq!(plan.conflicts.len(), 1);
+    assert_eq!(plan.conflicts[0].resolution, Resolution::Dest);
+    sync_two_way(&plan, &SyncOptions::default()).unwrap();
+    assert_eq!(fs::read(a.path().join("b_wins.txt")).unwrap(), b"b again");
+}